@@ -0,0 +1,98 @@
+// Times matching with many capture groups, where threads copy their captures as they
+// record them, against matching that only reports whole matches.
+//
+// Run with `cargo bench --bench captures`. The text is generated, so the numbers
+// only compare one build with another.
+use ergex::*;
+use std::time::Instant;
+
+struct CountingHandler {
+    matches: usize,
+}
+
+impl MatchHandler for CountingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        self.matches += 1;
+        ContinueMatching::Yes
+    }
+}
+
+// Generates lines of words from a small vocabulary.
+fn text(size: usize) -> Vec<u8> {
+    let words: Vec<&str> = "the time times to be or not that is question whether tis nobler in \
+                            mind suffer slings and arrows of outrageous fortune a sea troubles"
+        .split_whitespace()
+        .collect();
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut text = Vec::with_capacity(size + 16);
+    while text.len() < size {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let separator = if state.is_multiple_of(11) {
+            b'\n'
+        } else {
+            b' '
+        };
+        text.extend_from_slice(words[state as usize % words.len()].as_bytes());
+        text.push(separator);
+    }
+    text
+}
+
+fn run(name: &str, database: &Database, text: &[u8]) {
+    for block in [4096, text.len()] {
+        let started = Instant::now();
+        let mut handler = CountingHandler { matches: 0 };
+        let mut scratch = database.make_scratch(&mut handler);
+        for chunk in text.chunks(block) {
+            scratch.push(chunk);
+        }
+        scratch.finish();
+        let elapsed = started.elapsed().as_secs_f64();
+        println!(
+            "{:<12} block {:>8}: {:>6.2}s, {:>6.1} MB/s, {} matches",
+            name,
+            block,
+            elapsed,
+            text.len() as f64 / elapsed / 1e6,
+            handler.matches
+        );
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let text = text(4 << 20);
+
+    // Two words of up to nine letters each, with a group for every letter.
+    let groups = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(
+                1,
+                r"(?-u)\b(\w)(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?(\w)? (\w)(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?(\w)?\b",
+            )
+            .encoding(Encoding::Byte)
+            .mode(MatchMode::All(Submatch::All))
+            .build()?,
+        )
+        .build();
+    run("groups", &groups, &text);
+
+    // Expressions like those in the teakettle test, which only report where the whole
+    // match is.
+    let expression = |id, expression| {
+        Regex::new(id, expression)
+            .mode(MatchMode::All(Submatch::Expression))
+            .encoding(Encoding::Byte)
+            .case_sensitive(false)
+            .build()
+    };
+    let whole = DatabaseBuilder::new()
+        .with_expression(expression(1, r"question[^\n]*nobler[^\n]*fortune")?)
+        .with_expression(expression(2, r"^whether[^\r\n]*slings[^\r\n]*troubles")?)
+        .with_expression(expression(3, r"outrageous.*arrows")?)
+        .with_expression(expression(4, r"times")?)
+        .build();
+    run("expression", &whole, &text);
+    Ok(())
+}
@@ -3,6 +3,7 @@
 //         cookie thing is just terrible
 
 use std::collections::VecDeque;
+use std::mem::size_of;
 
 #[derive(Debug)]
 enum TransitionSet {
@@ -40,6 +41,13 @@ impl TransitionSet {
         }
     }
 
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::Dense(ref transitions) => transitions.capacity() * size_of::<Option<usize>>(),
+            Self::Sparse(ref transitions) => transitions.capacity() * size_of::<(u8, usize)>(),
+        }
+    }
+
     fn next_states(&self) -> Vec<usize> {
         match self {
             Self::Dense(ref transitions) => transitions
@@ -60,6 +68,10 @@ struct Node {
 }
 
 impl Node {
+    fn heap_size(&self) -> usize {
+        self.next_states.heap_size() + self.output.capacity() * size_of::<usize>()
+    }
+
     fn empty() -> Self {
         Self {
             value: None,
@@ -102,10 +114,14 @@ impl AhoCorasickMatch {
 pub struct AhoCorasick {
     trie: Vec<Node>,
     keywords: Vec<Vec<u8>>,
+
+    // Whether keywords match without regard to ASCII case. The trie holds them
+    // lowercased, and the input is lowercased as it's scanned.
+    folded: bool,
 }
 
 impl AhoCorasick {
-    pub fn new<I, P>(keywords: I, dense_prefix: usize) -> Self
+    pub fn new<I, P>(keywords: I, dense_prefix: usize, folded: bool) -> Self
     where
         I: IntoIterator<Item = P>,
         P: AsRef<[u8]>,
@@ -113,6 +129,7 @@ impl AhoCorasick {
         let mut ac = Self {
             trie: vec![Node::empty()],
             keywords: vec![],
+            folded,
         };
 
         for keyword in keywords.into_iter() {
@@ -140,7 +157,11 @@ impl AhoCorasick {
     where
         P: AsRef<[u8]>,
     {
-        let keyword = keyword.as_ref();
+        let keyword = if self.folded {
+            keyword.as_ref().to_ascii_lowercase()
+        } else {
+            keyword.as_ref().to_vec()
+        };
         let mut current_state = 0;
         let mut j = 0;
         let mut child = self.find_next_state(current_state, keyword[j]);
@@ -171,7 +192,7 @@ impl AhoCorasick {
 
         self.trie[current_state].output.push(self.keywords.len());
 
-        let mut keyword = keyword.to_vec();
+        let mut keyword = keyword;
         keyword.shrink_to_fit();
         self.keywords.push(keyword);
     }
@@ -208,7 +229,15 @@ impl AhoCorasick {
         }
     }
 
-    pub fn get_scratch(&self) -> AhoCorasickScratch {
+    // Returns the approximate number of bytes allocated by the automaton.
+    pub fn heap_size(&self) -> usize {
+        self.trie.capacity() * size_of::<Node>()
+            + self.trie.iter().map(|x| x.heap_size()).sum::<usize>()
+            + self.keywords.capacity() * size_of::<Vec<u8>>()
+            + self.keywords.iter().map(|x| x.capacity()).sum::<usize>()
+    }
+
+    pub fn get_scratch(&self) -> AhoCorasickScratch<'_> {
         AhoCorasickScratch {
             ac: self,
             current_state: 0,
@@ -229,7 +258,15 @@ impl<'a> AhoCorasickScratch<'a> {
         self.seen = 0;
     }
 
-    pub fn push(&mut self, text: &'a [u8]) -> AhoCorasickIterator {
+    // Starts scanning at `offset` in the input, as though `before` was the input just
+    // before it.
+    pub fn seek(&mut self, offset: usize, before: &[u8]) {
+        self.current_state = 0;
+        self.seen = offset - before.len();
+        for _ in self.push(before) {}
+    }
+
+    pub fn push<'b>(&'b mut self, text: &'b [u8]) -> AhoCorasickIterator<'b> {
         AhoCorasickIterator::new(self.ac, &mut self.current_state, &mut self.seen, text)
     }
 }
@@ -272,7 +309,7 @@ impl<'a> Iterator for AhoCorasickIterator<'a> {
                 let len = keyword.len();
                 self.current_output = Some(current_output + 1);
                 return Some(AhoCorasickMatch::new(
-                    self.offset - (len - 1),
+                    *self.seen + self.offset - len,
                     output[current_output],
                 ));
             }
@@ -281,6 +318,11 @@ impl<'a> Iterator for AhoCorasickIterator<'a> {
 
         for &byte in self.text[self.offset..].iter() {
             self.offset += 1;
+            let byte = if self.ac.folded {
+                byte.to_ascii_lowercase()
+            } else {
+                byte
+            };
 
             while *self.current_state != 0
                 && self.ac.find_next_state(*self.current_state, byte).is_none()
@@ -0,0 +1,105 @@
+// Keeping the input that captures might still cover, so that matches can be reported
+// along with the bytes they captured.
+//
+// A scratch space with a capture buffer adds each block to the buffer before it's
+// matched, and afterwards drops whatever no running thread could still capture, and
+// anything older than the buffer's limit. Captures are cut from the buffer before
+// their offsets are mapped, so they hold the bytes the expressions matched: decoded,
+// if the scratch space decodes its input.
+use crate::{Capture, ContinueMatching, MatchHandler, MatchInfo};
+
+pub struct CaptureBuffer {
+    // The offset of the first byte kept.
+    base: usize,
+    bytes: Vec<u8>,
+    limit: usize,
+}
+
+impl CaptureBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            base: 0,
+            bytes: Vec::new(),
+            limit,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.base = 0;
+        self.bytes.clear();
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // Forgets the bytes before `offset`, and all but the last `limit` bytes.
+    pub fn prune(&mut self, offset: usize) {
+        let end = self.base + self.bytes.len();
+        let offset = offset.max(end.saturating_sub(self.limit));
+        if offset > self.base {
+            self.bytes
+                .drain(..(offset - self.base).min(self.bytes.len()));
+            self.base = offset;
+        }
+    }
+
+    // The bytes a capture covers, if they're kept and no more than the limit.
+    fn get(&self, capture: Capture) -> Option<&[u8]> {
+        let start = capture.start?.checked_sub(self.base)?;
+        let end = capture.end? - self.base;
+        if end - start > self.limit {
+            return None;
+        }
+        self.bytes.get(start..end)
+    }
+}
+
+// Passes matches on to a handler with the bytes they captured, if there's a buffer.
+pub struct BufferedHandler<'a, 'b> {
+    handler: &'a mut (dyn MatchHandler + 'b),
+    buffer: Option<&'a CaptureBuffer>,
+    data: Vec<Option<&'a [u8]>>,
+}
+
+impl<'a, 'b> BufferedHandler<'a, 'b> {
+    pub fn new(
+        handler: &'a mut (dyn MatchHandler + 'b),
+        buffer: Option<&'a CaptureBuffer>,
+    ) -> Self {
+        Self {
+            handler,
+            buffer,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl MatchHandler for BufferedHandler<'_, '_> {
+    fn get_pulse_interval(&self) -> usize {
+        self.handler.get_pulse_interval()
+    }
+
+    fn on_pulse(&mut self) -> ContinueMatching {
+        self.handler.on_pulse()
+    }
+
+    fn on_reset(&mut self) {
+        self.handler.on_reset()
+    }
+
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.handler.on_match(id, captures)
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        let buffer = match self.buffer {
+            Some(buffer) => buffer,
+            None => return self.handler.on_match_info(info),
+        };
+        self.data.clear();
+        self.data
+            .extend(info.captures.iter().map(|&capture| buffer.get(capture)));
+        self.handler.on_match_with_data(info, &self.data)
+    }
+}
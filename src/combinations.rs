@@ -0,0 +1,608 @@
+// Combinations of expressions: rules like "1 & 2 & !3" over expression ids, which
+// match when the expressions they refer to have (or haven't) matched in a stream.
+//
+// Rules are judged with three-valued logic. Until the end of the stream, an
+// expression that hasn't matched yet might still match, so it's neither true nor
+// false, and a rule is reported as soon as it's true whatever those expressions go
+// on to do. At the end of the stream they're false, which settles the rules that
+// depend on an expression never matching.
+//
+// Sequences are rules that expressions match in order, each close to the one before.
+// Matches aren't found in the order of their offsets within a block, so a scratch
+// space keeps the block's matches for the expressions in sequences and runs them
+// through the sequences in order at the end of the block. Since matches are found
+// in the block they end in, that's the order of the whole stream. Each step of a
+// sequence keeps just enough to know whether the next step can follow it.
+use crate::{Capture, ContinueMatching, Error, MatchHandler, MatchInfo};
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+/// A rule that matches when a boolean expression over the ids of other expressions
+/// becomes true. Expressions are ids joined with `&` (and), `|` (or) and `!` (not),
+/// grouped with parentheses; `&` binds more tightly than `|`. For example,
+/// `"1 & (2 | 3) & !4"` matches once expression 1 and either 2 or 3 have matched, as
+/// long as 4 never does.
+///
+/// A combination is reported to `MatchHandler::on_match` under its own id, with an
+/// empty capture at the offset where it became true: the end of the match that made
+/// it true, or the end of the stream. Each combination is reported at most once per
+/// stream. The expressions it refers to are still reported as usual.
+pub struct CombinationRule {
+    id: usize,
+    expression: String,
+}
+
+impl CombinationRule {
+    pub fn new(id: usize, expression: &str) -> Self {
+        Self {
+            id,
+            expression: expression.to_string(),
+        }
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+/// A rule that matches when the expressions with the given ids match one after
+/// another: each match must end after the one before it ends. By default the
+/// matches can be any distance apart.
+///
+/// A sequence is reported to `MatchHandler::on_match` under its own id, with a
+/// capture from the start of its first match to the end of its last, whenever the
+/// last expression completes it. Where the earlier steps could have matched in
+/// several places, the sequence is made of the latest match of each step that
+/// could come before the next.
+pub struct SequenceRule {
+    id: usize,
+    ids: Vec<usize>,
+    within: usize,
+}
+
+impl SequenceRule {
+    pub fn new(id: usize, ids: &[usize]) -> Self {
+        Self {
+            id,
+            ids: ids.to_vec(),
+            within: usize::MAX,
+        }
+    }
+
+    /// Requires each match to start no more than `distance` bytes after the end of
+    /// the match before it. A match may also start before the one before it ends.
+    pub fn within(mut self, distance: usize) -> Self {
+        self.within = distance;
+        self
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+enum Combination {
+    Id(usize),             // An expression has matched.
+    Not(Box<Combination>), // The combination is false.
+    And(Vec<Combination>), // Every combination is true.
+    Or(Vec<Combination>),  // Any combination is true.
+}
+
+impl Combination {
+    // Returns whether the combination is true given the expressions that have
+    // matched, or None if that depends on expressions that might yet match.
+    fn evaluate(&self, matched: &HashSet<usize>, finished: bool) -> Option<bool> {
+        match self {
+            Self::Id(id) if matched.contains(id) => Some(true),
+            Self::Id(_) if finished => Some(false),
+            Self::Id(_) => None,
+            Self::Not(combination) => combination.evaluate(matched, finished).map(|x| !x),
+            Self::And(combinations) => {
+                let mut result = Some(true);
+                for combination in combinations.iter() {
+                    match combination.evaluate(matched, finished) {
+                        Some(false) => return Some(false),
+                        Some(true) => {}
+                        None => result = None,
+                    }
+                }
+                result
+            }
+            Self::Or(combinations) => {
+                let mut result = Some(false);
+                for combination in combinations.iter() {
+                    match combination.evaluate(matched, finished) {
+                        Some(true) => return Some(true),
+                        Some(false) => {}
+                        None => result = None,
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    fn ids(&self, ids: &mut Vec<usize>) {
+        match self {
+            Self::Id(id) => ids.push(*id),
+            Self::Not(combination) => combination.ids(ids),
+            Self::And(combinations) | Self::Or(combinations) => {
+                for combination in combinations.iter() {
+                    combination.ids(ids);
+                }
+            }
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::Id(_) => 0,
+            Self::Not(combination) => size_of::<Combination>() + combination.heap_size(),
+            Self::And(combinations) | Self::Or(combinations) => {
+                combinations.capacity() * size_of::<Combination>()
+                    + combinations.iter().map(|x| x.heap_size()).sum::<usize>()
+            }
+        }
+    }
+}
+
+// A recursive descent parser for combination expressions.
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(text: &'a str) -> Result<Combination, Error> {
+        let mut parser = Self {
+            text: text.as_bytes(),
+            at: 0,
+        };
+        let combination = parser.or()?;
+        match parser.peek() {
+            None => Ok(combination),
+            Some(_) => Err(parser.error("unexpected character")),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidExpression(format!("{} at offset {} of combination", message, self.at))
+    }
+
+    // The next character that isn't whitespace.
+    fn peek(&mut self) -> Option<u8> {
+        while self.at < self.text.len() && self.text[self.at].is_ascii_whitespace() {
+            self.at += 1;
+        }
+        self.text.get(self.at).copied()
+    }
+
+    fn or(&mut self) -> Result<Combination, Error> {
+        let mut combinations = vec![self.and()?];
+        while self.peek() == Some(b'|') {
+            self.at += 1;
+            combinations.push(self.and()?);
+        }
+        Ok(match combinations.len() {
+            1 => combinations.pop().unwrap(),
+            _ => Combination::Or(combinations),
+        })
+    }
+
+    fn and(&mut self) -> Result<Combination, Error> {
+        let mut combinations = vec![self.not()?];
+        while self.peek() == Some(b'&') {
+            self.at += 1;
+            combinations.push(self.not()?);
+        }
+        Ok(match combinations.len() {
+            1 => combinations.pop().unwrap(),
+            _ => Combination::And(combinations),
+        })
+    }
+
+    fn not(&mut self) -> Result<Combination, Error> {
+        match self.peek() {
+            Some(b'!') => {
+                self.at += 1;
+                Ok(Combination::Not(Box::new(self.not()?)))
+            }
+            Some(b'(') => {
+                self.at += 1;
+                let combination = self.or()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("expected ')'"));
+                }
+                self.at += 1;
+                Ok(combination)
+            }
+            Some(b'0'..=b'9') => {
+                let start = self.at;
+                while self.at < self.text.len() && self.text[self.at].is_ascii_digit() {
+                    self.at += 1;
+                }
+                std::str::from_utf8(&self.text[start..self.at])
+                    .unwrap()
+                    .parse()
+                    .map(Combination::Id)
+                    .map_err(|_| self.error("id out of range"))
+            }
+            Some(_) => Err(self.error("expected an id")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+}
+
+// A sequence as it's run: the rule and where its steps' progress is kept.
+struct Sequence {
+    id: usize,
+    ids: Vec<usize>,
+    within: usize,
+    first: usize,
+}
+
+// A step of a sequence completed by a match ending at `end`, in a sequence whose
+// first match started at `start`.
+#[derive(Clone, Copy)]
+struct Step {
+    start: usize,
+    end: usize,
+}
+
+// How far a sequence has got through one of its steps: the latest completion of the
+// step, and the latest that ended before it. Since matches come in the order they
+// end, that's all a later match needs to find one that ended before it.
+#[derive(Clone, Copy, Default)]
+struct Progress {
+    latest: Option<Step>,
+    earlier: Option<Step>,
+}
+
+impl Progress {
+    // The latest completion of the step that ended before `end`.
+    fn before(&self, end: usize) -> Option<Step> {
+        match self.latest {
+            Some(step) if step.end < end => Some(step),
+            _ => self.earlier.filter(|step| step.end < end),
+        }
+    }
+
+    fn record(&mut self, step: Step) {
+        match self.latest {
+            // Keep the longer of two sequences ending in the same place.
+            Some(latest) if latest.end == step.end => {
+                if step.start < latest.start {
+                    self.latest = Some(step);
+                }
+            }
+            latest => {
+                self.earlier = latest;
+                self.latest = Some(step);
+            }
+        }
+    }
+}
+
+// The combination and sequence rules of a database.
+pub struct Combinations {
+    rules: Vec<(usize, Combination)>,
+    sequences: Vec<Sequence>,
+
+    // The rules that refer to each expression id, and the steps of sequences that do:
+    // the index of the sequence and of the step.
+    referenced: HashMap<usize, Vec<usize>>,
+    sequenced: HashMap<usize, Vec<(usize, usize)>>,
+
+    // The number of steps of every sequence.
+    steps: usize,
+}
+
+// Checks that a rule doesn't reuse the id of an expression, and refers only to
+// expressions that exist.
+fn check_rule(
+    kind: &str,
+    id: usize,
+    ids: &[usize],
+    expressions: &HashMap<usize, Vec<usize>>,
+) -> Result<(), Error> {
+    if expressions.contains_key(&id) {
+        return Err(Error::InvalidExpression(format!(
+            "{} {} has the id of an expression",
+            kind, id
+        )));
+    }
+    match ids.iter().find(|x| !expressions.contains_key(x)) {
+        Some(unknown) => Err(Error::InvalidExpression(format!(
+            "{} {} refers to unknown expression {}",
+            kind, id, unknown
+        ))),
+        None => Ok(()),
+    }
+}
+
+impl Combinations {
+    // Parses the rules, checking that they refer only to the given expressions and
+    // don't reuse their ids.
+    pub fn new(
+        rules: Vec<CombinationRule>,
+        sequences: Vec<SequenceRule>,
+        expressions: &HashMap<usize, Vec<usize>>,
+    ) -> Result<Self, Error> {
+        let mut combinations = Self {
+            rules: Vec::with_capacity(rules.len()),
+            sequences: Vec::with_capacity(sequences.len()),
+            referenced: HashMap::new(),
+            sequenced: HashMap::new(),
+            steps: 0,
+        };
+
+        for rule in rules {
+            let combination = Parser::parse(&rule.expression)?;
+            let mut ids = vec![];
+            combination.ids(&mut ids);
+            ids.sort_unstable();
+            ids.dedup();
+            check_rule("combination", rule.id, &ids, expressions)?;
+            for id in ids {
+                combinations
+                    .referenced
+                    .entry(id)
+                    .or_default()
+                    .push(combinations.rules.len());
+            }
+            combinations.rules.push((rule.id, combination));
+        }
+
+        for rule in sequences {
+            if rule.ids.is_empty() {
+                return Err(Error::InvalidExpression(format!(
+                    "sequence {} is empty",
+                    rule.id
+                )));
+            }
+            check_rule("sequence", rule.id, &rule.ids, expressions)?;
+            for (step, &id) in rule.ids.iter().enumerate() {
+                combinations
+                    .sequenced
+                    .entry(id)
+                    .or_default()
+                    .push((combinations.sequences.len(), step));
+            }
+            combinations.sequences.push(Sequence {
+                id: rule.id,
+                first: combinations.steps,
+                ids: rule.ids,
+                within: rule.within,
+            });
+            combinations.steps += combinations.sequences.last().unwrap().ids.len();
+        }
+        Ok(combinations)
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.rules.capacity() * size_of::<(usize, Combination)>()
+            + self.rules.iter().map(|x| x.1.heap_size()).sum::<usize>()
+            + self.sequences.capacity() * size_of::<Sequence>()
+            + self
+                .sequences
+                .iter()
+                .map(|x| x.ids.capacity() * size_of::<usize>())
+                .sum::<usize>()
+            + self.referenced.capacity() * size_of::<(usize, Vec<usize>)>()
+            + self
+                .referenced
+                .values()
+                .map(|x| x.capacity() * size_of::<usize>())
+                .sum::<usize>()
+            + self.sequenced.capacity() * size_of::<(usize, Vec<(usize, usize)>)>()
+            + self
+                .sequenced
+                .values()
+                .map(|x| x.capacity() * size_of::<(usize, usize)>())
+                .sum::<usize>()
+    }
+
+    // Runs the matches kept from the last block through the sequences, in order.
+    pub fn flush(
+        &self,
+        state: &mut CombinationState,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let mut result = ContinueMatching::Yes;
+        state.pending.sort_unstable();
+        for index in 0..state.pending.len() {
+            let (end, start, id) = state.pending[index];
+            for &(sequence, step) in self.sequenced[&id].iter() {
+                if state.advance(self, sequence, step, Step { start, end }, handler)
+                    == ContinueMatching::No
+                {
+                    result = ContinueMatching::No;
+                }
+            }
+        }
+        state.pending.clear();
+        result
+    }
+
+    // Reports the rules that are true at the end of a stream and weren't already.
+    pub fn finish(
+        &self,
+        state: &mut CombinationState,
+        offset: usize,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let mut result = self.flush(state, handler);
+        for index in 0..self.rules.len() {
+            if state.report(self, index, offset, true, handler) == ContinueMatching::No {
+                result = ContinueMatching::No;
+            }
+        }
+        result
+    }
+}
+
+// What a scratch space knows about the combinations in the stream so far.
+pub struct CombinationState {
+    matched: HashSet<usize>,
+    reported: Vec<bool>,
+
+    // Matches for sequences from the current block, as (end, start, id), how far
+    // each step of each sequence has got, and where each sequence last ended.
+    pending: Vec<(usize, usize, usize)>,
+    progress: Vec<Progress>,
+    ended: Vec<Option<usize>>,
+}
+
+impl CombinationState {
+    pub fn new(combinations: &Combinations) -> Self {
+        Self {
+            matched: HashSet::new(),
+            reported: vec![false; combinations.rules.len()],
+            pending: Vec::new(),
+            progress: vec![Progress::default(); combinations.steps],
+            ended: vec![None; combinations.sequences.len()],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.matched.clear();
+        self.reported.fill(false);
+        self.pending.clear();
+        self.progress.fill(Progress::default());
+        self.ended.fill(None);
+    }
+
+    // Reports a rule if it's true and hasn't been reported before.
+    fn report(
+        &mut self,
+        combinations: &Combinations,
+        index: usize,
+        offset: usize,
+        finished: bool,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let (id, ref combination) = combinations.rules[index];
+        if self.reported[index] || combination.evaluate(&self.matched, finished) != Some(true) {
+            return ContinueMatching::Yes;
+        }
+        self.reported[index] = true;
+        handler.on_match(id, &[Capture::new(Some(offset), Some(offset))])
+    }
+
+    // Completes a step of a sequence with a match, if it can follow the step before,
+    // reporting the sequence if that was its last step.
+    fn advance(
+        &mut self,
+        combinations: &Combinations,
+        index: usize,
+        step: usize,
+        mut matched: Step,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let sequence = &combinations.sequences[index];
+        if step > 0 {
+            match self.progress[sequence.first + step - 1].before(matched.end) {
+                Some(before) if matched.start.saturating_sub(before.end) <= sequence.within => {
+                    matched.start = before.start;
+                }
+                _ => return ContinueMatching::Yes,
+            }
+        }
+
+        if step + 1 < sequence.ids.len() {
+            self.progress[sequence.first + step].record(matched);
+            return ContinueMatching::Yes;
+        }
+
+        // Expressions with several variants can match more than once in one place.
+        if self.ended[index] == Some(matched.end) {
+            return ContinueMatching::Yes;
+        }
+        self.ended[index] = Some(matched.end);
+        handler.on_match(
+            sequence.id,
+            &[Capture::new(Some(matched.start), Some(matched.end))],
+        )
+    }
+}
+
+// Passes matches on to a handler, along with the combinations they make true.
+pub struct CombiningHandler<'a, 'b> {
+    handler: &'a mut (dyn MatchHandler + 'b),
+    combinations: &'a Combinations,
+    state: &'a mut CombinationState,
+}
+
+impl<'a, 'b> CombiningHandler<'a, 'b> {
+    pub fn new(
+        handler: &'a mut (dyn MatchHandler + 'b),
+        combinations: &'a Combinations,
+        state: &'a mut CombinationState,
+    ) -> Self {
+        Self {
+            handler,
+            combinations,
+            state,
+        }
+    }
+
+    fn combine(
+        &mut self,
+        id: usize,
+        captures: &[Capture],
+        result: ContinueMatching,
+    ) -> ContinueMatching {
+        let offset = captures[0].end.unwrap_or(0);
+        if self.combinations.sequenced.contains_key(&id) {
+            let start = captures[0].start.unwrap_or(offset);
+            self.state.pending.push((offset, start, id));
+        }
+
+        let rules = match self.combinations.referenced.get(&id) {
+            Some(rules) if self.state.matched.insert(id) => rules,
+            _ => return result,
+        };
+
+        let mut result = result;
+        for &index in rules.iter() {
+            if self
+                .state
+                .report(self.combinations, index, offset, false, self.handler)
+                == ContinueMatching::No
+            {
+                result = ContinueMatching::No;
+            }
+        }
+        result
+    }
+}
+
+impl MatchHandler for CombiningHandler<'_, '_> {
+    fn get_pulse_interval(&self) -> usize {
+        self.handler.get_pulse_interval()
+    }
+
+    fn on_pulse(&mut self) -> ContinueMatching {
+        self.handler.on_pulse()
+    }
+
+    fn on_reset(&mut self) {
+        self.handler.on_reset()
+    }
+
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        let result = self.handler.on_match(id, captures);
+        self.combine(id, captures, result)
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        let result = self.handler.on_match_info(info);
+        self.combine(info.id, info.captures, result)
+    }
+
+    fn on_match_with_data(&mut self, info: &MatchInfo, data: &[Option<&[u8]>]) -> ContinueMatching {
+        let result = self.handler.on_match_with_data(info, data);
+        self.combine(info.id, info.captures, result)
+    }
+}
@@ -1,12 +1,30 @@
-use crate::queue::{End, Queue};
+use crate::optimizer::{merge_literals, optimize};
+use crate::queue::{self, CountedLoop, End, Queue, Slots};
+#[cfg(feature = "trace")]
+use crate::trace::Tracer;
+use crate::trace::{trace, TracerSlot};
 use crate::transformers::{
-    ByteAlternationSimplifier, ByteTransformer, ClassSimplifier, GroupEliminator, Transformer,
+    base64_literal, folded_letter, literal_bytes, ByteAlternationSimplifier, ByteTransformer,
+    CaseFolder, ClassSimplifier, GroupEliminator, HexTransformer, Latin1Transformer,
+    LineTerminatorTransformer, Reverser, Transformer, UTF16Transformer,
     UnicodeAlternationSimplifier,
 };
-use crate::{ContinueMatching, Encoding, Error, MatchHandler, MatchMode, Submatch};
+use crate::{
+    Capture, ContinueMatching, Encoding, Error, LineTerminator, MatchHandler, MatchInfo, MatchMode,
+    Submatch, Variant,
+};
 
 use crate::aho_corasick::{AhoCorasick, AhoCorasickScratch};
+use crate::buffer::{BufferedHandler, CaptureBuffer};
+use crate::combinations::{
+    CombinationRule, CombinationState, Combinations, CombiningHandler, SequenceRule,
+};
+use crate::decoders::{Decoder, DecoderChain};
+use crate::glushkov::{Glushkov, Positions};
 use crate::intset::{GrowSet, ShrinkSet};
+use crate::lookback::{History, Longest};
+use crate::merged::{Merged, MergedScratch};
+use crate::offsets::{MappedHandler, OffsetMap};
 use regex_syntax::hir::literal::Literals;
 use regex_syntax::hir::{
     Anchor, Class, ClassBytes, Group, GroupKind, Hir, HirKind, Literal, Repetition, RepetitionKind,
@@ -15,7 +33,9 @@ use regex_syntax::hir::{
 use regex_syntax::ParserBuilder;
 use std::cmp::{max, min};
 use std::collections::HashMap;
-use std::mem::swap;
+use std::fmt::{self, Write};
+use std::mem::{size_of, swap, take};
+use std::time::{Duration, Instant};
 
 const CR: u8 = 0x0d;
 const NL: u8 = 0x0a;
@@ -37,6 +57,13 @@ pub struct Regex {
     // Maximum repeat count in a repetition.
     max_repeat: usize,
 
+    // Greedy repetitions of single bytes with at least this many
+    // iterations are compiled into counted loops instead of being unrolled.
+    counted_repetition_threshold: usize,
+
+    // Should the compiled program be optimized.
+    optimize: bool,
+
     // Parser options.
     max_depth: u32,
     case_sensitive: bool,
@@ -44,6 +71,24 @@ pub struct Regex {
     allow_invalid_utf8: bool,
     dot_matches_new_line: bool,
     multi_line: bool,
+    swap_greed: bool,
+    octal: bool,
+
+    // Whether Unicode mode is on; if unset, it follows the encoding.
+    unicode: Option<bool>,
+
+    // What ends a line.
+    line_terminator: LineTerminator,
+
+    // How many single-byte edits an approximate match may make.
+    max_edits: usize,
+
+    // How far back from the end of a match to look for its start, instead of
+    // tracking the start of every thread; zero to track them.
+    lookback: usize,
+
+    // Whether small expressions may run on the bit-parallel engine.
+    bit_parallel: bool,
 
     // A list of transformers to apply to this regular expression.
     preprocessors: Vec<Box<dyn Transformer>>,
@@ -51,6 +96,9 @@ pub struct Regex {
 
     // Should this expression default to Unicode-aware.
     encoding: Encoding,
+
+    // The forms of the expression to compile with build_all.
+    variants: Vec<Variant>,
 }
 
 impl Regex {
@@ -61,21 +109,25 @@ impl Regex {
             mode: MatchMode::All(Submatch::All),
             max_instructions: 1048576,
             max_repeat: 65535,
+            counted_repetition_threshold: 16,
+            optimize: true,
             max_depth: 250,
             case_sensitive: true,
             allow_whitespace: false,
             allow_invalid_utf8: false,
             dot_matches_new_line: false,
             multi_line: false,
+            swap_greed: false,
+            octal: false,
+            unicode: None,
+            line_terminator: LineTerminator::CROrLF,
+            max_edits: 0,
+            lookback: 0,
+            bit_parallel: true,
             preprocessors: Vec::new(),
-            postprocessors: vec![
-                Box::new(UnicodeAlternationSimplifier::new()),
-                Box::new(ClassSimplifier::new()),
-                Box::new(ByteTransformer::new()),
-                Box::new(ByteAlternationSimplifier::new()),
-                Box::new(ClassSimplifier::new()),
-            ],
+            postprocessors: Vec::new(),
             encoding: Encoding::UTF8,
+            variants: Vec::new(),
         }
     }
 
@@ -129,68 +181,311 @@ impl Regex {
         self
     }
 
+    pub fn counted_repetition_threshold(mut self, threshold: usize) -> Self {
+        self.counted_repetition_threshold = threshold;
+        self
+    }
+
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Lets `^` and `$` match at the start and end of lines as well as of the
+    /// input, as with the `m` flag. What ends a line is set by `line_terminator`; by
+    /// default either `\r` or `\n` does.
     pub fn multi_line(mut self, multi_line: bool) -> Self {
         self.multi_line = multi_line;
         self
     }
 
+    /// Sets what ends a line. This decides where `^` and `$` match in multi-line
+    /// mode, and which bytes `.` excludes unless it matches new lines.
+    pub fn line_terminator(mut self, line_terminator: LineTerminator) -> Self {
+        self.line_terminator = line_terminator;
+        self
+    }
+
+    /// Makes repetitions lazy by default and greedy when followed by `?`, as with
+    /// the `U` flag.
+    pub fn swap_greed(mut self, swap_greed: bool) -> Self {
+        self.swap_greed = swap_greed;
+        self
+    }
+
+    /// Allows octal escapes like `\141`. These are off by default because they are
+    /// easily mistaken for backreferences, which aren't supported.
+    pub fn octal(mut self, octal: bool) -> Self {
+        self.octal = octal;
+        self
+    }
+
+    /// Turns Unicode mode on or off, as with the `u` flag. By default it's off for
+    /// `Encoding::Byte` and on for the text encodings.
+    ///
+    /// With Unicode mode on, classes like `\w` and `.` match characters, encoded as
+    /// UTF-8 for `Encoding::Byte`, which finds UTF-8 text in otherwise binary input.
+    /// With Unicode mode off, they match single bytes, and expressions are allowed to
+    /// match invalid UTF-8 even under `Encoding::UTF8`.
+    pub fn unicode(mut self, unicode: bool) -> Self {
+        self.unicode = Some(unicode);
+        self
+    }
+
+    /// Allows matches that differ from the expression by up to `edits` insertions,
+    /// deletions and substitutions of single bytes. Each match reports how many edits
+    /// it needed in `MatchInfo::edits`. Of the matches that overlap, only the one with
+    /// the fewest edits is reported, and of those the longest, so a match can't be
+    /// reported until no match overlapping it can still be found. Approximate
+    /// expressions can't be found by their prefixes, so they run over all of the input.
+    pub fn max_edits(mut self, edits: usize) -> Self {
+        self.max_edits = edits;
+        self
+    }
+
+    /// Finds where matches start by running the expression backwards from where they
+    /// end, over at most `bytes` bytes of input, instead of carrying the start of
+    /// every thread along as it runs. Only expressions reporting
+    /// `Submatch::Expression` can do this. A match that could start further back
+    /// reports the earliest start within reach, or none if there isn't one.
+    pub fn lookback(mut self, bytes: usize) -> Self {
+        self.lookback = bytes;
+        self
+    }
+
+    /// Lets the expression run on a bit-parallel engine instead of the VM when it's
+    /// small enough, which is on by default. Expressions without capture groups or
+    /// assertions other than at either end, and with at most 128 bytes and classes
+    /// once repetitions are expanded, are run by tracking where in the expression a
+    /// match could be as a set of bits. Such an expression must also report
+    /// `Submatch::None`, use a lookback, or match only strings of one length, since
+    /// the engine doesn't know where its matches started. The engine isn't traced.
+    pub fn bit_parallel(mut self, bit_parallel: bool) -> Self {
+        self.bit_parallel = bit_parallel;
+        self
+    }
+
     pub fn encoding(mut self, encoding: Encoding) -> Self {
         self.encoding = encoding;
         self
     }
 
-    pub fn build(mut self) -> Result<CompiledRegex, Error> {
-        // Add a GroupEliminator to eliminate all captures that we don't need.
+    /// Sets the forms of the expression that `build_all` compiles, e.g. the same
+    /// string as ASCII, UTF-16 and base64. Matches report which variant fired.
+    pub fn variants(mut self, variants: &[Variant]) -> Self {
+        self.variants = variants.to_vec();
+        self
+    }
+
+    /// Compiles the expression in its own encoding, ignoring any variants.
+    pub fn build(self) -> Result<CompiledRegex, Error> {
+        self.compile(Variant::Encoded(self.encoding))
+    }
+
+    /// Compiles one program for each variant given to `variants`, all with this
+    /// expression's id, or just the one `build` would if there are none.
+    pub fn build_all(self) -> Result<Vec<CompiledRegex>, Error> {
+        if self.variants.is_empty() {
+            return Ok(vec![self.build()?]);
+        }
+        self.variants
+            .iter()
+            .map(|variant| self.compile(*variant))
+            .collect()
+    }
+
+    fn compile(&self, variant: Variant) -> Result<CompiledRegex, Error> {
+        let encoding = match variant {
+            Variant::Encoded(encoding) => encoding,
+            Variant::Hex | Variant::Base64 => self.encoding,
+        };
+
+        // Use a GroupEliminator to eliminate all captures that we don't need.
         // This makes things simpler on the compilation and match sides: the code can be
         // generic. This also makes it possible to more aggressively optimize.
-        let max_capture = self.get_max_capture();
-        self.preprocessors
-            .push(Box::new(GroupEliminator::with_max_capture(max_capture)));
+        let eliminator = GroupEliminator::with_max_capture(self.get_max_capture());
 
         // Perform our transformations.
-        let mut hir = self.parse()?;
-        for transformer in self.preprocessors {
+        let mut hir = self.parse(encoding)?;
+        if self.line_terminator != LineTerminator::CROrLF {
+            hir = LineTerminatorTransformer::new(self.line_terminator).transform(hir);
+        }
+        for transformer in self.preprocessors.iter() {
             hir = transformer.transform(hir);
         }
-        for transformer in self.postprocessors {
+        hir = eliminator.transform(hir);
+        for transformer in encoding_transformers(encoding) {
             hir = transformer.transform(hir);
         }
+        for transformer in self.postprocessors.iter() {
+            hir = transformer.transform(hir);
+        }
+        hir = match variant {
+            Variant::Encoded(_) => hir,
+            Variant::Hex => HexTransformer::new().transform(hir),
+            Variant::Base64 => literal_bytes(&hir)
+                .and_then(|bytes| base64_literal(&bytes))
+                .ok_or_else(|| {
+                    Error::InvalidExpression(
+                        "base64 variants need a literal of at least two bytes".to_string(),
+                    )
+                })?,
+        };
+
+        // Letters that match either case are classes by now, and every combination of
+        // their cases would be a separate prefix. Look for them without regard to
+        // case instead.
+        let folded = CaseFolder::new().transform(hir.clone());
 
         // The compiled regex itself. We let a Compiler fill it in.
+        // Existence-only expressions don't even keep track of where they started, and
+        // nor do expressions that look back for their starts once they've matched.
+        let existence_only = matches!(
+            self.mode,
+            MatchMode::First(Submatch::None) | MatchMode::All(Submatch::None)
+        );
+        if self.lookback > 0 && (existence_only || self.get_max_capture() > 0 || self.max_edits > 0)
+        {
+            return Err(Error::InvalidExpression(
+                "lookback needs Submatch::Expression, without max_edits".to_string(),
+            ));
+        }
+
         let mut compiled = CompiledRegex {
             id: self.id,
-            capture_count: 1,
+            capture_count: if existence_only || self.lookback > 0 {
+                0
+            } else {
+                1
+            },
             named_captures: HashMap::new(),
             char_classes: Vec::new(),
             mode: self.mode,
             program: Vec::new(),
+            unoptimized_size: 0,
+            repeats: Vec::new(),
+            literals: Vec::new(),
+            literal_states: Vec::new(),
             anchored_start: hir.is_anchored_start(),
-            prefixes: if hir.is_anchored_start() {
+            line_terminator: self.line_terminator,
+            variant,
+            max_edits: self.max_edits,
+            lookback: self.lookback,
+            reverse: None,
+            reversed: false,
+            positions: None,
+            glushkov: None,
+            folded_prefixes: folded != hir,
+            prefixes: if hir.is_anchored_start() || self.max_edits > 0 {
                 // Since we can early exit on anchored expressions,
                 // there's no need to bloat the AC automaton with its prefixes.
+                // Approximate matches needn't contain their prefixes at all.
                 vec![]
             } else {
-                Literals::prefixes(&hir)
+                Literals::prefixes(&folded)
                     .literals()
                     .iter()
                     .map(|x| x.to_vec())
                     .collect()
             },
         };
+        self.assemble(&mut compiled, hir.kind())?;
 
-        Compiler::new(&mut compiled, self.max_repeat, self.max_instructions).build(hir.kind())?;
+        // Expressions that needn't know where their matches start, or whose starts
+        // follow from their ends, can run on the bit-parallel engine.
+        if self.max_edits == 0 && compiled.capture_count <= 1 {
+            compiled.positions = Positions::new(&hir);
+        }
+        if let Some(positions) = compiled.positions.as_ref() {
+            if self.bit_parallel
+                && (existence_only || self.lookback > 0 || positions.length().is_some())
+            {
+                compiled.glushkov = Glushkov::new(positions);
+            }
+        }
+
+        if self.lookback > 0 {
+            // The reversed program only ever runs back from the end of a match, so it's
+            // anchored there, and it reports every start it reaches.
+            let mut reverse = CompiledRegex {
+                id: self.id,
+                capture_count: 1,
+                named_captures: HashMap::new(),
+                char_classes: Vec::new(),
+                mode: MatchMode::All(Submatch::Expression),
+                program: Vec::new(),
+                unoptimized_size: 0,
+                repeats: Vec::new(),
+                literals: Vec::new(),
+                literal_states: Vec::new(),
+                anchored_start: true,
+                line_terminator: self.line_terminator,
+                variant,
+                max_edits: 0,
+                lookback: 0,
+                reverse: None,
+                reversed: true,
+                positions: None,
+                glushkov: None,
+                folded_prefixes: false,
+                prefixes: vec![],
+            };
+            self.assemble(&mut reverse, Reverser::new().transform(hir).kind())?;
+            compiled.reverse = Some(Box::new(reverse));
+        }
         Ok(compiled)
     }
 
-    fn parse(&self) -> Result<Hir, Error> {
+    // Compiles an expression into a program, and optimizes it if asked to.
+    fn assemble(&self, compiled: &mut CompiledRegex, hir: &HirKind) -> Result<(), Error> {
+        // Counted loops track threads by where they entered, which leaves no room for
+        // an edit count, so approximate expressions are always unrolled.
+        let counted_repetition_threshold = if self.max_edits > 0 {
+            usize::MAX
+        } else {
+            self.counted_repetition_threshold
+        };
+
+        Compiler::new(
+            compiled,
+            self.max_repeat,
+            self.max_instructions,
+            counted_repetition_threshold,
+        )
+        .build(hir)?;
+
+        compiled.unoptimized_size = compiled.program.len();
+        if self.optimize {
+            let map = optimize(&mut compiled.program);
+            for repeat in compiled.repeats.iter_mut() {
+                repeat.pc = map[repeat.pc];
+            }
+
+            // Approximate expressions can skip or repeat any byte of a run, so each
+            // byte keeps an instruction of its own.
+            if compiled.max_edits == 0 {
+                let (runs, map) = merge_literals(&mut compiled.program);
+                for repeat in compiled.repeats.iter_mut() {
+                    repeat.pc = map[repeat.pc];
+                }
+                compiled.add_literals(runs);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(&self, encoding: Encoding) -> Result<Hir, Error> {
+        let unicode = self.unicode.unwrap_or(encoding != Encoding::Byte);
         Ok(ParserBuilder::new()
             .nest_limit(self.max_depth)
-            .allow_invalid_utf8(self.allow_invalid_utf8 || self.encoding != Encoding::UTF8)
+            .allow_invalid_utf8(self.allow_invalid_utf8 || encoding != Encoding::UTF8 || !unicode)
             .ignore_whitespace(self.allow_whitespace)
             .case_insensitive(!self.case_sensitive)
             .multi_line(self.multi_line)
             .dot_matches_new_line(self.dot_matches_new_line)
-            .unicode(self.encoding == Encoding::UTF8)
+            .swap_greed(self.swap_greed)
+            .octal(self.octal)
+            .unicode(unicode)
             .build()
             .parse(&self.expression)?)
     }
@@ -203,6 +498,7 @@ impl Regex {
             MatchMode::All(Submatch::All) => u32::MAX,
             MatchMode::All(Submatch::AtMost(n)) => n,
             MatchMode::All(Submatch::Expression) => 0,
+            MatchMode::First(Submatch::None) | MatchMode::All(Submatch::None) => 0,
         }
     }
 }
@@ -214,7 +510,22 @@ pub struct CompiledRegex {
     char_classes: Vec<CharClass>,
     mode: MatchMode,
     program: Vec<Instruction>,
+    unoptimized_size: usize,
+    repeats: Vec<CountedRepetition>,
+    literals: Vec<LiteralRun>,
+    // The instruction and number of bytes matched for each state partway through a
+    // literal run, numbered from the end of the program.
+    literal_states: Vec<(usize, usize)>,
     anchored_start: bool,
+    line_terminator: LineTerminator,
+    variant: Variant,
+    max_edits: usize,
+    lookback: usize,
+    reverse: Option<Box<CompiledRegex>>,
+    reversed: bool,
+    positions: Option<Positions>,
+    glushkov: Option<Glushkov>,
+    folded_prefixes: bool,
     prefixes: Vec<Vec<u8>>,
 }
 
@@ -222,6 +533,300 @@ impl CompiledRegex {
     pub fn get_id(&self) -> usize {
         self.id
     }
+
+    /// Returns the variant of the expression this program was compiled from.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Returns the number of instructions in the compiled program.
+    pub fn program_size(&self) -> usize {
+        self.program.len()
+    }
+
+    /// Returns the number of instructions in the compiled program before it was
+    /// optimized.
+    pub fn unoptimized_program_size(&self) -> usize {
+        self.unoptimized_size
+    }
+
+    // The positions of an expression that can be merged with others: one that needn't
+    // know where its matches start, or whose starts follow from their ends.
+    fn mergeable(&self) -> Option<&Positions> {
+        let existence_only = matches!(
+            self.mode,
+            MatchMode::First(Submatch::None) | MatchMode::All(Submatch::None)
+        );
+        self.positions
+            .as_ref()
+            .filter(|x| self.lookback == 0 && (existence_only || x.length().is_some()))
+    }
+
+    // Whether the expression is done once it has matched: it's reported at most once a
+    // stream.
+    pub(crate) fn reports_once(&self) -> bool {
+        matches!(
+            self.mode,
+            MatchMode::First(_) | MatchMode::All(Submatch::None)
+        )
+    }
+
+    // The number of states a thread can be in: one for each instruction and number of
+    // edits made, and one for each byte partway through a literal run.
+    fn states(&self) -> usize {
+        (self.program.len() + self.literal_states.len()) * (self.max_edits + 1)
+    }
+
+    // Returns the instruction a thread in the given state is at, and how many bytes of
+    // it the thread has matched if it's partway through a literal run.
+    fn locate(&self, state: usize) -> (usize, usize) {
+        match state.checked_sub(self.program.len()) {
+            Some(index) => self.literal_states[index],
+            None => (state, 0),
+        }
+    }
+
+    // Numbers the states partway through each of the program's literal runs.
+    fn add_literals(&mut self, runs: Vec<(Vec<u8>, bool)>) {
+        for (bytes, folded) in runs {
+            self.literals.push(LiteralRun {
+                bytes,
+                folded,
+                state: 0,
+            });
+        }
+        for (pc, instruction) in self.program.iter().enumerate() {
+            if let Instruction::Literal(l) = *instruction {
+                let run = &mut self.literals[l];
+                run.state = self.program.len() + self.literal_states.len();
+                self.literal_states
+                    .extend((1..run.bytes.len()).map(|matched| (pc, matched)));
+            }
+        }
+    }
+
+    // The number of capture slots threads can hold at once: one for each state in
+    // either queue, and one for each thread a counted loop can hold.
+    fn slot_count(&self) -> usize {
+        2 * self.states() + self.repeats.iter().map(|x| x.capacity()).sum::<usize>()
+    }
+
+    /// Returns the approximate number of bytes allocated by the compiled expression.
+    pub fn heap_size(&self) -> usize {
+        self.program.capacity() * size_of::<Instruction>()
+            + self.char_classes.capacity() * size_of::<CharClass>()
+            + self.repeats.capacity() * size_of::<CountedRepetition>()
+            + self.literals.capacity() * size_of::<LiteralRun>()
+            + self
+                .literals
+                .iter()
+                .map(|x| x.bytes.capacity())
+                .sum::<usize>()
+            + self.literal_states.capacity() * size_of::<(usize, usize)>()
+            + self.prefixes.capacity() * size_of::<Vec<u8>>()
+            + self.prefixes.iter().map(|x| x.capacity()).sum::<usize>()
+            + self.named_captures.capacity() * size_of::<(String, usize)>()
+            + self
+                .named_captures
+                .keys()
+                .map(|x| x.capacity())
+                .sum::<usize>()
+            + self
+                .reverse
+                .as_ref()
+                .map_or(0, |x| size_of::<CompiledRegex>() + x.heap_size())
+            + self.positions.as_ref().map_or(0, |x| x.heap_size())
+            + self.glushkov.as_ref().map_or(0, |x| x.heap_size())
+    }
+
+    /// Returns the approximate number of bytes each scratch space allocates to run
+    /// this expression. This grows with the product of the program size and the
+    /// number of capture groups.
+    pub fn scratch_size(&self) -> usize {
+        size_of::<RegexScratch>()
+            + 2 * Queue::heap_size(self.states())
+            + Slots::heap_size(self.slot_count(), self.capture_count)
+            + self.capture_count * size_of::<Capture>()
+            + self.repeats.len() * size_of::<CountedLoop>()
+            + self
+                .repeats
+                .iter()
+                .map(|x| CountedLoop::heap_size(x.capacity()))
+                .sum::<usize>()
+            + self.reverse.as_ref().map_or(0, |x| {
+                size_of::<Lookback>() + 2 * self.lookback + x.scratch_size()
+            })
+    }
+}
+
+// Debugging output.
+impl CompiledRegex {
+    /// Returns the compiled program.
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    /// Returns a human-readable listing of the compiled program, along with the
+    /// capture slots, anchoring and prefixes of the expression.
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "expression {}", self.id).unwrap();
+        writeln!(out, "mode: {:?}", self.mode).unwrap();
+        writeln!(out, "anchored: {}", self.anchored_start).unwrap();
+        writeln!(out, "line terminator: {:?}", self.line_terminator).unwrap();
+        if self.max_edits > 0 {
+            writeln!(out, "max edits: {}", self.max_edits).unwrap();
+        }
+        if self.lookback > 0 {
+            writeln!(out, "lookback: {}", self.lookback).unwrap();
+        }
+        if let Some(glushkov) = self.glushkov.as_ref() {
+            writeln!(
+                out,
+                "engine: bit-parallel ({} positions)",
+                glushkov.positions()
+            )
+            .unwrap();
+        }
+
+        let mut names: Vec<(&String, &usize)> = self.named_captures.iter().collect();
+        names.sort_by_key(|x| x.1);
+        write!(out, "captures: {}", self.capture_count).unwrap();
+        for (name, index) in names {
+            write!(out, " {}={}", index, name).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        write!(out, "prefixes:").unwrap();
+        if self.folded_prefixes {
+            write!(out, " (case-insensitive)").unwrap();
+        }
+        for prefix in self.prefixes.iter() {
+            write!(out, " \"{}\"", escape_bytes(prefix)).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        writeln!(
+            out,
+            "instructions: {} ({} before optimization)",
+            self.program.len(),
+            self.unoptimized_size
+        )
+        .unwrap();
+        for pc in 0..self.program.len() {
+            writeln!(out, "{:04} {}", pc, self.describe(pc)).unwrap();
+        }
+        out
+    }
+
+    /// Returns the program as a Graphviz DOT graph. Edges are labelled with the byte
+    /// or class consumed to follow them, and the branches of a split are labelled
+    /// with their priority.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph expression_{} {{", self.id).unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+        for pc in 0..self.program.len() {
+            let label = format!("{:04} {}", pc, self.describe(pc));
+            writeln!(out, "    {} [label=\"{}\"];", pc, escape_dot(&label)).unwrap();
+        }
+
+        for (pc, instruction) in self.program.iter().enumerate() {
+            let mut edge = |to: usize, label: &str| {
+                writeln!(
+                    out,
+                    "    {} -> {} [label=\"{}\"];",
+                    pc,
+                    to,
+                    escape_dot(label)
+                )
+                .unwrap();
+            };
+            match *instruction {
+                Instruction::Byte(b) => edge(pc + 1, &escape_bytes(&[b])),
+                Instruction::Literal(l) => edge(pc + 1, &self.literals[l].text()),
+                Instruction::FoldedByte(b) => edge(pc + 1, &folded_text(b)),
+                Instruction::CharClass(cc) => edge(pc + 1, &format!("{:?}", self.char_classes[cc])),
+                Instruction::Jump(target) => edge(target, ""),
+                Instruction::Match => {}
+                Instruction::Split(pc_a, pc_b) => {
+                    edge(pc_a, "1");
+                    edge(pc_b, "2");
+                }
+                Instruction::RepeatEnter(r) => {
+                    let repeat = &self.repeats[r];
+                    edge(repeat.consume(), "");
+                    if repeat.minimum == 0 {
+                        edge(repeat.exit(), "exit");
+                    }
+                }
+                Instruction::RepeatNext(r) => {
+                    let repeat = &self.repeats[r];
+                    edge(repeat.consume(), "");
+                    edge(repeat.exit(), "exit");
+                }
+                Instruction::RepeatConsume(r) => {
+                    let repeat = &self.repeats[r];
+                    edge(
+                        repeat.next(),
+                        &format!("{:?}", self.char_classes[repeat.class]),
+                    );
+                }
+                _ => edge(pc + 1, ""),
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    fn describe(&self, pc: usize) -> String {
+        match self.program[pc] {
+            Instruction::Assert(a) => format!("assert {:?}", a),
+            Instruction::Byte(b) => format!("byte '{}'", escape_bytes(&[b])),
+            Instruction::Literal(l) => format!("literal \"{}\"", self.literals[l].text()),
+            Instruction::FoldedByte(b) => format!("folded byte '{}'", folded_text(b)),
+            Instruction::CharClass(cc) => format!("class {:?}", self.char_classes[cc]),
+            Instruction::End(index) => format!("end {}", index),
+            Instruction::Jump(target) => format!("jump {}", target),
+            Instruction::Match => "match".to_string(),
+            Instruction::Split(pc_a, pc_b) => format!("split {}, {}", pc_a, pc_b),
+            Instruction::Start(index) => format!("start {}", index),
+            Instruction::NoOp => "noop".to_string(),
+            Instruction::RepeatEnter(r) => {
+                let repeat = &self.repeats[r];
+                let maximum = match repeat.maximum {
+                    Some(maximum) => maximum.to_string(),
+                    None => String::new(),
+                };
+                format!(
+                    "repeat.enter {} {:?}{{{},{}}} exit {}",
+                    r,
+                    self.char_classes[repeat.class],
+                    repeat.minimum,
+                    maximum,
+                    repeat.exit()
+                )
+            }
+            Instruction::RepeatNext(r) => format!("repeat.next {}", r),
+            Instruction::RepeatConsume(r) => format!("repeat.consume {}", r),
+        }
+    }
+}
+
+// Returns bytes as printable ASCII, escaping anything that isn't.
+fn escape_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'\\' | b'\'' | b'"' => format!("\\{}", b as char),
+            b if b.is_ascii_graphic() || b == b' ' => (b as char).to_string(),
+            b => format!("\\x{:02x}", b),
+        })
+        .collect()
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
 struct Compiler<'a> {
@@ -229,14 +834,21 @@ struct Compiler<'a> {
     classes: HashMap<CharClass, usize>,
     max_repeat: usize,
     max_instructions: usize,
+    counted_repetition_threshold: usize,
 }
 
 impl<'a> Compiler<'a> {
-    fn new(compiled: &'a mut CompiledRegex, max_repeat: usize, max_instructions: usize) -> Self {
+    fn new(
+        compiled: &'a mut CompiledRegex,
+        max_repeat: usize,
+        max_instructions: usize,
+        counted_repetition_threshold: usize,
+    ) -> Self {
         Self {
             compiled,
             max_repeat,
             max_instructions,
+            counted_repetition_threshold,
             classes: HashMap::new(),
         }
     }
@@ -302,6 +914,24 @@ impl<'a> Compiler<'a> {
             return Err(Error::TooManyRepetitions);
         }
 
+        // Large greedy repetitions of a single byte are run as a counted loop rather
+        // than being unrolled; see CountedRepetition.
+        if greedy && max(minimum, maximum) >= self.counted_repetition_threshold {
+            if let Some(char_class) = single_byte_class(body) {
+                let class = self.add_byte_class(char_class);
+                let index = self.compiled.repeats.len();
+                self.compiled.repeats.push(CountedRepetition {
+                    pc: self.compiled.program.len(),
+                    class,
+                    minimum,
+                    maximum: if kleene { None } else { Some(maximum) },
+                });
+                self.add_instruction(Instruction::RepeatEnter(index))?;
+                self.add_instruction(Instruction::RepeatNext(index))?;
+                return self.add_instruction(Instruction::RepeatConsume(index));
+            }
+        }
+
         for _ in 0..minimum {
             self.compile(body)?;
         }
@@ -374,6 +1004,9 @@ impl<'a> Compiler<'a> {
                     "Unicode character classes are not supported; use ByteTransformer".to_string(),
                 ));
             }
+            HirKind::Class(Class::Bytes(b)) if folded_letter(b).is_some() => {
+                self.add_instruction(Instruction::FoldedByte(folded_letter(b).unwrap()))?;
+            }
             HirKind::Class(Class::Bytes(b)) => {
                 let char_class = CharClass::new_from_bytes(b);
                 let index = self.add_byte_class(char_class);
@@ -436,6 +1069,45 @@ impl<'a> Compiler<'a> {
     }
 }
 
+// The transformers that turn an expression into bytes in the given encoding. These
+// run before any user-supplied postprocessors.
+fn encoding_transformers(encoding: Encoding) -> Vec<Box<dyn Transformer>> {
+    let encoder: Box<dyn Transformer> = match encoding {
+        Encoding::Byte | Encoding::UTF8 => Box::new(ByteTransformer::new()),
+        Encoding::UTF16LE => Box::new(UTF16Transformer::new(false)),
+        Encoding::UTF16BE => Box::new(UTF16Transformer::new(true)),
+        Encoding::Latin1 => Box::new(Latin1Transformer::new()),
+    };
+
+    vec![
+        Box::new(UnicodeAlternationSimplifier::new()),
+        Box::new(ClassSimplifier::new()),
+        encoder,
+        Box::new(ByteAlternationSimplifier::new()),
+        Box::new(ClassSimplifier::new()),
+    ]
+}
+
+// Both cases of a folded letter, for debugging output.
+fn folded_text(b: u8) -> String {
+    format!("{}{}", b.to_ascii_uppercase() as char, b as char)
+}
+
+// Returns the set of bytes matched by a body that always consumes exactly one byte.
+fn single_byte_class(body: &HirKind) -> Option<CharClass> {
+    let mut char_class = CharClass::new();
+    match body {
+        HirKind::Literal(Literal::Byte(b)) => char_class.add(*b),
+        HirKind::Literal(Literal::Unicode(u)) if u.is_ascii() => char_class.add(*u as u8),
+        HirKind::Class(Class::Bytes(b)) => char_class = CharClass::new_from_bytes(b),
+        HirKind::Group(group) if group.kind == GroupKind::NonCapturing => {
+            return single_byte_class(group.hir.kind())
+        }
+        _ => return None,
+    }
+    Some(char_class)
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Assertion {
     StartOfText,
@@ -450,6 +1122,8 @@ pub enum Assertion {
 pub enum Instruction {
     Assert(Assertion),
     Byte(u8),
+    Literal(usize),
+    FoldedByte(u8),
     CharClass(usize),
     End(usize),
     Jump(usize),
@@ -457,6 +1131,85 @@ pub enum Instruction {
     Split(usize, usize),
     Start(usize),
     NoOp,
+    RepeatEnter(usize),
+    RepeatNext(usize),
+    RepeatConsume(usize),
+}
+
+// A counted loop over a single character class, used in place of unrolling
+// bounded repetitions like `[a-z]{1,5000}`.
+//
+// Because the body always consumes exactly one byte, every thread inside the
+// loop advances in lockstep and a thread's iteration count is simply the number
+// of bytes consumed since it entered. The loop is therefore tracked by the
+// entry offsets of the threads inside it, rather than by one program location
+// per iteration.
+//
+// A loop occupies three instructions: threads enter at RepeatEnter, and the loop
+// as a whole is represented in the queues by a single thread that alternates
+// between RepeatNext, which lets the best eligible thread leave the loop, and
+// RepeatConsume, which keeps the loop alive if the next byte is in the class.
+// The loop's thread and the threads leaving it are queued in order of their
+// starting offsets, as every other thread is.
+//
+// Only greedy repetitions are counted. The unrolled program for a lazy one leaves
+// it by way of its splits' first branches, which aren't queued, so every thread
+// in it that can leave does, and reports matches of its own; a counted loop only
+// lets one leave at a time.
+// A run of bytes matched by a single instruction, made by the optimizer out of a
+// run of Byte or FoldedByte instructions. A thread partway through the run is in a state of its
+// own, as it would have been at the Byte instruction it had reached: the thread
+// that has matched `n` bytes is in state `state + n - 1`.
+#[derive(Clone, Debug)]
+struct LiteralRun {
+    bytes: Vec<u8>,
+    folded: bool,
+    state: usize,
+}
+
+impl LiteralRun {
+    fn matches(&self, matched: usize, byte: u8) -> bool {
+        if self.folded {
+            byte.to_ascii_lowercase() == self.bytes[matched]
+        } else {
+            byte == self.bytes[matched]
+        }
+    }
+
+    fn text(&self) -> String {
+        if self.folded {
+            format!("(?i){}", escape_bytes(&self.bytes))
+        } else {
+            escape_bytes(&self.bytes)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CountedRepetition {
+    pc: usize,
+    class: usize,
+    minimum: usize,
+    maximum: Option<usize>,
+}
+
+impl CountedRepetition {
+    fn next(&self) -> usize {
+        self.pc + 1
+    }
+
+    fn consume(&self) -> usize {
+        self.pc + 2
+    }
+
+    fn exit(&self) -> usize {
+        self.pc + 3
+    }
+
+    // The largest number of distinct entry offsets that can be inside the loop at once.
+    fn capacity(&self) -> usize {
+        self.maximum.unwrap_or(self.minimum) + 1
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Hash)]
@@ -490,44 +1243,210 @@ impl CharClass {
     }
 }
 
+// Classes are shown as a list of ranges, e.g. [0-9A-Fa-f].
+impl fmt::Debug for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let escape = |b: u8| match b {
+            b'\\' | b'[' | b']' | b'-' | b'^' => format!("\\{}", b as char),
+            b if b.is_ascii_graphic() => (b as char).to_string(),
+            b => format!("\\x{:02x}", b),
+        };
+
+        write!(f, "[")?;
+        let mut byte = 0usize;
+        while byte < 256 {
+            if !self.contains(byte as u8) {
+                byte += 1;
+                continue;
+            }
+            let start = byte;
+            while byte < 256 && self.contains(byte as u8) {
+                byte += 1;
+            }
+            match byte - start {
+                1 => write!(f, "{}", escape(start as u8))?,
+                2 => write!(f, "{}{}", escape(start as u8), escape(start as u8 + 1))?,
+                _ => write!(f, "{}-{}", escape(start as u8), escape((byte - 1) as u8))?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// The memory used by a single expression in a database.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryUsage {
+    pub id: usize,
+    pub heap_size: usize,
+    pub scratch_size: usize,
+}
+
 pub struct Database {
     expressions: Vec<CompiledRegex>,
     ids: HashMap<usize, Vec<usize>>,
     ac: AhoCorasick,
     scratch_map: HashMap<usize, Vec<usize>>,
+
+    // The automaton and map for expressions whose prefixes are case-insensitive.
+    folded_ac: AhoCorasick,
+    folded_scratch_map: HashMap<usize, Vec<usize>>,
+
+    // The expressions that run together in one automaton, if any do.
+    merged: Option<Merged>,
+
+    combinations: Combinations,
 }
 
 impl<'a> Database {
-    pub fn make_scratch(&'a self, handler: &'a mut dyn MatchHandler) -> Scratch {
+    /// Returns the approximate number of bytes allocated by the database itself.
+    pub fn heap_size(&self) -> usize {
+        self.expressions.capacity() * size_of::<CompiledRegex>()
+            + self
+                .expressions
+                .iter()
+                .map(|x| x.heap_size())
+                .sum::<usize>()
+            + self.ac.heap_size()
+            + self.folded_ac.heap_size()
+            + map_size(&self.ids)
+            + map_size(&self.scratch_map)
+            + map_size(&self.folded_scratch_map)
+            + self.merged.as_ref().map_or(0, |x| x.heap_size())
+            + self.combinations.heap_size()
+    }
+
+    /// Returns the approximate number of bytes allocated by each scratch space made
+    /// from this database.
+    pub fn scratch_size(&self) -> usize {
+        scratch_overhead(self.expressions.len())
+            + self
+                .expressions
+                .iter()
+                .map(|x| x.scratch_size())
+                .sum::<usize>()
+            + self.merged.as_ref().map_or(0, |x| x.scratch_size())
+    }
+
+    /// Returns the memory used by each expression, in the order they were added to
+    /// the database.
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.expressions
+            .iter()
+            .map(|x| MemoryUsage {
+                id: x.get_id(),
+                heap_size: x.heap_size(),
+                scratch_size: x.scratch_size(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn expressions(&self) -> &[CompiledRegex] {
+        &self.expressions
+    }
+
+    pub(crate) fn combinations(&self) -> &Combinations {
+        &self.combinations
+    }
+
+    pub fn make_scratch(&'a self, handler: &'a mut dyn MatchHandler) -> Scratch<'a> {
+        let lookback = self
+            .ac
+            .keywords()
+            .iter()
+            .chain(self.folded_ac.keywords().iter())
+            .map(|x| x.len())
+            .max()
+            .unwrap_or(0);
+
         Scratch {
             database: self,
             enabled: ShrinkSet::new(self.expressions.len()),
             disabled: GrowSet::with_capacity(self.expressions.len()),
-            scratch: self.expressions.iter().map(RegexScratch::new).collect(),
+            scratch: self
+                .expressions
+                .iter()
+                .enumerate()
+                .map(|(index, x)| RegexScratch {
+                    merged: self.merged.as_ref().is_some_and(|x| x.contains(index)),
+                    ..RegexScratch::new(x)
+                })
+                .collect(),
             next_check: handler.get_pulse_interval(),
             seen: 0,
             handler,
             last_byte: None,
             ac_scratch: self.ac.get_scratch(),
+            folded_ac_scratch: self.folded_ac.get_scratch(),
+            // The tail is topped up before it's trimmed, so it can briefly hold twice
+            // the lookback.
+            tail: Vec::with_capacity(2 * lookback),
+            tracer: TracerSlot::default(),
+            decoders: DecoderChain::new(),
+            decoded: Vec::new(),
+            offsets: None,
+            combinations: CombinationState::new(&self.combinations),
+            buffer: None,
+            lookback,
+            merged: self.merged.as_ref().map(|x| x.make_scratch()),
         }
     }
 }
 
+// The number of bytes allocated by a scratch space regardless of the expressions it runs.
+fn scratch_overhead(expressions: usize) -> usize {
+    4 * expressions * size_of::<usize>()
+}
+
+fn map_size(map: &HashMap<usize, Vec<usize>>) -> usize {
+    map.capacity() * size_of::<(usize, Vec<usize>)>()
+        + map
+            .values()
+            .map(|x| x.capacity() * size_of::<usize>())
+            .sum::<usize>()
+}
+
 pub struct DatabaseBuilder {
     expressions: Vec<CompiledRegex>,
     ids: HashMap<usize, Vec<usize>>,
+    combinations: Vec<CombinationRule>,
+    sequences: Vec<SequenceRule>,
+    max_scratch_bytes: usize,
+    merge: bool,
 }
 
-impl<'a> DatabaseBuilder {
+impl DatabaseBuilder {
     pub fn new() -> Self {
         Self {
             expressions: Vec::new(),
             ids: HashMap::new(),
+            combinations: Vec::new(),
+            sequences: Vec::new(),
+            max_scratch_bytes: usize::MAX,
+            merge: false,
         }
     }
 
+    /// Limits the number of bytes each scratch space made from the database may
+    /// allocate. `try_build` fails if the expressions would need more than this, and
+    /// `build` panics.
+    pub fn max_scratch_bytes(mut self, max_scratch_bytes: usize) -> Self {
+        self.max_scratch_bytes = max_scratch_bytes;
+        self
+    }
+
+    /// Runs the expressions that can be run together in a single automaton, so that
+    /// adding expressions adds little to the time each byte takes. These are the
+    /// expressions without capture groups or approximate matching, with assertions only
+    /// at either end, that report `Submatch::None` or match only strings of one length;
+    /// see `Regex::bit_parallel`. Matches are reported with the same ids as before.
+    /// The automaton is built as the input needs it, in a cache in each scratch space.
+    pub fn merge_expressions(mut self, merge: bool) -> Self {
+        self.merge = merge;
+        self
+    }
+
     pub fn with_expression(mut self, expression: CompiledRegex) -> Self {
-        self.ids.entry(expression.get_id()).or_insert_with(Vec::new);
+        self.ids.entry(expression.get_id()).or_default();
 
         let referenced_expressions = self.ids.get_mut(&expression.get_id()).unwrap();
         referenced_expressions.push(self.expressions.len());
@@ -542,24 +1461,109 @@ impl<'a> DatabaseBuilder {
         self
     }
 
+    /// Adds a rule that matches when a combination of the database's expressions
+    /// has matched. See `CombinationRule`.
+    pub fn with_combination(mut self, rule: CombinationRule) -> Self {
+        self.combinations.push(rule);
+        self
+    }
+
+    /// Adds a rule that matches when some of the database's expressions match in
+    /// order. See `SequenceRule`.
+    pub fn with_sequence(mut self, rule: SequenceRule) -> Self {
+        self.sequences.push(rule);
+        self
+    }
+
+    /// Builds the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a combination or sequence rule is invalid, or if each scratch space
+    /// would need more than `max_scratch_bytes`. Use `try_build` to get the error
+    /// instead.
     pub fn build(self) -> Database {
-        let (scratch_map, ac) = self.build_aho_corasick(6);
-        Database {
+        match self.try_build() {
+            Ok(database) => database,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Builds the database, failing if a combination or sequence rule is invalid, or
+    /// if each scratch space would need more than `max_scratch_bytes`.
+    pub fn try_build(mut self) -> Result<Database, Error> {
+        let combinations = Combinations::new(
+            take(&mut self.combinations),
+            take(&mut self.sequences),
+            &self.ids,
+        )?;
+        let mut scratch_size = scratch_overhead(self.expressions.len());
+        for expression in self.expressions.iter() {
+            scratch_size = scratch_size.saturating_add(expression.scratch_size());
+            if scratch_size > self.max_scratch_bytes {
+                return Err(Error::ScratchTooLarge(expression.get_id()));
+            }
+        }
+
+        let merged = if self.merge {
+            Merged::new(
+                self.expressions.len(),
+                self.expressions
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, x)| Some((index, x.mergeable()?, x.line_terminator)))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        if let Some(merged) = merged.as_ref() {
+            scratch_size = scratch_size.saturating_add(merged.scratch_size());
+            if scratch_size > self.max_scratch_bytes {
+                let index = (0..self.expressions.len())
+                    .find(|&x| merged.contains(x))
+                    .unwrap();
+                return Err(Error::ScratchTooLarge(self.expressions[index].get_id()));
+            }
+        }
+
+        let (scratch_map, ac) = self.build_aho_corasick(6, false, merged.as_ref());
+        let (folded_scratch_map, folded_ac) = self.build_aho_corasick(6, true, merged.as_ref());
+        Ok(Database {
             expressions: self.expressions,
             ids: self.ids,
             ac,
             scratch_map,
-        }
+            folded_ac,
+            folded_scratch_map,
+            merged,
+            combinations,
+        })
     }
 
-    fn build_aho_corasick(&self, length: usize) -> (HashMap<usize, Vec<usize>>, AhoCorasick) {
+    fn build_aho_corasick(
+        &self,
+        length: usize,
+        folded: bool,
+        merged: Option<&Merged>,
+    ) -> (HashMap<usize, Vec<usize>>, AhoCorasick) {
         let mut literal_map: HashMap<Vec<u8>, usize> = HashMap::new();
         let mut literals: Vec<Vec<u8>> = vec![];
         let mut scratch_map: HashMap<usize, Vec<usize>> = HashMap::new();
 
         for (index, expression) in self.expressions.iter().enumerate() {
+            // Merged expressions run over all of the input.
+            if expression.folded_prefixes != folded || merged.is_some_and(|x| x.contains(index)) {
+                continue;
+            }
+
             for literal in expression.prefixes.iter() {
                 let literal = &literal[..min(length, literal.len())];
+                let literal = &if folded {
+                    literal.to_ascii_lowercase()
+                } else {
+                    literal.to_vec()
+                };
                 if !literal_map.contains_key(literal) {
                     literal_map.insert(literal.to_vec(), literals.len());
                     literals.push(literal.to_vec());
@@ -575,16 +1579,65 @@ impl<'a> DatabaseBuilder {
             }
         }
 
-        (scratch_map, AhoCorasick::new(&literals, 2))
+        (scratch_map, AhoCorasick::new(&literals, 2, folded))
     }
 }
 
-impl<'a> Default for DatabaseBuilder {
+impl Default for DatabaseBuilder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Counters for a single expression in a scratch space.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ExpressionStats {
+    pub id: usize,
+
+    // The number of times one of the expression's prefixes was found.
+    pub prefix_hits: usize,
+
+    // The number of bytes the expression's threads were run over.
+    pub bytes_executed: usize,
+
+    // The largest number of threads queued at once.
+    pub peak_threads: usize,
+
+    // The number of matches reported to the handler.
+    pub matches: usize,
+
+    // The time spent running the expression.
+    pub time: Duration,
+}
+
+impl ExpressionStats {
+    fn new(id: usize) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+}
+
+/// A snapshot of the statistics collected by a scratch space, with one entry per
+/// expression in the order they were added to the database.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScratchStats {
+    pub expressions: Vec<ExpressionStats>,
+}
+
+impl ScratchStats {
+    /// Returns the statistics for all expressions with the given id.
+    pub fn get(&self, id: usize) -> impl Iterator<Item = &ExpressionStats> {
+        self.expressions.iter().filter(move |x| x.id == id)
+    }
+
+    /// Returns the total time spent running expressions.
+    pub fn time(&self) -> Duration {
+        self.expressions.iter().map(|x| x.time).sum()
+    }
+}
+
 #[derive(Eq, PartialEq)]
 enum Disabled {
     Yes(ContinueMatching),
@@ -611,6 +1664,31 @@ pub struct Scratch<'a> {
     handler: &'a mut dyn MatchHandler,
     last_byte: Option<u8>,
     ac_scratch: AhoCorasickScratch<'a>,
+    folded_ac_scratch: AhoCorasickScratch<'a>,
+
+    // The end of the input so far, for prefixes that started in an earlier block.
+    tail: Vec<u8>,
+
+    tracer: TracerSlot<'a>,
+
+    // Decoders the input goes through before it's matched, the bytes they decoded
+    // from the last block, and where those bytes came from.
+    decoders: DecoderChain<'a>,
+    decoded: Vec<u8>,
+    offsets: Option<OffsetMap>,
+
+    // How far before the current block a match found by its prefix can start.
+    lookback: usize,
+
+    // Which expressions that combinations refer to have matched, and which
+    // combinations have been reported.
+    combinations: CombinationState,
+
+    // The input that captures might still cover, if matches are reported with it.
+    buffer: Option<CaptureBuffer>,
+
+    // The cached automaton of the merged expressions, if there are any.
+    merged: Option<MergedScratch<'a>>,
 }
 
 impl<'a> Scratch<'a> {
@@ -622,11 +1700,72 @@ impl<'a> Scratch<'a> {
         self.next_check = self.handler.get_pulse_interval();
         self.last_byte = None;
         self.ac_scratch.reset();
+        self.folded_ac_scratch.reset();
+        self.tail.clear();
         for scratch in self.scratch.iter_mut() {
             scratch.reset();
         }
-        self
-    }
+        if let Some(merged) = self.merged.as_mut() {
+            merged.reset();
+        }
+        self.decoders.reset();
+        if let Some(offsets) = self.offsets.as_mut() {
+            offsets.clear();
+        }
+        self.combinations.reset();
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.clear();
+        }
+        self
+    }
+
+    /// Decodes the input with `decoder` before matching it. Decoders added this way
+    /// run in order, each decoding the output of the one before, and the offsets of
+    /// matches are mapped back to the input as it was pushed.
+    pub fn with_decoder(mut self, decoder: Box<dyn Decoder + 'a>) -> Self {
+        self.decoders.push(decoder);
+        self.offsets.get_or_insert_with(OffsetMap::new);
+        self
+    }
+
+    /// Keeps up to the last `limit` bytes of input that running matches might capture,
+    /// and reports matches to `MatchHandler::on_match_with_data` along with the bytes
+    /// they captured. Captures longer than `limit` are reported without their bytes.
+    /// If the input is decoded, the bytes are those the expressions matched.
+    pub fn with_capture_buffer(mut self, limit: usize) -> Self {
+        self.buffer = Some(CaptureBuffer::new(limit));
+        self
+    }
+
+    /// Reports the execution of the matching VM to a tracer.
+    #[cfg(feature = "trace")]
+    pub fn with_tracer(mut self, tracer: &'a mut dyn Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Collects statistics about each expression as it runs; see `Scratch::stats`.
+    pub fn with_stats(mut self) -> Self {
+        for scratch in self.scratch.iter_mut() {
+            scratch.profile = true;
+        }
+        self
+    }
+
+    /// Returns the statistics collected since the scratch space was made or they
+    /// were last reset. Statistics are kept across calls to `reset` and `finish`.
+    /// Nothing is collected unless the scratch space was made `with_stats`.
+    pub fn stats(&self) -> ScratchStats {
+        ScratchStats {
+            expressions: self.scratch.iter().map(|x| x.stats).collect(),
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        for scratch in self.scratch.iter_mut() {
+            scratch.stats = ExpressionStats::new(scratch.regex.id);
+        }
+    }
 
     pub fn finish(mut self) -> Self {
         self.push_finish();
@@ -642,30 +1781,233 @@ impl<'a> Scratch<'a> {
     }
 
     pub fn push_finish(&mut self) {
+        if !self.decoders.is_empty() {
+            let mut decoded = take(&mut self.decoded);
+            decoded.clear();
+            self.decoders
+                .finish(&mut decoded, self.offsets.as_mut().unwrap());
+            self.scan(&decoded);
+            self.decoded = decoded;
+        }
+
+        let mut combining = CombiningHandler::new(
+            &mut *self.handler,
+            &self.database.combinations,
+            &mut self.combinations,
+        );
+        let mut mapped = MappedHandler::new(&mut combining, self.offsets.as_ref());
+        let mut handler = BufferedHandler::new(&mut mapped, self.buffer.as_ref());
+        if let Some(merged) = self.merged.as_mut() {
+            let (enabled, disabled, scratch) =
+                (&self.enabled, &mut self.disabled, &mut self.scratch);
+            let mut report = |index, offset, last_byte, byte| {
+                report_merged(
+                    index,
+                    offset,
+                    last_byte,
+                    byte,
+                    enabled,
+                    disabled,
+                    scratch,
+                    &mut handler,
+                )
+            };
+            merged.finish(self.seen, self.last_byte, &mut report);
+        }
         for &index in self.enabled.iter() {
-            self.scratch[index].handle_finish(self.seen, self.last_byte, self.handler);
+            let scratch = &mut self.scratch[index];
+            if scratch.merged {
+                continue;
+            }
+            let started = scratch.start_timer();
+            scratch.handle_finish(self.seen, self.last_byte, &mut handler, &mut self.tracer);
+            scratch.stop_timer(started);
+        }
+
+        // Expressions that haven't matched by now never will.
+        let end = self
+            .offsets
+            .as_ref()
+            .map_or(self.seen, |x| x.start(self.seen));
+        self.database
+            .combinations
+            .finish(&mut self.combinations, end, &mut *self.handler);
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> ContinueMatching {
+        self.push_spans(bytes, None)
+    }
+
+    // Starts a new scratch space at `offset` in the input, as though it had scanned the
+    // input before it and found nothing running there. `before` is the input up to
+    // `offset`, of which only the last few bytes are needed, for the prefixes of the
+    // expressions and the assertions before the first byte.
+    pub(crate) fn seek(&mut self, offset: usize, before: &[u8]) {
+        // Assertions see the last byte even when there are no prefixes to look back for.
+        self.last_byte = before.last().copied();
+        let before = &before[before.len().saturating_sub(self.lookback)..];
+        self.seen = offset;
+        self.tail.clear();
+        self.tail.extend_from_slice(before);
+        self.ac_scratch.seek(offset, before);
+        self.folded_ac_scratch.seek(offset, before);
+    }
+
+    // Whether no expression is partway through a match.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.enabled
+            .iter()
+            .all(|&index| self.scratch[index].is_idle())
+            && self.merged.as_ref().is_none_or(|x| x.is_idle())
+    }
+
+    /// Pushes input that was normalized before it got here, along with the span of
+    /// the original stream each byte came from. The offsets of matches are mapped
+    /// back to the original. Input pushed with `push` afterwards is taken to follow
+    /// on from the last byte mapped.
+    ///
+    /// Panics if `offsets` doesn't map every byte of `bytes`.
+    pub fn push_mapped(&mut self, bytes: &[u8], offsets: &OffsetMap) -> ContinueMatching {
+        assert_eq!(
+            bytes.len(),
+            offsets.len(),
+            "every byte pushed must be mapped"
+        );
+        self.offsets.get_or_insert_with(OffsetMap::new);
+        self.push_spans(bytes, Some(offsets))
+    }
+
+    fn push_spans(&mut self, bytes: &[u8], spans: Option<&OffsetMap>) -> ContinueMatching {
+        match self.offsets.as_mut() {
+            None => self.scan(bytes),
+            Some(offsets) if self.decoders.is_empty() => {
+                for index in 0..bytes.len() {
+                    let (start, end) = spans.map_or(offsets.next(), |x| x.span(index).unwrap());
+                    offsets.push(start, end);
+                }
+                self.scan(bytes)
+            }
+            Some(offsets) => {
+                let mut decoded = take(&mut self.decoded);
+                decoded.clear();
+                self.decoders.decode(bytes, spans, &mut decoded, offsets);
+                let result = self.scan(&decoded);
+                self.decoded = decoded;
+                result
+            }
+        }
+    }
+
+    // Forgets where decoded bytes came from, and the buffered input, once no match
+    // can include them.
+    fn prune(&mut self) {
+        if self.offsets.is_none() && self.buffer.is_none() {
+            return;
+        }
+        let earliest = self.earliest_start();
+        if let Some(offsets) = self.offsets.as_mut() {
+            offsets.prune(earliest);
+        }
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.prune(earliest);
+        }
+    }
+
+    // The earliest offset a match could still start at: where the earliest running
+    // thread started, or where a prefix that continues into the next block could.
+    fn earliest_start(&self) -> usize {
+        let prefix = self.seen.saturating_sub(self.lookback);
+        self.enabled
+            .iter()
+            .filter_map(|&index| self.scratch[index].earliest_start())
+            .chain(
+                self.merged
+                    .as_ref()
+                    .and_then(|x| x.earliest_start(self.seen)),
+            )
+            .min()
+            .map_or(prefix, |x| x.min(prefix))
+    }
+
+    fn scan(&mut self, bytes: &[u8]) -> ContinueMatching {
+        let result = self.scan_block(bytes);
+        self.prune();
+
+        // Sequences need the block's matches in order, so they wait until it's done.
+        let sequenced = self
+            .database
+            .combinations
+            .flush(&mut self.combinations, &mut *self.handler);
+        match result {
+            ContinueMatching::Yes => sequenced,
+            ContinueMatching::No => ContinueMatching::No,
         }
     }
 
     // FIXME - this whole thing needs to be cleaned up a lot. Holy hell.
-    pub fn push(&mut self, bytes: &'a [u8]) -> ContinueMatching {
+    fn scan_block(&mut self, bytes: &[u8]) -> ContinueMatching {
         // Nothing to actually do.
         if bytes.is_empty() || self.enabled.is_empty() {
             return ContinueMatching::Yes;
         }
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.push(bytes);
+        }
+        let mut combining = CombiningHandler::new(
+            &mut *self.handler,
+            &self.database.combinations,
+            &mut self.combinations,
+        );
+        let mut mapped = MappedHandler::new(&mut combining, self.offsets.as_ref());
+        let mut handler = BufferedHandler::new(&mut mapped, self.buffer.as_ref());
+
+        // The merged expressions run together, over all of the input.
+        if let Some(merged) = self.merged.as_mut() {
+            let (enabled, disabled, scratch) =
+                (&self.enabled, &mut self.disabled, &mut self.scratch);
+            let mut report = |index, offset, last_byte, byte| {
+                report_merged(
+                    index,
+                    offset,
+                    last_byte,
+                    byte,
+                    enabled,
+                    disabled,
+                    scratch,
+                    &mut handler,
+                )
+            };
+            if merged.scan(self.seen, self.last_byte, bytes, &mut report) == ContinueMatching::No {
+                return ContinueMatching::No;
+            }
+        }
 
         // Deal with initial setup and picking up where we left off from previous
         // blocks and executing expressions that can run anywhere.
         for &index in self.enabled.iter() {
             let scratch = &mut self.scratch[index];
+            if scratch.merged {
+                continue;
+            }
 
             // If there is any leftover execution from the previous block, or if these
             // expressions can run anywhere, run them now.
-            if !scratch.current_set.is_empty()
+            if !scratch.is_idle()
                 || scratch.regex.anchored_start
                 || scratch.regex.prefixes.is_empty()
             {
-                match scratch.handle_bytes(self.seen, self.last_byte, bytes, self.handler, false) {
+                let started = scratch.start_timer();
+                let disabled = scratch.handle_bytes(
+                    self.seen,
+                    self.last_byte,
+                    bytes,
+                    &mut handler,
+                    &mut self.tracer,
+                    false,
+                );
+                scratch.stop_timer(started);
+
+                match disabled {
                     Disabled::Yes(ContinueMatching::Yes) => self.disabled.add(index),
                     Disabled::Yes(ContinueMatching::No) | Disabled::No(ContinueMatching::No) => {
                         return ContinueMatching::No
@@ -676,53 +2018,85 @@ impl<'a> Scratch<'a> {
         }
 
         // Walk through all of the possible starts for the regexes with prefixes.
-        for m in self.ac_scratch.push(bytes) {
-            let keyword = &self.database.ac.keywords()[m.pattern()];
-
-            for &index in self.database.scratch_map.get(&m.pattern()).unwrap() {
-                let mut new_offset = m.offset();
-                let scratch = &mut self.scratch[index];
-                let mut last_byte = None;
-
-                // skip...
-                if !self.enabled.contains(index) // disabled expressions
-                    || self.disabled.contains(index) // disabled expressions
-                    || new_offset < scratch.offset // input we've already examined
-                    || !scratch.current_set.is_empty()
-                // already started
-                {
-                    continue;
-                }
-
-                // If the keyword starts before the block, push the portion of the keyword that happened
-                // before the current block.
-                scratch.handle_bytes(
-                    new_offset,
-                    None, // NOTE - This works iff ZWA break prefixes.
-                    keyword,
-                    self.handler,
-                    true,
-                );
-                new_offset = scratch.offset;
+        let prefilters = [
+            (&mut self.ac_scratch, &self.database.scratch_map),
+            (
+                &mut self.folded_ac_scratch,
+                &self.database.folded_scratch_map,
+            ),
+        ];
+        for (ac_scratch, scratch_map) in prefilters {
+            for m in ac_scratch.push(bytes) {
+                for &index in scratch_map.get(&m.pattern()).unwrap() {
+                    let new_offset = m.offset();
+                    let scratch = &mut self.scratch[index];
+                    if scratch.profile {
+                        scratch.stats.prefix_hits += 1;
+                    }
 
-                // Push the block we've got here, if there's any left.
-                if new_offset < self.seen + bytes.len() && !self.disabled.contains(index) {
-                    let start = scratch.offset - self.seen;
-                    if start > 0 {
-                        last_byte = Some(bytes[start - 1]);
+                    // skip...
+                    if !self.enabled.contains(index) // disabled expressions
+                        || self.disabled.contains(index) // disabled expressions
+                        || new_offset < scratch.offset // input we've already examined
+                        || !scratch.is_idle()
+                    // already started
+                    {
+                        continue;
                     }
 
-                    scratch.handle_bytes(
-                        new_offset,
-                        last_byte,
-                        &bytes[start..],
-                        self.handler,
-                        true,
-                    );
+                    let started = scratch.start_timer();
+                    let disabled = if new_offset < self.seen {
+                        // The prefix started before this block, so push the part of it
+                        // we kept from earlier blocks and then carry on into this one.
+                        let tail = &self.tail[self.tail.len() - (self.seen - new_offset)..];
+                        match scratch.handle_bytes(
+                            new_offset,
+                            None, // NOTE - This works iff ZWA break prefixes.
+                            tail,
+                            &mut handler,
+                            &mut self.tracer,
+                            true,
+                        ) {
+                            Disabled::No(ContinueMatching::Yes) if scratch.offset == self.seen => {
+                                scratch.handle_bytes(
+                                    self.seen,
+                                    tail.last().copied(),
+                                    bytes,
+                                    &mut handler,
+                                    &mut self.tracer,
+                                    false,
+                                )
+                            }
+                            disabled => disabled,
+                        }
+                    } else {
+                        scratch.handle_bytes(
+                            new_offset,
+                            None, // NOTE - This works iff ZWA break prefixes.
+                            &bytes[new_offset - self.seen..],
+                            &mut handler,
+                            &mut self.tracer,
+                            true,
+                        )
+                    };
+                    scratch.stop_timer(started);
+
+                    match disabled {
+                        Disabled::Yes(ContinueMatching::Yes) => self.disabled.add(index),
+                        Disabled::Yes(ContinueMatching::No)
+                        | Disabled::No(ContinueMatching::No) => return ContinueMatching::No,
+                        Disabled::No(ContinueMatching::Yes) => {}
+                    }
                 }
             }
         }
 
+        // Keep the end of the input for prefixes that continue into the next block.
+        self.tail
+            .extend_from_slice(&bytes[bytes.len().saturating_sub(self.lookback)..]);
+        let excess = self.tail.len().saturating_sub(self.lookback);
+        self.tail.drain(..excess);
+
         // And update the scratch state.
         self.last_byte = Some(bytes[bytes.len() - 1]);
         self.seen += bytes.len();
@@ -738,20 +2112,67 @@ impl<'a> Scratch<'a> {
     }
 }
 
+// Reports a match of a merged expression, unless it's no longer enabled, and disables
+// it if it's done.
+#[allow(clippy::too_many_arguments)]
+fn report_merged(
+    index: usize,
+    offset: usize,
+    last_byte: Option<u8>,
+    byte: Option<u8>,
+    enabled: &ShrinkSet,
+    disabled: &mut GrowSet,
+    scratch: &mut [RegexScratch],
+    handler: &mut dyn MatchHandler,
+) -> ContinueMatching {
+    if !enabled.contains(index) || disabled.contains(index) {
+        return ContinueMatching::Yes;
+    }
+    match scratch[index].report_positions(offset, last_byte, byte, handler) {
+        Some(Disabled::Yes(result)) => {
+            disabled.add(index);
+            result
+        }
+        Some(Disabled::No(result)) => result,
+        None => ContinueMatching::Yes,
+    }
+}
+
 fn is_alphanumeric(byte: u8) -> bool {
     byte.is_ascii_alphanumeric() || byte == b'_'
 }
 
-fn check_assertion(assertion: Assertion, last_byte: Option<u8>, byte: Option<u8>) -> bool {
+fn is_start_of_line(terminator: LineTerminator, last_byte: u8, byte: Option<u8>) -> bool {
+    match terminator {
+        LineTerminator::CROrLF => last_byte == CR || last_byte == NL,
+        LineTerminator::LF => last_byte == NL,
+        LineTerminator::CRLF => last_byte == NL || (last_byte == CR && byte != Some(NL)),
+        LineTerminator::Byte(b) => last_byte == b,
+    }
+}
+
+fn is_end_of_line(terminator: LineTerminator, last_byte: Option<u8>, byte: u8) -> bool {
+    match terminator {
+        LineTerminator::CROrLF => byte == CR || byte == NL,
+        LineTerminator::LF => byte == NL,
+        LineTerminator::CRLF => byte == CR || (byte == NL && last_byte != Some(CR)),
+        LineTerminator::Byte(b) => byte == b,
+    }
+}
+
+pub fn check_assertion(
+    assertion: Assertion,
+    terminator: LineTerminator,
+    last_byte: Option<u8>,
+    byte: Option<u8>,
+) -> bool {
     match (assertion, last_byte, byte) {
         (Assertion::StartOfText, None, _) => true,
         (Assertion::StartOfLine, None, _) => true,
-        (Assertion::StartOfLine, Some(CR), _) => true,
-        (Assertion::StartOfLine, Some(NL), _) => true,
+        (Assertion::StartOfLine, Some(a), b) => is_start_of_line(terminator, a, b),
         (Assertion::EndOfText, _, None) => true,
         (Assertion::EndOfLine, _, None) => true,
-        (Assertion::EndOfLine, _, Some(CR)) => true,
-        (Assertion::EndOfLine, _, Some(NL)) => true,
+        (Assertion::EndOfLine, a, Some(b)) => is_end_of_line(terminator, a, b),
         (Assertion::ByteWordBoundary, None, Some(b)) => is_alphanumeric(b),
         (Assertion::ByteWordBoundary, Some(b), None) => is_alphanumeric(b),
         (Assertion::ByteWordBoundary, Some(a), Some(b)) => {
@@ -773,22 +2194,303 @@ pub struct RegexScratch<'a> {
     regex: &'a CompiledRegex,
     current_set: Queue,
     ready_set: Queue,
+    slots: Slots,
+    loops: Vec<CountedLoop>,
     offset: usize,
+    profile: bool,
+    stats: ExpressionStats,
+
+    // The best match found at the current offset by an approximate expression, and
+    // the best of the matches overlapping it that are waiting to be reported.
+    best: Vec<Capture>,
+    pending: Vec<Capture>,
+    pending_edits: Option<usize>,
+
+    // Where an expression with a lookback finds the starts of its matches.
+    lookback: Option<Box<Lookback<'a>>>,
+
+    // The active positions of an expression on the bit-parallel engine.
+    bits: u128,
+
+    // Whether the expression runs with others in a merged automaton instead.
+    merged: bool,
+}
+
+// The input a lookback expression ran over most recently, and its reversed program.
+struct Lookback<'a> {
+    history: History,
+    scratch: RegexScratch<'a>,
+    // Where the last match reported ended. Threads that reach a match at the same
+    // offset would find the same start, so the match is only reported once.
+    reported: Option<usize>,
+}
+
+impl Lookback<'_> {
+    fn reset(&mut self) {
+        self.history.clear();
+        self.scratch.reset();
+        self.reported = None;
+    }
+
+    // Finds where a match ending at `offset` started, by running the reversed program
+    // back over the input before it. `next` is the byte after the match, if any.
+    fn find_start(&mut self, offset: usize, next: Option<u8>) -> Option<usize> {
+        let mut longest = Longest::default();
+        let mut tracer = TracerSlot::default();
+        let (bytes, exhausted) = self.history.reversed();
+
+        self.scratch.reset();
+        let disabled = self
+            .scratch
+            .handle_bytes(0, next, bytes, &mut longest, &mut tracer, true);
+
+        // With nothing left to look at, threads that are still running see the end. With
+        // no history at all, no thread has started yet, and one starts at the end.
+        if exhausted && (bytes.is_empty() || !self.scratch.is_idle()) {
+            if let Disabled::No(_) = disabled {
+                self.scratch.handle_finish(
+                    bytes.len(),
+                    bytes.last().copied(),
+                    &mut longest,
+                    &mut tracer,
+                );
+            }
+        }
+        longest.length.map(|length| offset - length)
+    }
 }
 
 impl<'a> RegexScratch<'a> {
     pub fn new(regex: &'a CompiledRegex) -> Self {
         Self {
             regex,
-            current_set: Queue::new(regex.program.len(), regex.capture_count),
-            ready_set: Queue::new(regex.program.len(), regex.capture_count),
+            current_set: Queue::new(regex.states()),
+            ready_set: Queue::new(regex.states()),
+            slots: Slots::new(regex.slot_count(), regex.capture_count),
+            loops: regex
+                .repeats
+                .iter()
+                .map(|r| CountedLoop::new(r.capacity()))
+                .collect(),
             offset: 0,
+            profile: false,
+            stats: ExpressionStats::new(regex.id),
+            best: vec![Capture::new(None, None); regex.capture_count],
+            pending: vec![Capture::new(None, None); regex.capture_count],
+            pending_edits: None,
+            lookback: regex.reverse.as_ref().map(|reverse| {
+                Box::new(Lookback {
+                    history: History::new(regex.lookback),
+                    scratch: RegexScratch::new(reverse),
+                    reported: None,
+                })
+            }),
+            bits: 0,
+            merged: false,
+        }
+    }
+
+    fn start_timer(&self) -> Option<Instant> {
+        if self.profile {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    fn stop_timer(&mut self, started: Option<Instant>) {
+        if let Some(started) = started {
+            self.stats.time += started.elapsed();
         }
     }
 
     fn reset(&mut self) {
-        self.current_set.clear();
-        self.ready_set.clear();
+        self.offset = 0;
+        self.bits = 0;
+        self.pending_edits = None;
+        self.current_set.clear(&mut self.slots);
+        self.ready_set.clear(&mut self.slots);
+        for counted_loop in self.loops.iter_mut() {
+            counted_loop.clear(&mut self.slots);
+        }
+        if let Some(lookback) = self.lookback.as_mut() {
+            lookback.reset();
+        }
+    }
+
+    // The earliest offset at which a running thread started. Threads in expressions
+    // with a lookback could have started anywhere in it.
+    fn earliest_start(&self) -> Option<usize> {
+        if let Some(lookback) = self.lookback.as_ref() {
+            return (!self.is_idle()).then(|| lookback.history.start());
+        }
+        if self.regex.glushkov.is_some() {
+            return self
+                .regex
+                .positions
+                .as_ref()
+                .and_then(|x| x.length())
+                .filter(|_| self.bits != 0)
+                .map(|length| self.offset.saturating_sub(length));
+        }
+        self.loops
+            .iter()
+            .filter_map(|x| x.earliest_start(&self.slots))
+            .chain(self.current_set.earliest_start(&self.slots))
+            .min()
+    }
+
+    // Reports that an existence-only expression has matched, as of `offset`.
+    fn report_existence(
+        &mut self,
+        offset: usize,
+        edits: usize,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        if self.profile {
+            self.stats.matches += 1;
+        }
+        handler.on_match_info(&MatchInfo {
+            id: self.regex.id,
+            variant: self.regex.variant,
+            edits,
+            captures: &[Capture::new(None, Some(offset))],
+        })
+    }
+
+    // Checks an assertion between two bytes. Reversed programs see the bytes the other
+    // way around.
+    fn check_assertion(
+        &self,
+        assertion: Assertion,
+        last_byte: Option<u8>,
+        byte: Option<u8>,
+    ) -> bool {
+        let terminator = self.regex.line_terminator;
+        if self.regex.reversed {
+            check_assertion(assertion, terminator, byte, last_byte)
+        } else {
+            check_assertion(assertion, terminator, last_byte, byte)
+        }
+    }
+
+    // Reports a match of an expression with a lookback, ending at `offset`.
+    fn report_lookback(
+        &mut self,
+        offset: usize,
+        next: Option<u8>,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let lookback = self.lookback.as_mut().unwrap();
+        if lookback.reported == Some(offset) {
+            return ContinueMatching::Yes;
+        }
+        lookback.reported = Some(offset);
+        let start = lookback.find_start(offset, next);
+        if self.profile {
+            self.stats.matches += 1;
+        }
+        handler.on_match_info(&MatchInfo {
+            id: self.regex.id,
+            variant: self.regex.variant,
+            edits: 0,
+            captures: &[Capture::new(start, Some(offset))],
+        })
+    }
+
+    // Returns true if there are no threads running, including inside counted loops.
+    fn is_idle(&self) -> bool {
+        self.current_set.is_empty() && self.loops.iter().all(|x| x.is_empty()) && self.bits == 0
+    }
+
+    // Advance the threads inside counted loops past a byte.
+    fn advance_loops(&mut self, offset: usize, byte: u8) {
+        for (repeat, counted_loop) in self.regex.repeats.iter().zip(self.loops.iter_mut()) {
+            if !counted_loop.is_empty() {
+                let matched = self.regex.char_classes[repeat.class].contains(byte);
+                counted_loop.advance(
+                    offset,
+                    matched,
+                    repeat.minimum,
+                    repeat.maximum,
+                    &mut self.slots,
+                );
+            }
+        }
+    }
+
+    // Runs a thread at one of a counted loop's instructions, at the byte at `offset`,
+    // or at the end of the stream if there's no byte.
+    fn step_loop(
+        &mut self,
+        instruction: Instruction,
+        offset: usize,
+        byte: Option<u8>,
+        thread_id: usize,
+        tracer: &mut TracerSlot,
+    ) {
+        match instruction {
+            Instruction::RepeatEnter(r) => {
+                let repeat = self.regex.repeats[r];
+                if byte.is_none() {
+                    // There's no more input to loop over, so only an empty loop can exit.
+                    if repeat.minimum == 0 {
+                        self.push_loop(repeat.exit(), thread_id);
+                    }
+                    return;
+                }
+
+                // Threads in a loop are run by its RepeatNext and RepeatConsume
+                // instructions, in the order in which the loop was first reached. The
+                // loop's own thread may have consumed this byte already, though, so a
+                // thread entering the loop takes its first step through it here, before
+                // it can leave.
+                let slot = self.current_set.slot(thread_id);
+                self.loops[r].enter(offset, slot, &mut self.slots);
+                if repeat.maximum != Some(0)
+                    && byte.is_some_and(|x| self.regex.char_classes[repeat.class].contains(x))
+                {
+                    self.ready_set
+                        .push_sorted(End::Back, repeat.next(), slot, &mut self.slots);
+                }
+                if repeat.minimum == 0 {
+                    self.push_loop(repeat.exit(), thread_id);
+                }
+            }
+            Instruction::RepeatNext(r) => {
+                let repeat = self.regex.repeats[r];
+                if let Some(slot) = self.loops[r].exit(offset, repeat.minimum, &self.slots) {
+                    self.current_set
+                        .push_sorted(End::Front, repeat.exit(), slot, &mut self.slots);
+                }
+                if byte.is_some() {
+                    self.push_loop(repeat.consume(), thread_id);
+                }
+            }
+            Instruction::RepeatConsume(r) => {
+                let repeat = self.regex.repeats[r];
+                if byte.is_some_and(|x| self.regex.char_classes[repeat.class].contains(x)) {
+                    if let Some(slot) = self.loops[r].survivor(offset, repeat.maximum, &self.slots)
+                    {
+                        self.ready_set
+                            .push_sorted(End::Back, repeat.next(), slot, &mut self.slots);
+                    }
+                } else {
+                    trace!(
+                        tracer,
+                        on_thread_death(self.regex.id, offset, thread_id, repeat.consume())
+                    );
+                }
+            }
+            _ => unreachable!("not a counted loop instruction"),
+        }
+    }
+
+    // Queues the thread at `thread_id` at one of a counted loop's instructions, ahead of
+    // the others.
+    fn push_loop(&mut self, pc: usize, thread_id: usize) {
+        self.current_set
+            .push_from_current(End::Front, pc, thread_id, &mut self.slots);
     }
 
     fn handle_finish(
@@ -796,29 +2498,68 @@ impl<'a> RegexScratch<'a> {
         offset: usize,
         last_byte: Option<u8>,
         handler: &mut dyn MatchHandler,
+        tracer: &mut TracerSlot,
     ) {
+        if self.regex.glushkov.is_some() {
+            self.report_bits(offset, last_byte, None, handler);
+            return;
+        }
+
         let id = self.regex.id;
 
         // Figure out if we need to exit early, or push a new starting thread.
-        if self.current_set.is_empty() || !self.regex.anchored_start {
-            self.current_set.push_empty(offset);
+        if self.is_idle() || !self.regex.anchored_start {
+            self.current_set.push_empty(offset, &mut self.slots);
+        }
+
+        if self.regex.max_edits > 0 {
+            self.step_approximate(offset, last_byte, None, handler, tracer);
+            return;
         }
 
         // Execute for as long as there are pending threads.
+        trace!(
+            tracer,
+            on_step(id, offset, None, &self.current_set.threads())
+        );
         while !self.current_set.is_empty() {
             let thread_id = self.current_set.pop();
-            let mut pc = thread_id;
+            let (mut pc, _) = self.regex.locate(thread_id);
             loop {
+                trace!(
+                    tracer,
+                    on_execute(id, offset, thread_id, pc, self.regex.program[pc])
+                );
                 match self.regex.program[pc] {
                     Instruction::Assert(a) => {
-                        if check_assertion(a, last_byte, None) {
+                        let passed = self.check_assertion(a, last_byte, None);
+                        trace!(tracer, on_assertion(id, offset, thread_id, a, passed));
+                        if passed {
                             pc += 1;
                             continue;
                         }
+                        trace!(tracer, on_thread_death(id, offset, thread_id, pc));
+                    }
+                    Instruction::Byte(_)
+                    | Instruction::Literal(_)
+                    | Instruction::FoldedByte(_)
+                    | Instruction::CharClass(_) => {
+                        trace!(tracer, on_thread_death(id, offset, thread_id, pc));
                     }
-                    Instruction::Byte(_) | Instruction::CharClass(_) => {}
                     Instruction::End(index) => {
-                        self.current_set.captures[thread_id][index].end = Some(offset);
+                        self.current_set
+                            .capture_mut(thread_id, index, &mut self.slots)
+                            .end = Some(offset);
+                        trace!(
+                            tracer,
+                            on_capture(
+                                id,
+                                offset,
+                                thread_id,
+                                index,
+                                self.current_set.captures(thread_id, &self.slots)[index]
+                            )
+                        );
                         pc += 1;
                         continue;
                     }
@@ -827,8 +2568,36 @@ impl<'a> RegexScratch<'a> {
                         continue;
                     }
                     Instruction::Match => {
-                        self.current_set.captures[thread_id][0].end = Some(offset);
-                        handler.on_match(id, &self.current_set.captures[thread_id]);
+                        if self.lookback.is_some() {
+                            self.report_lookback(offset, None, handler);
+                            return;
+                        }
+                        if self.regex.capture_count == 0 {
+                            self.report_existence(offset, 0, handler);
+                            return;
+                        }
+                        self.current_set
+                            .capture_mut(thread_id, 0, &mut self.slots)
+                            .end = Some(offset);
+                        trace!(
+                            tracer,
+                            on_capture(
+                                id,
+                                offset,
+                                thread_id,
+                                0,
+                                self.current_set.captures(thread_id, &self.slots)[0]
+                            )
+                        );
+                        if self.profile {
+                            self.stats.matches += 1;
+                        }
+                        handler.on_match_info(&MatchInfo {
+                            id,
+                            variant: self.regex.variant,
+                            edits: 0,
+                            captures: self.current_set.captures(thread_id, &self.slots),
+                        });
                     }
                     Instruction::NoOp => {
                         pc += 1;
@@ -836,16 +2605,37 @@ impl<'a> RegexScratch<'a> {
                     }
                     Instruction::Split(pc_a, pc_b) => {
                         // this is magic, but a bit smelly
-                        self.current_set
-                            .push_from_current(End::Front, pc_b, thread_id);
+                        self.current_set.push_from_current(
+                            End::Front,
+                            pc_b,
+                            thread_id,
+                            &mut self.slots,
+                        );
                         pc = pc_a;
                         continue;
                     }
                     Instruction::Start(index) => {
-                        self.current_set.captures[thread_id][index].start = Some(offset);
+                        self.current_set
+                            .capture_mut(thread_id, index, &mut self.slots)
+                            .start = Some(offset);
+                        trace!(
+                            tracer,
+                            on_capture(
+                                id,
+                                offset,
+                                thread_id,
+                                index,
+                                self.current_set.captures(thread_id, &self.slots)[index]
+                            )
+                        );
                         pc += 1;
                         continue;
                     }
+                    Instruction::RepeatEnter(_)
+                    | Instruction::RepeatNext(_)
+                    | Instruction::RepeatConsume(_) => {
+                        self.step_loop(self.regex.program[pc], offset, None, thread_id, tracer);
+                    }
                 }
                 // different instructions above continue the thread, but if we made it
                 // here, we're done with this thread.
@@ -860,24 +2650,41 @@ impl<'a> RegexScratch<'a> {
         mut last_byte: Option<u8>,
         bytes: &[u8],
         handler: &mut dyn MatchHandler,
+        tracer: &mut TracerSlot,
         mut at_start: bool,
     ) -> Disabled {
+        if self.regex.max_edits > 0 {
+            return self
+                .handle_bytes_approximate(offset, last_byte, bytes, handler, tracer, at_start);
+        }
+
         let id = self.regex.id;
         let mode = self.regex.mode;
         let start_anywhere = self.regex.prefixes.is_empty();
         let mut at = 0usize;
 
+        // Matches can't start before a gap in the input this expression has seen, or
+        // before a prefix it started from anew.
+        if let Some(lookback) = self.lookback.as_mut() {
+            if at_start || lookback.history.end() != offset {
+                lookback.history.restart(offset, last_byte);
+            }
+        }
+        if self.regex.glushkov.is_some() {
+            return self.handle_bytes_bits(offset, last_byte, bytes, handler, at_start);
+        }
+
         let len = bytes.len();
         'LOOP: while at < len {
             // If we don't have any threads, see if we can be finished.
-            if self.current_set.is_empty() {
+            if self.is_idle() {
                 if self.regex.anchored_start && offset > 0 {
                     return Disabled::Yes(ContinueMatching::Yes);
                 }
 
                 if at_start || start_anywhere {
                     at_start = false;
-                    self.current_set.push_empty(offset);
+                    self.current_set.push_empty(offset, &mut self.slots);
                     continue 'LOOP;
                 }
 
@@ -890,43 +2697,104 @@ impl<'a> RegexScratch<'a> {
             // start running at a prefix, but if we're already running, we have to
             // check all of the "internal" start positions as well.
             if !self.regex.anchored_start {
-                self.current_set.push_empty(offset);
+                self.current_set.push_empty(offset, &mut self.slots);
             }
 
             // Execute for as long as there are pending threads.
             let byte = bytes[at];
+            trace!(
+                tracer,
+                on_step(id, offset, Some(byte), &self.current_set.threads())
+            );
             while !self.current_set.is_empty() {
                 let thread_id = self.current_set.pop();
-                let mut pc = thread_id;
+                let (mut pc, matched) = self.regex.locate(thread_id);
                 loop {
+                    trace!(
+                        tracer,
+                        on_execute(id, offset, thread_id, pc, self.regex.program[pc])
+                    );
                     match self.regex.program[pc] {
                         Instruction::Assert(a) => {
-                            if check_assertion(a, last_byte, Some(byte)) {
+                            let passed = self.check_assertion(a, last_byte, Some(byte));
+                            trace!(tracer, on_assertion(id, offset, thread_id, a, passed));
+                            if passed {
                                 pc += 1;
                                 continue;
                             }
+                            trace!(tracer, on_thread_death(id, offset, thread_id, pc));
                         }
                         Instruction::Byte(b) => {
                             if Some(b) == Some(byte) {
                                 self.ready_set.push(
                                     End::Back,
                                     pc + 1,
-                                    &self.current_set.captures[thread_id],
+                                    self.current_set.slot(thread_id),
+                                    &mut self.slots,
+                                );
+                            } else {
+                                trace!(tracer, on_thread_death(id, offset, thread_id, pc));
+                            }
+                        }
+                        Instruction::Literal(l) => {
+                            // Only a thread that starts its step here can be partway
+                            // through the run.
+                            let run = &self.regex.literals[l];
+                            if run.matches(matched, byte) {
+                                let next = if matched + 1 == run.bytes.len() {
+                                    pc + 1
+                                } else {
+                                    run.state + matched
+                                };
+                                self.ready_set.push(
+                                    End::Back,
+                                    next,
+                                    self.current_set.slot(thread_id),
+                                    &mut self.slots,
                                 );
+                            } else {
+                                trace!(tracer, on_thread_death(id, offset, thread_id, pc));
                             }
                         }
                         Instruction::End(index) => {
-                            self.current_set.captures[thread_id][index].end = Some(offset);
+                            self.current_set
+                                .capture_mut(thread_id, index, &mut self.slots)
+                                .end = Some(offset);
+                            trace!(
+                                tracer,
+                                on_capture(
+                                    id,
+                                    offset,
+                                    thread_id,
+                                    index,
+                                    self.current_set.captures(thread_id, &self.slots)[index]
+                                )
+                            );
                             pc += 1;
                             continue;
                         }
+                        Instruction::FoldedByte(b) => {
+                            if byte.to_ascii_lowercase() == b {
+                                self.ready_set.push(
+                                    End::Back,
+                                    pc + 1,
+                                    self.current_set.slot(thread_id),
+                                    &mut self.slots,
+                                );
+                            } else {
+                                trace!(tracer, on_thread_death(id, offset, thread_id, pc));
+                            }
+                        }
                         Instruction::CharClass(cc) => {
                             if self.regex.char_classes[cc].contains(byte) {
                                 self.ready_set.push(
                                     End::Back,
                                     pc + 1,
-                                    &self.current_set.captures[thread_id],
+                                    self.current_set.slot(thread_id),
+                                    &mut self.slots,
                                 );
+                            } else {
+                                trace!(tracer, on_thread_death(id, offset, thread_id, pc));
                             }
                         }
                         Instruction::Jump(target) => {
@@ -934,16 +2802,52 @@ impl<'a> RegexScratch<'a> {
                             continue;
                         }
                         Instruction::Match => {
-                            self.current_set.captures[thread_id][0].end = Some(offset);
+                            if self.lookback.is_some() {
+                                let result = self.report_lookback(offset, Some(byte), handler);
+                                match mode {
+                                    MatchMode::First(_) => return Disabled::Yes(result),
+                                    MatchMode::All(_) if result == ContinueMatching::No => {
+                                        return Disabled::No(ContinueMatching::No)
+                                    }
+                                    MatchMode::All(_) => {}
+                                }
+                                break;
+                            }
+                            if self.regex.capture_count == 0 {
+                                return Disabled::Yes(self.report_existence(offset, 0, handler));
+                            }
+                            self.current_set
+                                .capture_mut(thread_id, 0, &mut self.slots)
+                                .end = Some(offset);
+                            trace!(
+                                tracer,
+                                on_capture(
+                                    id,
+                                    offset,
+                                    thread_id,
+                                    0,
+                                    self.current_set.captures(thread_id, &self.slots)[0]
+                                )
+                            );
+                            if self.profile {
+                                self.stats.matches += 1;
+                            }
                             match mode {
                                 MatchMode::First(_) => {
-                                    return Disabled::Yes(
-                                        handler.on_match(id, &self.current_set.captures[thread_id]),
-                                    );
+                                    return Disabled::Yes(handler.on_match_info(&MatchInfo {
+                                        id,
+                                        variant: self.regex.variant,
+                                        edits: 0,
+                                        captures: self.current_set.captures(thread_id, &self.slots),
+                                    }));
                                 }
                                 MatchMode::All(_) => {
-                                    if handler.on_match(id, &self.current_set.captures[thread_id])
-                                        == ContinueMatching::No
+                                    if handler.on_match_info(&MatchInfo {
+                                        id,
+                                        variant: self.regex.variant,
+                                        edits: 0,
+                                        captures: self.current_set.captures(thread_id, &self.slots),
+                                    }) == ContinueMatching::No
                                     {
                                         return Disabled::No(ContinueMatching::No);
                                     }
@@ -955,29 +2859,462 @@ impl<'a> RegexScratch<'a> {
                             continue;
                         }
                         Instruction::Split(pc_a, pc_b) => {
-                            self.current_set
-                                .push_from_current(End::Front, pc_b, thread_id); // this is magic, if a bit smelly
+                            self.current_set.push_from_current(
+                                End::Front,
+                                pc_b,
+                                thread_id,
+                                &mut self.slots,
+                            ); // this is magic, if a bit smelly
                             pc = pc_a;
                             continue;
                         }
                         Instruction::Start(index) => {
-                            self.current_set.captures[thread_id][index].start = Some(offset);
+                            self.current_set
+                                .capture_mut(thread_id, index, &mut self.slots)
+                                .start = Some(offset);
+                            trace!(
+                                tracer,
+                                on_capture(
+                                    id,
+                                    offset,
+                                    thread_id,
+                                    index,
+                                    self.current_set.captures(thread_id, &self.slots)[index]
+                                )
+                            );
                             pc += 1;
                             continue;
                         }
+                        Instruction::RepeatEnter(_)
+                        | Instruction::RepeatNext(_)
+                        | Instruction::RepeatConsume(_) => {
+                            self.step_loop(
+                                self.regex.program[pc],
+                                offset,
+                                Some(byte),
+                                thread_id,
+                                tracer,
+                            );
+                        }
                     }
                     // different instructions above continue the thread, but if we made it
                     // here, we're done with this thread.
                     break;
                 }
             }
+            self.advance_loops(offset, byte);
+            if let Some(lookback) = self.lookback.as_mut() {
+                lookback.history.push(byte);
+            }
             last_byte = Some(byte);
             offset += 1;
             at += 1;
             self.offset = offset;
             swap(&mut self.current_set, &mut self.ready_set);
-            self.ready_set.clear();
+            self.ready_set.clear(&mut self.slots);
+
+            if self.profile {
+                self.stats.bytes_executed += 1;
+                self.stats.peak_threads = max(self.stats.peak_threads, self.current_set.len());
+            }
         }
         Disabled::No(ContinueMatching::Yes)
     }
+
+    // As handle_bytes, for expressions on the bit-parallel engine.
+    fn handle_bytes_bits(
+        &mut self,
+        mut offset: usize,
+        mut last_byte: Option<u8>,
+        bytes: &[u8],
+        handler: &mut dyn MatchHandler,
+        mut at_start: bool,
+    ) -> Disabled {
+        let glushkov = self.regex.glushkov.as_ref().unwrap();
+        let positions = self.regex.positions.as_ref().unwrap();
+        let start_anywhere = self.regex.prefixes.is_empty();
+
+        for &byte in bytes {
+            // As with threads, a match can start at every byte while one is running, or
+            // where a prefix was found or anywhere at all if there are none.
+            let start = if self.bits == 0 {
+                if self.regex.anchored_start && offset > 0 {
+                    return Disabled::Yes(ContinueMatching::Yes);
+                }
+                if !at_start && !start_anywhere {
+                    break;
+                }
+                at_start = false;
+                true
+            } else {
+                !self.regex.anchored_start
+            };
+
+            if let Some(disabled) = self.report_bits(offset, last_byte, Some(byte), handler) {
+                return disabled;
+            }
+
+            let start = start
+                && positions
+                    .leading()
+                    .iter()
+                    .all(|&a| self.check_assertion(a, last_byte, Some(byte)));
+            self.bits = glushkov.step(self.bits, byte, start);
+            if let Some(lookback) = self.lookback.as_mut() {
+                lookback.history.push(byte);
+            }
+            last_byte = Some(byte);
+            offset += 1;
+            self.offset = offset;
+
+            if self.profile {
+                self.stats.bytes_executed += 1;
+                self.stats.peak_threads =
+                    max(self.stats.peak_threads, self.bits.count_ones() as usize);
+            }
+        }
+        Disabled::No(ContinueMatching::Yes)
+    }
+
+    // Reports a match ending at `offset` on the bit-parallel engine, if there is one,
+    // returning whether the expression is done as handle_bytes would.
+    fn report_bits(
+        &mut self,
+        offset: usize,
+        last_byte: Option<u8>,
+        byte: Option<u8>,
+        handler: &mut dyn MatchHandler,
+    ) -> Option<Disabled> {
+        if !self.regex.glushkov.as_ref()?.accepts(self.bits) {
+            return None;
+        }
+        self.report_positions(offset, last_byte, byte, handler)
+    }
+
+    // Reports a match of an expression's positions ending at `offset`, if its trailing
+    // assertions hold there.
+    fn report_positions(
+        &mut self,
+        offset: usize,
+        last_byte: Option<u8>,
+        byte: Option<u8>,
+        handler: &mut dyn MatchHandler,
+    ) -> Option<Disabled> {
+        let positions = self.regex.positions.as_ref()?;
+        if !positions
+            .trailing()
+            .iter()
+            .all(|&a| self.check_assertion(a, last_byte, byte))
+        {
+            return None;
+        }
+
+        let result = if self.lookback.is_some() {
+            self.report_lookback(offset, byte, handler)
+        } else if self.regex.capture_count == 0 {
+            return Some(Disabled::Yes(self.report_existence(offset, 0, handler)));
+        } else {
+            if self.profile {
+                self.stats.matches += 1;
+            }
+            let start = positions.length().map(|length| offset - length);
+            handler.on_match_info(&MatchInfo {
+                id: self.regex.id,
+                variant: self.regex.variant,
+                edits: 0,
+                captures: &[Capture::new(start, Some(offset))],
+            })
+        };
+        match self.regex.mode {
+            MatchMode::First(_) => Some(Disabled::Yes(result)),
+            MatchMode::All(_) if result == ContinueMatching::No => {
+                Some(Disabled::No(ContinueMatching::No))
+            }
+            MatchMode::All(_) => None,
+        }
+    }
+
+    // As handle_bytes, for approximate expressions.
+    fn handle_bytes_approximate(
+        &mut self,
+        mut offset: usize,
+        mut last_byte: Option<u8>,
+        bytes: &[u8],
+        handler: &mut dyn MatchHandler,
+        tracer: &mut TracerSlot,
+        mut at_start: bool,
+    ) -> Disabled {
+        let start_anywhere = self.regex.prefixes.is_empty();
+
+        for &byte in bytes {
+            if self.is_idle() {
+                if self.regex.anchored_start && offset > 0 {
+                    return Disabled::Yes(ContinueMatching::Yes);
+                }
+                if !at_start && !start_anywhere {
+                    break;
+                }
+                at_start = false;
+                self.current_set.push_empty(offset, &mut self.slots);
+            } else if !self.regex.anchored_start {
+                self.current_set.push_empty(offset, &mut self.slots);
+            }
+
+            if let Some(disabled) =
+                self.step_approximate(offset, last_byte, Some(byte), handler, tracer)
+            {
+                return disabled;
+            }
+
+            last_byte = Some(byte);
+            offset += 1;
+            self.offset = offset;
+            swap(&mut self.current_set, &mut self.ready_set);
+            self.ready_set.clear(&mut self.slots);
+
+            if self.profile {
+                self.stats.bytes_executed += 1;
+                self.stats.peak_threads = max(self.stats.peak_threads, self.current_set.len());
+            }
+        }
+        Disabled::No(ContinueMatching::Yes)
+    }
+
+    // Runs the threads of an approximate expression over a byte, or over the end of
+    // input when `byte` is None. Each thread's id is its program counter plus the
+    // program size times the number of edits it has made, so threads that have made
+    // different edits to get to the same place are kept apart. A thread at a byte or
+    // class can make an edit by consuming a byte it doesn't match (a substitution),
+    // by consuming a byte without moving on (an insertion), or by moving on without
+    // consuming a byte (a deletion).
+    fn step_approximate(
+        &mut self,
+        offset: usize,
+        last_byte: Option<u8>,
+        byte: Option<u8>,
+        handler: &mut dyn MatchHandler,
+        tracer: &mut TracerSlot,
+    ) -> Option<Disabled> {
+        let id = self.regex.id;
+        let size = self.regex.program.len();
+        let max_edits = self.regex.max_edits;
+        let mut best: Option<usize> = None;
+
+        trace!(
+            tracer,
+            on_step(id, offset, byte, &self.current_set.threads())
+        );
+        while !self.current_set.is_empty() {
+            let thread_id = self.current_set.pop();
+            let edits = thread_id / size;
+            let mut pc = thread_id % size;
+            loop {
+                trace!(
+                    tracer,
+                    on_execute(id, offset, thread_id, pc, self.regex.program[pc])
+                );
+                match self.regex.program[pc] {
+                    Instruction::Assert(a) => {
+                        let passed =
+                            check_assertion(a, self.regex.line_terminator, last_byte, byte);
+                        trace!(tracer, on_assertion(id, offset, thread_id, a, passed));
+                        if passed {
+                            pc += 1;
+                            continue;
+                        }
+                        trace!(tracer, on_thread_death(id, offset, thread_id, pc));
+                    }
+                    Instruction::Byte(_)
+                    | Instruction::FoldedByte(_)
+                    | Instruction::CharClass(_) => {
+                        let slot = self.current_set.slot(thread_id);
+                        let state = edits * size + pc;
+                        if let Some(byte) = byte {
+                            let matched = match self.regex.program[pc] {
+                                Instruction::Byte(b) => byte == b,
+                                Instruction::FoldedByte(b) => byte.to_ascii_lowercase() == b,
+                                Instruction::CharClass(cc) => {
+                                    self.regex.char_classes[cc].contains(byte)
+                                }
+                                _ => unreachable!(),
+                            };
+                            if matched {
+                                self.ready_set
+                                    .push(End::Back, state + 1, slot, &mut self.slots);
+                            }
+                            if edits < max_edits {
+                                self.ready_set.push(
+                                    End::Back,
+                                    state + size + 1,
+                                    slot,
+                                    &mut self.slots,
+                                );
+                                self.ready_set
+                                    .push(End::Back, state + size, slot, &mut self.slots);
+                            }
+                        }
+                        if edits < max_edits {
+                            self.current_set.push_from_current(
+                                End::Front,
+                                state + size + 1,
+                                thread_id,
+                                &mut self.slots,
+                            );
+                        }
+                    }
+                    Instruction::End(index) => {
+                        self.current_set
+                            .capture_mut(thread_id, index, &mut self.slots)
+                            .end = Some(offset);
+                        trace!(
+                            tracer,
+                            on_capture(
+                                id,
+                                offset,
+                                thread_id,
+                                index,
+                                self.current_set.captures(thread_id, &self.slots)[index]
+                            )
+                        );
+                        pc += 1;
+                        continue;
+                    }
+                    Instruction::Jump(target) => {
+                        pc = target;
+                        continue;
+                    }
+                    Instruction::Match => {
+                        // Keep the match with the fewest edits, and then the longest.
+                        // They all end here, so the longest is the one that starts first.
+                        let start = self.slots.start(self.current_set.slot(thread_id));
+                        let better = match best {
+                            None => true,
+                            Some(best_edits) => {
+                                edits < best_edits
+                                    || (edits == best_edits && start < queue::start(&self.best))
+                            }
+                        };
+                        if better {
+                            self.best
+                                .copy_from_slice(self.current_set.captures(thread_id, &self.slots));
+                            if let Some(capture) = self.best.first_mut() {
+                                capture.end = Some(offset);
+                                trace!(tracer, on_capture(id, offset, thread_id, 0, *capture));
+                            }
+                            best = Some(edits);
+                        }
+                    }
+                    Instruction::NoOp => {
+                        pc += 1;
+                        continue;
+                    }
+                    Instruction::Split(pc_a, pc_b) => {
+                        self.current_set.push_from_current(
+                            End::Front,
+                            edits * size + pc_b,
+                            thread_id,
+                            &mut self.slots,
+                        );
+                        pc = pc_a;
+                        continue;
+                    }
+                    Instruction::Start(index) => {
+                        self.current_set
+                            .capture_mut(thread_id, index, &mut self.slots)
+                            .start = Some(offset);
+                        trace!(
+                            tracer,
+                            on_capture(
+                                id,
+                                offset,
+                                thread_id,
+                                index,
+                                self.current_set.captures(thread_id, &self.slots)[index]
+                            )
+                        );
+                        pc += 1;
+                        continue;
+                    }
+                    Instruction::RepeatEnter(_)
+                    | Instruction::RepeatNext(_)
+                    | Instruction::RepeatConsume(_) => {
+                        unreachable!("approximate expressions don't have counted loops")
+                    }
+                    Instruction::Literal(_) => {
+                        unreachable!("approximate expressions don't have literal runs")
+                    }
+                }
+                break;
+            }
+        }
+
+        if let Some(edits) = best {
+            if self.regex.capture_count == 0 {
+                return Some(Disabled::Yes(self.report_existence(offset, edits, handler)));
+            }
+
+            // Matches that overlap are the same occurrence, found with different edits,
+            // so only the best of them is reported: the one with the fewest edits, and
+            // then the longest.
+            let start = queue::start(&self.best).unwrap();
+            let pending_start = queue::start(&self.pending);
+            let pending_end = self.pending[0].end;
+            let better = match self.pending_edits {
+                Some(pending_edits) if start < pending_end.unwrap() => {
+                    let length = offset - start;
+                    let pending_length = pending_end.unwrap() - pending_start.unwrap();
+                    edits < pending_edits || (edits == pending_edits && length > pending_length)
+                }
+                Some(_) => {
+                    if let Some(disabled) = self.report_approximate(id, handler) {
+                        return Some(disabled);
+                    }
+                    true
+                }
+                None => true,
+            };
+            if better {
+                self.pending.copy_from_slice(&self.best);
+                self.pending_edits = Some(edits);
+            }
+        }
+
+        // Report the pending match once no thread that's still running could find a
+        // match overlapping it.
+        self.pending_edits?;
+        let end = self.pending[0].end.unwrap();
+        if self
+            .ready_set
+            .earliest_start(&self.slots)
+            .is_some_and(|x| x < end)
+        {
+            return None;
+        }
+        self.report_approximate(id, handler)
+    }
+
+    // Reports the pending match of an approximate expression.
+    fn report_approximate(
+        &mut self,
+        id: usize,
+        handler: &mut dyn MatchHandler,
+    ) -> Option<Disabled> {
+        let edits = self.pending_edits.take()?;
+        if self.profile {
+            self.stats.matches += 1;
+        }
+        let result = handler.on_match_info(&MatchInfo {
+            id,
+            variant: self.regex.variant,
+            edits,
+            captures: &self.pending,
+        });
+        match self.regex.mode {
+            MatchMode::First(_) => Some(Disabled::Yes(result)),
+            MatchMode::All(_) if result == ContinueMatching::No => {
+                Some(Disabled::No(ContinueMatching::No))
+            }
+            MatchMode::All(_) => None,
+        }
+    }
 }
@@ -0,0 +1,312 @@
+// Decoders that sit in front of a scratch space, so that encoded input can be
+// matched as if it had been decoded first.
+//
+// Decoders work a byte at a time and hold back at most a few bytes of partial input
+// between pushes, so decoding never needs to buffer the stream. Each decoded byte is
+// passed on along with the offset in the original stream at which its encoding
+// begins; it ends wherever the input byte being decoded ends. That's enough for the
+// scratch space to map the offsets of matches back to the original stream.
+use crate::offsets::OffsetMap;
+use std::mem::swap;
+
+/// Decodes a stream one byte at a time. See `Scratch::with_decoder`.
+pub trait Decoder {
+    /// Decodes one byte of input that begins at `start` in the original stream,
+    /// appending any bytes it completes to `output` along with where they begin.
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>);
+
+    /// Called at the end of the input to flush anything held back.
+    fn finish(&mut self, _output: &mut Vec<(u8, usize)>) {
+        /* do nothing by default */
+    }
+
+    /// Forgets any partial input, ready for a new stream.
+    fn reset(&mut self);
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes base64, in either the standard or the URL-safe alphabet. Whitespace is
+/// skipped, padding ends a group, and anything else outside the alphabet discards the
+/// partial group it interrupts.
+#[derive(Default)]
+pub struct Base64Decoder {
+    bits: u32,
+    count: u32,
+    start: usize,
+}
+
+impl Base64Decoder {
+    pub fn new() -> Self {
+        Self {
+            bits: 0,
+            count: 0,
+            start: 0,
+        }
+    }
+
+    fn value(byte: u8) -> Option<u32> {
+        match byte {
+            b'A'..=b'Z' => Some((byte - b'A') as u32),
+            b'a'..=b'z' => Some((byte - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((byte - b'0') as u32 + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        }
+    }
+}
+
+impl Decoder for Base64Decoder {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        let value = match Self::value(byte) {
+            Some(value) => value,
+            None if byte.is_ascii_whitespace() => return,
+            None => return self.reset(),
+        };
+
+        // A decoded byte starts in the character holding its first bit.
+        if self.count == 0 {
+            self.start = start;
+        }
+        self.bits = (self.bits << 6) | value;
+        self.count += 6;
+        if self.count >= 8 {
+            self.count -= 8;
+            output.push(((self.bits >> self.count) as u8, self.start));
+            self.bits &= (1 << self.count) - 1;
+            self.start = start;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// Decodes pairs of hexadecimal digits, in either case. Anything that isn't a digit
+/// is skipped, discarding a digit left waiting for its pair.
+#[derive(Default)]
+pub struct HexDecoder {
+    high: Option<(u8, usize)>,
+}
+
+impl HexDecoder {
+    pub fn new() -> Self {
+        Self { high: None }
+    }
+}
+
+impl Decoder for HexDecoder {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        match (hex_value(byte), self.high.take()) {
+            (Some(low), Some((high, start))) => output.push(((high << 4) | low, start)),
+            (Some(high), None) => self.high = Some((high, start)),
+            (None, _) => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.high = None;
+    }
+}
+
+/// Decodes percent-encoding, as in URLs. Escapes that aren't followed by two
+/// hexadecimal digits are passed through as they are.
+#[derive(Default)]
+pub struct PercentDecoder {
+    // The escape seen so far and where each byte of it started.
+    pending: Vec<(u8, usize)>,
+}
+
+impl PercentDecoder {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::with_capacity(2),
+        }
+    }
+}
+
+impl Decoder for PercentDecoder {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        match (self.pending.len(), hex_value(byte)) {
+            (0, _) if byte == b'%' => self.pending.push((byte, start)),
+            (0, _) => output.push((byte, start)),
+            (1, Some(_)) => self.pending.push((byte, start)),
+            (_, Some(low)) => {
+                let high = hex_value(self.pending[1].0).unwrap();
+                output.push(((high << 4) | low, self.pending[0].1));
+                self.pending.clear();
+            }
+            (_, None) => {
+                // Not an escape after all.
+                output.append(&mut self.pending);
+                self.decode(byte, start, output);
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<(u8, usize)>) {
+        output.append(&mut self.pending);
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Chunked {
+    Size(usize),      // Reading the size of the next chunk.
+    Extension(usize), // Skipping the rest of the size line.
+    Data(usize),      // Reading a chunk, with this many bytes left.
+    DataEnd,          // Skipping the line ending after a chunk.
+    Trailer(usize),   // Skipping trailers after the last chunk, this far into a line.
+    Done,             // The body is over.
+}
+
+/// Decodes an HTTP body sent with chunked transfer encoding, passing on the data in
+/// its chunks. Chunk extensions and trailers are skipped, and anything after the
+/// body is ignored.
+pub struct ChunkedDecoder {
+    state: Chunked,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: Chunked::Size(0),
+        }
+    }
+}
+
+// Where a size line of the given size leads: the chunk, or trailers after the last.
+fn chunk(size: usize) -> Chunked {
+    match size {
+        0 => Chunked::Trailer(0),
+        size => Chunked::Data(size),
+    }
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ChunkedDecoder {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        self.state = match (self.state, byte) {
+            (Chunked::Size(size), _) if hex_value(byte).is_some() => {
+                Chunked::Size(size.saturating_mul(16) + hex_value(byte).unwrap() as usize)
+            }
+            (Chunked::Size(size) | Chunked::Extension(size), b'\n') => chunk(size),
+            (Chunked::Size(size), b'\r') => Chunked::Size(size),
+            (Chunked::Size(size) | Chunked::Extension(size), _) => Chunked::Extension(size),
+            (Chunked::Data(0) | Chunked::DataEnd, b'\n') => Chunked::Size(0),
+            (Chunked::Data(0) | Chunked::DataEnd, _) => Chunked::DataEnd,
+            (Chunked::Data(left), _) => {
+                output.push((byte, start));
+                Chunked::Data(left - 1)
+            }
+            (Chunked::Trailer(0), b'\n') => Chunked::Done,
+            (Chunked::Trailer(_), b'\n') => Chunked::Trailer(0),
+            (Chunked::Trailer(count), b'\r') => Chunked::Trailer(count),
+            (Chunked::Trailer(count), _) => Chunked::Trailer(count + 1),
+            (Chunked::Done, _) => Chunked::Done,
+        };
+    }
+
+    fn reset(&mut self) {
+        self.state = Chunked::Size(0);
+    }
+}
+
+// A series of decoders, each decoding the output of the one before.
+pub struct DecoderChain<'a> {
+    decoders: Vec<Box<dyn Decoder + 'a>>,
+    input: Vec<(u8, usize)>,
+    output: Vec<(u8, usize)>,
+
+    // Where the last byte of input ended in the original stream.
+    end: usize,
+}
+
+impl<'a> DecoderChain<'a> {
+    pub fn new() -> Self {
+        Self {
+            decoders: Vec::new(),
+            input: Vec::new(),
+            output: Vec::new(),
+            end: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    pub fn push(&mut self, decoder: Box<dyn Decoder + 'a>) {
+        self.decoders.push(decoder);
+    }
+
+    pub fn reset(&mut self) {
+        for decoder in self.decoders.iter_mut() {
+            decoder.reset();
+        }
+        self.end = 0;
+    }
+
+    // Decodes a block of input, appending the decoded bytes to `decoded` and their
+    // spans to `offsets`. Input bytes came from the given spans of the original
+    // stream, or else directly follow the input before them.
+    pub fn decode(
+        &mut self,
+        bytes: &[u8],
+        spans: Option<&OffsetMap>,
+        decoded: &mut Vec<u8>,
+        offsets: &mut OffsetMap,
+    ) {
+        for (index, &byte) in bytes.iter().enumerate() {
+            let (start, end) = spans.map_or((self.end, self.end + 1), |x| x.span(index).unwrap());
+            self.input.clear();
+            self.input.push((byte, start));
+            self.run(0, decoded, offsets, end);
+            self.end = end;
+        }
+    }
+
+    // Flushes every decoder in turn through the ones after it.
+    pub fn finish(&mut self, decoded: &mut Vec<u8>, offsets: &mut OffsetMap) {
+        for index in 0..self.decoders.len() {
+            self.input.clear();
+            self.decoders[index].finish(&mut self.input);
+            self.run(index + 1, decoded, offsets, self.end);
+        }
+    }
+
+    // Runs the bytes in `input` through the decoders from `first` on. Everything
+    // decoded ends at `end`.
+    fn run(&mut self, first: usize, decoded: &mut Vec<u8>, offsets: &mut OffsetMap, end: usize) {
+        for decoder in self.decoders[first..].iter_mut() {
+            self.output.clear();
+            for &(byte, start) in self.input.iter() {
+                decoder.decode(byte, start, &mut self.output);
+            }
+            swap(&mut self.input, &mut self.output);
+        }
+
+        for &(byte, start) in self.input.iter() {
+            decoded.push(byte);
+            offsets.push(start, end);
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// Position automata for expressions, and a bit-parallel matcher for small ones.
+//
+// The Glushkov automaton of an expression has a state for each byte or class in it,
+// its positions, and entering a position always means consuming a byte that the
+// position matches. With no more than 128 positions, the set of positions a match
+// could be at fits in a word, and a step over a byte takes a few word operations:
+// follow each active position to the positions that can come next, add the first
+// positions if a match can start here, and keep only those that match the byte.
+// Positions followed by nothing but the next position, as in literals, are followed
+// all at once with a shift.
+//
+// Only expressions that can't match the empty string, and that have no assertions
+// except at either end, are built this way. Leading assertions are checked where a
+// match starts, and trailing ones where it ends, just as the VM would check them.
+use crate::Assertion;
+use regex_syntax::hir::{
+    Anchor, Class, Hir, HirKind, Literal, RepetitionKind, RepetitionRange, WordBoundary,
+};
+use std::mem::size_of;
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not, Shl};
+
+// The most positions an expression can have, and the most parts of it that are
+// built on the way. Repetitions are expanded, so this also keeps large repetition
+// counts from taking too long to build, even of parts without positions.
+const MAX_POSITIONS: usize = 1024;
+const MAX_STEPS: usize = 16 * MAX_POSITIONS;
+
+// The most positions the bit-parallel matcher can have.
+const MAX_WORD_POSITIONS: usize = 128;
+
+// The positions of an expression, and the positions that can follow each of them.
+pub struct Positions {
+    // The bytes each position matches.
+    bytes: Vec<[u64; 4]>,
+    follow: Vec<Vec<u32>>,
+    first: Vec<u32>,
+    last: Vec<u32>,
+    // How long every match is, if they're all the same length.
+    length: Option<usize>,
+    leading: Vec<Assertion>,
+    trailing: Vec<Assertion>,
+}
+
+impl Positions {
+    // Builds the automaton for an expression over bytes, if it can be built.
+    pub fn new(hir: &Hir) -> Option<Self> {
+        let items: Vec<&Hir> = match hir.kind() {
+            HirKind::Concat(items) => items.iter().collect(),
+            _ => vec![hir],
+        };
+        let leading: Vec<Assertion> = items.iter().map_while(|x| assertion(x)).collect();
+        let trailing: Vec<Assertion> = items[leading.len()..]
+            .iter()
+            .rev()
+            .map_while(|x| assertion(x))
+            .collect();
+        let body = &items[leading.len()..items.len() - trailing.len()];
+
+        let mut builder = Builder {
+            bytes: Vec::new(),
+            follow: Vec::new(),
+            steps: 0,
+        };
+        let mut fragment = Fragment::empty();
+        for item in body {
+            let next = builder.build(item)?;
+            fragment = builder.concat(fragment, next);
+        }
+        if fragment.nullable {
+            return None;
+        }
+
+        for follow in builder.follow.iter_mut() {
+            follow.sort_unstable();
+            follow.dedup();
+        }
+        Some(Self {
+            bytes: builder.bytes,
+            follow: builder.follow,
+            first: sorted(fragment.first),
+            last: sorted(fragment.last),
+            length: fragment.max.filter(|&x| x == fragment.min),
+            leading,
+            trailing: trailing.into_iter().rev().collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.follow.len()
+    }
+
+    // The bytes a position matches, as a bit for each byte.
+    pub fn bytes(&self, position: u32) -> &[u64; 4] {
+        &self.bytes[position as usize]
+    }
+
+    // Whether a position matches a byte.
+    pub fn matches(&self, position: u32, byte: u8) -> bool {
+        self.bytes[position as usize][byte as usize / 64] & 1 << (byte % 64) != 0
+    }
+
+    pub fn follow(&self, position: u32) -> &[u32] {
+        &self.follow[position as usize]
+    }
+
+    // The positions a match can start at.
+    pub fn first(&self) -> &[u32] {
+        &self.first
+    }
+
+    // The positions a match can end at.
+    pub fn last(&self) -> &[u32] {
+        &self.last
+    }
+
+    pub fn length(&self) -> Option<usize> {
+        self.length
+    }
+
+    // The assertions checked where a match starts.
+    pub fn leading(&self) -> &[Assertion] {
+        &self.leading
+    }
+
+    // The assertions checked where a match ends.
+    pub fn trailing(&self) -> &[Assertion] {
+        &self.trailing
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.bytes.capacity() * size_of::<[u64; 4]>()
+            + self.follow.capacity() * size_of::<Vec<u32>>()
+            + self
+                .follow
+                .iter()
+                .map(|x| x.capacity() * size_of::<u32>())
+                .sum::<usize>()
+            + (self.first.capacity() + self.last.capacity()) * size_of::<u32>()
+            + (self.leading.capacity() + self.trailing.capacity()) * size_of::<Assertion>()
+    }
+}
+
+fn sorted(mut positions: Vec<u32>) -> Vec<u32> {
+    positions.sort_unstable();
+    positions.dedup();
+    positions
+}
+
+trait Word:
+    Copy
+    + Eq
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitOrAssign
+    + Not<Output = Self>
+    + Shl<u32, Output = Self>
+{
+    const ZERO: Self;
+
+    fn from_bits(bits: u128) -> Self;
+    fn into_bits(self) -> u128;
+    fn trailing_zeros(self) -> u32;
+}
+
+macro_rules! word {
+    ($t:ty) => {
+        impl Word for $t {
+            const ZERO: Self = 0;
+
+            fn from_bits(bits: u128) -> Self {
+                bits as $t
+            }
+
+            fn into_bits(self) -> u128 {
+                self as u128
+            }
+
+            fn trailing_zeros(self) -> u32 {
+                <$t>::trailing_zeros(self)
+            }
+        }
+    };
+}
+
+word!(u64);
+word!(u128);
+
+fn word_of(positions: &[u32]) -> u128 {
+    positions.iter().fold(0, |word, &x| word | 1 << x)
+}
+
+struct Automaton<W> {
+    // The positions that match each byte.
+    masks: Vec<W>,
+    // The positions that can follow each position.
+    follow: Vec<W>,
+    // The positions followed by nothing but the next one.
+    linear: W,
+    first: W,
+    last: W,
+}
+
+impl<W: Word> Automaton<W> {
+    fn new(positions: &Positions) -> Self {
+        let follow: Vec<u128> = positions.follow.iter().map(|x| word_of(x)).collect();
+        let linear = follow
+            .iter()
+            .enumerate()
+            .filter(|&(position, &follow)| {
+                position + 1 < MAX_WORD_POSITIONS && follow == 1 << (position + 1)
+            })
+            .fold(0, |linear, (position, _)| linear | 1 << position);
+        let masks = (0..=255u8).map(|byte| {
+            (0..positions.len() as u32)
+                .filter(|&position| positions.matches(position, byte))
+                .fold(0, |mask, position| mask | 1 << position)
+        });
+
+        Self {
+            masks: masks.map(W::from_bits).collect(),
+            follow: follow.into_iter().map(W::from_bits).collect(),
+            linear: W::from_bits(linear),
+            first: W::from_bits(word_of(&positions.first)),
+            last: W::from_bits(word_of(&positions.last)),
+        }
+    }
+
+    fn step(&self, state: W, byte: u8, start: bool) -> W {
+        let mut next = (state & self.linear) << 1;
+        let mut rest = state & !self.linear;
+        while rest != W::ZERO {
+            let position = rest.trailing_zeros();
+            next |= self.follow[position as usize];
+            rest = rest & !(W::from_bits(1) << position);
+        }
+        if start {
+            next |= self.first;
+        }
+        next & self.masks[byte as usize]
+    }
+
+    fn accepts(&self, state: W) -> bool {
+        state & self.last != W::ZERO
+    }
+
+    fn heap_size(&self) -> usize {
+        (self.masks.capacity() + self.follow.capacity()) * size_of::<W>()
+    }
+}
+
+// Automata that fit in 64 positions step with narrower words.
+enum Engine {
+    Narrow(Automaton<u64>),
+    Wide(Automaton<u128>),
+}
+
+pub struct Glushkov {
+    engine: Engine,
+    positions: usize,
+}
+
+impl Glushkov {
+    // Builds the bit-parallel matcher for an expression's positions, if there are few
+    // enough of them.
+    pub fn new(positions: &Positions) -> Option<Self> {
+        let engine = match positions.len() {
+            0..=64 => Engine::Narrow(Automaton::new(positions)),
+            65..=MAX_WORD_POSITIONS => Engine::Wide(Automaton::new(positions)),
+            _ => return None,
+        };
+        Some(Self {
+            engine,
+            positions: positions.len(),
+        })
+    }
+
+    pub fn positions(&self) -> usize {
+        self.positions
+    }
+
+    // Moves the active positions in `state` past a byte. If `start` is set, a match
+    // can also start with the byte.
+    pub fn step(&self, state: u128, byte: u8, start: bool) -> u128 {
+        match &self.engine {
+            Engine::Narrow(automaton) => automaton
+                .step(u64::from_bits(state), byte, start)
+                .into_bits(),
+            Engine::Wide(automaton) => automaton.step(state, byte, start),
+        }
+    }
+
+    // Whether a match ends with the positions in `state`.
+    pub fn accepts(&self, state: u128) -> bool {
+        match &self.engine {
+            Engine::Narrow(automaton) => automaton.accepts(u64::from_bits(state)),
+            Engine::Wide(automaton) => automaton.accepts(state),
+        }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        match &self.engine {
+            Engine::Narrow(automaton) => automaton.heap_size(),
+            Engine::Wide(automaton) => automaton.heap_size(),
+        }
+    }
+}
+
+// The assertion an expression is, if it is one.
+fn assertion(hir: &Hir) -> Option<Assertion> {
+    match hir.kind() {
+        HirKind::Anchor(Anchor::StartText) => Some(Assertion::StartOfText),
+        HirKind::Anchor(Anchor::EndText) => Some(Assertion::EndOfText),
+        HirKind::Anchor(Anchor::StartLine) => Some(Assertion::StartOfLine),
+        HirKind::Anchor(Anchor::EndLine) => Some(Assertion::EndOfLine),
+        HirKind::WordBoundary(WordBoundary::Ascii) => Some(Assertion::ByteWordBoundary),
+        HirKind::WordBoundary(WordBoundary::AsciiNegate) => Some(Assertion::ByteNonwordBoundary),
+        _ => None,
+    }
+}
+
+// Part of an expression, as the positions it can start and end at.
+struct Fragment {
+    first: Vec<u32>,
+    last: Vec<u32>,
+    nullable: bool,
+    min: usize,
+    max: Option<usize>,
+}
+
+impl Fragment {
+    fn empty() -> Self {
+        Self {
+            first: vec![],
+            last: vec![],
+            nullable: true,
+            min: 0,
+            max: Some(0),
+        }
+    }
+}
+
+struct Builder {
+    bytes: Vec<[u64; 4]>,
+    follow: Vec<Vec<u32>>,
+    steps: usize,
+}
+
+impl Builder {
+    fn position(&mut self, ranges: impl Iterator<Item = (u8, u8)>) -> Option<Fragment> {
+        let position = self.follow.len() as u32;
+        if self.follow.len() == MAX_POSITIONS {
+            return None;
+        }
+        let mut bytes = [0u64; 4];
+        for (start, end) in ranges {
+            for byte in start..=end {
+                bytes[byte as usize / 64] |= 1 << (byte % 64);
+            }
+        }
+        self.bytes.push(bytes);
+        self.follow.push(vec![]);
+        Some(Fragment {
+            first: vec![position],
+            last: vec![position],
+            nullable: false,
+            min: 1,
+            max: Some(1),
+        })
+    }
+
+    fn concat(&mut self, mut a: Fragment, mut b: Fragment) -> Fragment {
+        self.link(&a.last, &b.first);
+        let nullable = a.nullable && b.nullable;
+        if a.nullable {
+            a.first.extend_from_slice(&b.first);
+        }
+        if b.nullable {
+            b.last.extend_from_slice(&a.last);
+        }
+        Fragment {
+            first: a.first,
+            last: b.last,
+            nullable,
+            min: a.min + b.min,
+            max: a.max.zip(b.max).map(|(a, b)| a + b),
+        }
+    }
+
+    fn alternate(&mut self, mut a: Fragment, b: Fragment) -> Fragment {
+        a.first.extend_from_slice(&b.first);
+        a.last.extend_from_slice(&b.last);
+        Fragment {
+            first: a.first,
+            last: a.last,
+            nullable: a.nullable || b.nullable,
+            min: a.min.min(b.min),
+            max: a.max.zip(b.max).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    // Lets every position in `from` be followed by every position in `to`.
+    fn link(&mut self, from: &[u32], to: &[u32]) {
+        for &position in from {
+            self.follow[position as usize].extend_from_slice(to);
+        }
+    }
+
+    fn build(&mut self, hir: &Hir) -> Option<Fragment> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return None;
+        }
+        match hir.kind() {
+            HirKind::Empty => Some(Fragment::empty()),
+            HirKind::Literal(Literal::Byte(b)) => self.position(std::iter::once((*b, *b))),
+            HirKind::Literal(Literal::Unicode(c)) => {
+                let mut fragment = Fragment::empty();
+                for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                    let next = self.position(std::iter::once((b, b)))?;
+                    fragment = self.concat(fragment, next);
+                }
+                Some(fragment)
+            }
+            HirKind::Class(Class::Bytes(class)) => {
+                self.position(class.iter().map(|x| (x.start(), x.end())))
+            }
+            HirKind::Class(Class::Unicode(_)) | HirKind::Anchor(_) | HirKind::WordBoundary(_) => {
+                None
+            }
+            HirKind::Group(group) => self.build(&group.hir),
+            HirKind::Concat(items) => {
+                let mut fragment = Fragment::empty();
+                for item in items {
+                    let next = self.build(item)?;
+                    fragment = self.concat(fragment, next);
+                }
+                Some(fragment)
+            }
+            HirKind::Alternation(branches) => {
+                let mut fragment = self.build(&branches[0])?;
+                for branch in &branches[1..] {
+                    let next = self.build(branch)?;
+                    fragment = self.alternate(fragment, next);
+                }
+                Some(fragment)
+            }
+            HirKind::Repetition(repetition) => {
+                let (min, max) = match &repetition.kind {
+                    RepetitionKind::ZeroOrOne => (0, Some(1)),
+                    RepetitionKind::ZeroOrMore => (0, None),
+                    RepetitionKind::OneOrMore => (1, None),
+                    RepetitionKind::Range(RepetitionRange::Exactly(n)) => (*n, Some(*n)),
+                    RepetitionKind::Range(RepetitionRange::AtLeast(n)) => (*n, None),
+                    RepetitionKind::Range(RepetitionRange::Bounded(n, m)) => (*n, Some(*m)),
+                };
+                self.repeat(&repetition.hir, min as usize, max.map(|x| x as usize))
+            }
+        }
+    }
+
+    // Each iteration of a repetition gets positions of its own, so this builds the
+    // repeated expression once for each iteration it needs.
+    fn repeat(&mut self, hir: &Hir, min: usize, max: Option<usize>) -> Option<Fragment> {
+        let mut fragment = Fragment::empty();
+        for _ in 0..min {
+            let next = self.build(hir)?;
+            fragment = self.concat(fragment, next);
+        }
+
+        match max {
+            // Any more iterations go around a loop.
+            None => {
+                let mut next = self.build(hir)?;
+                self.link(&next.last, &next.first);
+                next.nullable = true;
+                next.min = 0;
+                next.max = None;
+                Some(self.concat(fragment, next))
+            }
+            Some(max) => {
+                for _ in min..max {
+                    let mut next = self.build(hir)?;
+                    next.nullable = true;
+                    next.min = 0;
+                    fragment = self.concat(fragment, next);
+                }
+                Some(fragment)
+            }
+        }
+    }
+}
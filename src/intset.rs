@@ -12,30 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! This crate provides a collection of data structures for storing sets of integers.
+//! This module provides a collection of data structures for storing sets of integers.
 //! The different data structures are designed to make different operations efficient.
 //!
-//! All of the data structures in this crate support the following operations with the
+//! All of the data structures in this module support the following operations with the
 //! associated complexity:
 //!
 //! - **contains** - check if an integer is in the set in O(1) time
-//! - **iterate** - iterate over the members of the set in O(*n*) time, where *n* is the number of
-//!                 elements in the set
+//! - **iterate** - iterate over the members of the set in O(*n*) time, where *n* is the
+//!   number of elements in the set
 //! - **len** - return the number of elements in the set in O(1) time
+//! - **pop** - remove and return a member of the set in O(1) time
 //!
 //! Individual set data structures support additional operations, as documented below.
 //!
-//! All of the set data structures in this crate have a maximum capacity, specified as the
+//! All of the set data structures in this module have a maximum capacity, specified as the
 //! largest integer that can be stored in the set plus one. Once a set is created, it does
 //! no further allocations.
 
+use std::error;
+use std::fmt;
+
+/// The error returned when trying to store an integer that is outside of a set's capacity.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CapacityError {
+    value: usize,
+    capacity: usize,
+}
+
+impl CapacityError {
+    /// The integer that could not be stored.
+    pub fn value(&self) -> usize {
+        self.value
+    }
+
+    /// The capacity of the set that rejected the integer.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl error::Error for CapacityError {}
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is out of range for a set with capacity {}",
+            self.value, self.capacity
+        )
+    }
+}
+
 /// A `GrowSet` is a set of integers that supports efficient addition and clearing.
 ///
 /// It supports the following additional operations with the associated complexity:
 ///
 /// - **add** - add an integer to the set in O(1) time
 /// - **clear** - remove all members from the set in O(1) time
-/// - **pop** - remove and return a random member of the set in O(1) time
+/// - **pop** - remove and return the most recently added member of the set in O(1) time
+/// - **remove** - remove an integer from the set in O(*n*) time
+///
+/// Members are iterated in the order in which they were added.
 ///
 /// `GrowSet`s are useful for sets that need to be cleared frequently and rebuilt.
 ///
@@ -48,7 +85,7 @@ pub struct GrowSet {
     dense: Vec<usize>,
 }
 
-impl<'a> GrowSet {
+impl GrowSet {
     pub fn with_capacity(size: usize) -> Self {
         Self {
             n: 0,
@@ -57,9 +94,24 @@ impl<'a> GrowSet {
         }
     }
 
+    /// Returns one more than the largest integer that can be stored in the set.
+    pub fn capacity(&self) -> usize {
+        self.sparse.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
     #[inline(always)]
     pub fn contains(&self, value: usize) -> bool {
-        self.sparse[value] < self.n && self.dense[self.sparse[value]] == value
+        value < self.sparse.len()
+            && self.sparse[value] < self.n
+            && self.dense[self.sparse[value]] == value
     }
 
     /// Remove all items from the set.
@@ -68,6 +120,9 @@ impl<'a> GrowSet {
         self.n = 0;
     }
 
+    /// Add an integer to the set.
+    ///
+    /// Panics if the integer is outside of the set's capacity; see `try_add`.
     #[inline(always)]
     pub fn add(&mut self, value: usize) {
         if !self.contains(value) {
@@ -77,9 +132,49 @@ impl<'a> GrowSet {
         }
     }
 
-    /// Returns an iterator over the values in the set.
-    /// Uniqueness is guaranteed; ordering is not.
-    pub fn iter(&'a self) -> impl Iterator<Item = &usize> + 'a {
+    /// Add an integer to the set, returning whether it was newly added, or an error if
+    /// the integer is outside of the set's capacity.
+    pub fn try_add(&mut self, value: usize) -> Result<bool, CapacityError> {
+        if value >= self.capacity() {
+            return Err(CapacityError {
+                value,
+                capacity: self.capacity(),
+            });
+        }
+
+        let added = !self.contains(value);
+        self.add(value);
+        Ok(added)
+    }
+
+    /// Remove an integer from the set, returning whether it was present.
+    /// The remaining members keep their insertion order.
+    pub fn remove(&mut self, value: usize) -> bool {
+        if !self.contains(value) {
+            return false;
+        }
+
+        for index in self.sparse[value]..self.n - 1 {
+            let moved = self.dense[index + 1];
+            self.dense[index] = moved;
+            self.sparse[moved] = index;
+        }
+        self.n -= 1;
+        true
+    }
+
+    /// Remove and return the most recently added member of the set.
+    pub fn pop(&mut self) -> Option<usize> {
+        if self.n == 0 {
+            None
+        } else {
+            self.n -= 1;
+            Some(self.dense[self.n])
+        }
+    }
+
+    /// Returns an iterator over the values in the set, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = &usize> + '_ {
         self.dense.iter().take(self.n)
     }
 }
@@ -94,8 +189,9 @@ impl<'a> GrowSet {
 /// time complexity:
 ///
 /// - **remove** - remove an integer from the set in O(1) time
+/// - **insert** - add a previously-removed integer back into the set in O(1) time
 /// - **refill** - adds all removed elements back into the set in O(1) time
-/// - **pop** - remove and return a random member of the set in O(1) time
+/// - **pop** - remove and return the last member of the set in iteration order in O(1) time
 ///
 /// `ShrinkSet`s are useful for situations where we want to prune search spaces or
 /// work queues (for example) and then reset them to their initial state efficiently.
@@ -109,7 +205,7 @@ pub struct ShrinkSet {
     values: Vec<usize>,
 }
 
-impl<'a> ShrinkSet {
+impl ShrinkSet {
     pub fn new(size: usize) -> Self {
         Self {
             p: size,
@@ -117,6 +213,16 @@ impl<'a> ShrinkSet {
             values: (0..size).collect(),
         }
     }
+
+    /// Returns one more than the largest integer that can be stored in the set.
+    pub fn capacity(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.p
+    }
+
     pub fn contains(&self, value: usize) -> bool {
         value < self.map.len() && self.map[value] < self.p
     }
@@ -127,7 +233,7 @@ impl<'a> ShrinkSet {
 
     /// Returns an iterator over the values in the set.
     /// Uniqueness is guaranteed; ordering is not.
-    pub fn iter(&'a self) -> impl Iterator<Item = &usize> + 'a {
+    pub fn iter(&self) -> impl Iterator<Item = &usize> + '_ {
         self.values.iter().take(self.p)
     }
 
@@ -137,17 +243,42 @@ impl<'a> ShrinkSet {
 
     pub fn remove(&mut self, item: usize) -> usize {
         if self.contains(item) {
-            let item_index = self.map[item];
-            let last_item = self.values[self.p - 1];
-            let last_item_index = self.map[last_item];
-
-            self.values[last_item_index] = item;
-            self.values[item_index] = last_item;
-            self.map[last_item] = item_index;
-            self.map[item] = last_item_index;
+            self.swap(self.map[item], self.p - 1);
             self.p -= 1;
         }
 
         item
     }
+
+    /// Add a previously-removed integer back into the set, returning whether it was
+    /// newly added. Integers outside of the set's capacity are never added.
+    pub fn insert(&mut self, item: usize) -> bool {
+        if item >= self.map.len() || self.contains(item) {
+            return false;
+        }
+
+        self.swap(self.map[item], self.p);
+        self.p += 1;
+        true
+    }
+
+    /// Remove and return the last member of the set in iteration order.
+    pub fn pop(&mut self) -> Option<usize> {
+        if self.p == 0 {
+            None
+        } else {
+            self.p -= 1;
+            Some(self.values[self.p])
+        }
+    }
+
+    // Swap the values at two positions, keeping the map in sync.
+    fn swap(&mut self, a: usize, b: usize) {
+        let value_a = self.values[a];
+        let value_b = self.values[b];
+        self.values[a] = value_b;
+        self.values[b] = value_a;
+        self.map[value_a] = b;
+        self.map[value_b] = a;
+    }
 }
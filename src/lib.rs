@@ -2,12 +2,37 @@ use std::error;
 use std::fmt;
 
 mod aho_corasick;
+mod buffer;
+mod combinations;
 mod compiler;
-mod intset;
+mod decoders;
+mod glushkov;
+pub mod intset;
+mod lookback;
+mod merged;
+mod normalizers;
+mod offsets;
+mod optimizer;
+mod parallel;
 mod queue;
+#[cfg(feature = "async")]
+mod streams;
+mod trace;
 mod transformers;
 
-pub use compiler::{CompiledRegex, Database, DatabaseBuilder, Regex, Scratch};
+pub use combinations::{CombinationRule, SequenceRule};
+pub use compiler::{
+    Assertion, CompiledRegex, Database, DatabaseBuilder, ExpressionStats, Instruction, MemoryUsage,
+    Regex, Scratch, ScratchStats,
+};
+pub use decoders::{Base64Decoder, ChunkedDecoder, Decoder, HexDecoder, PercentDecoder};
+pub use normalizers::{HtmlEntityDecoder, LowercaseNormalizer, WhitespaceNormalizer};
+pub use offsets::OffsetMap;
+pub use parallel::ParallelScanner;
+#[cfg(feature = "async")]
+pub use streams::{scan_async_read, ScratchSink, SinkError};
+#[cfg(feature = "trace")]
+pub use trace::Tracer;
 pub use transformers::Transformer;
 
 #[derive(Debug)]
@@ -17,6 +42,7 @@ pub enum Error {
     TooManyRepetitions,
     SyntaxError(String),
     ProgramTooLarge,
+    ScratchTooLarge(usize),
 }
 
 impl error::Error for Error {}
@@ -30,6 +56,9 @@ impl fmt::Display for Error {
             Error::TooManyRepetitions => write!(f, "maximum repetition count exceeded"),
             Error::SyntaxError(ref msg) => write!(f, "syntax error: {}", msg),
             Error::ProgramTooLarge => write!(f, "expression too large"),
+            Error::ScratchTooLarge(id) => {
+                write!(f, "scratch space too large at expression {}", id)
+            }
         }
     }
 }
@@ -45,6 +74,7 @@ pub enum Submatch {
     All,         // Report all submatches. Equivalent to AtMost(u32::MAX).
     AtMost(u32), // Report at most n submatches.
     Expression,  // Report the position of the expression as a whole. Equivalent to AtMost(0).
+    None,        // Report only where a match was first certain, once, without tracking captures.
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -53,10 +83,36 @@ pub enum MatchMode {
     All(Submatch),   // Report all matches.
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Encoding {
-    Byte, // Byte-at-a-time
-    UTF8, // UTF8
+    Byte,    // Byte-at-a-time
+    UTF8,    // UTF8
+    UTF16LE, // UTF16, little-endian
+    UTF16BE, // UTF16, big-endian
+    Latin1,  // ISO-8859-1
+}
+
+// What ends a line, for the purposes of `^` and `$` in multi-line mode and of `.`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LineTerminator {
+    CROrLF,   // Either \r or \n ends a line, and . excludes only \n. The default.
+    LF,       // Only \n ends a line.
+    CRLF,     // \r, \n or \r\n ends a line, and \r\n is never split. . excludes both.
+    Byte(u8), // Any single byte ends a line.
+}
+
+impl LineTerminator {
+    // NUL terminated records, like the output of find -print0.
+    pub const NUL: LineTerminator = LineTerminator::Byte(0);
+}
+
+// A form of an expression to compile, for finding the same thing however it's been
+// written down. See Regex::variants.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Variant {
+    Encoded(Encoding), // The expression as text in the given encoding.
+    Hex,               // Hexadecimal digits of the encoded expression, in either case.
+    Base64, // Standard base64 of the encoded expression, at any alignment. Literals only.
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -91,6 +147,34 @@ pub trait MatchHandler {
     }
 
     fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching;
+
+    /// Called for every match in place of `on_match`, with everything known about the
+    /// match. By default this passes the match on to `on_match`.
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.on_match(info.id, info.captures)
+    }
+
+    /// Called for every match in place of `on_match_info` by a scratch space with a
+    /// capture buffer (see `Scratch::with_capture_buffer`), with the bytes each capture
+    /// covers. A capture's bytes are None if it didn't take part in the match, or if
+    /// they're no longer buffered. By default this passes the match on to
+    /// `on_match_info`.
+    fn on_match_with_data(
+        &mut self,
+        info: &MatchInfo,
+        _data: &[Option<&[u8]>],
+    ) -> ContinueMatching {
+        self.on_match_info(info)
+    }
+}
+
+// A match, as passed to MatchHandler::on_match_info.
+#[derive(Copy, Clone, Debug)]
+pub struct MatchInfo<'a> {
+    pub id: usize,
+    pub variant: Variant, // Which of the expression's variants matched.
+    pub edits: usize,     // How many edits an approximate match needed; see Regex::max_edits.
+    pub captures: &'a [Capture],
 }
 
 #[cfg(test)]
@@ -0,0 +1,85 @@
+// Finding where matches started by looking back from where they ended.
+//
+// An expression with a lookback doesn't carry the start of each thread along as it
+// runs. Instead it keeps the last bytes it ran over, and when it matches, its reversed
+// program runs back over them from the end of the match: the longest reversed match
+// gives the earliest start.
+use crate::{Capture, ContinueMatching, MatchHandler, MatchInfo};
+use std::collections::VecDeque;
+
+pub struct History {
+    bytes: VecDeque<u8>,
+    // The offset just past the last byte kept.
+    end: usize,
+    // The byte before the first one kept, if there is one.
+    before: Option<u8>,
+    limit: usize,
+    reversed: Vec<u8>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            bytes: VecDeque::with_capacity(limit),
+            end: 0,
+            before: None,
+            limit,
+            reversed: Vec::with_capacity(limit + 1),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.restart(0, None);
+    }
+
+    // Forgets the bytes kept and starts again at `offset`, where the byte before is
+    // taken to be `before`.
+    pub fn restart(&mut self, offset: usize, before: Option<u8>) {
+        self.bytes.clear();
+        self.end = offset;
+        self.before = before;
+    }
+
+    // The offset of the first byte kept.
+    pub fn start(&self) -> usize {
+        self.end - self.bytes.len()
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.bytes.len() == self.limit {
+            self.before = self.bytes.pop_front();
+        }
+        self.bytes.push_back(byte);
+        self.end += 1;
+    }
+
+    // The bytes kept, last first, followed by the byte before them if there is one.
+    // Also returns whether there is nothing before them.
+    pub fn reversed(&mut self) -> (&[u8], bool) {
+        self.reversed.clear();
+        self.reversed.extend(self.bytes.iter().rev());
+        self.reversed.extend(self.before);
+        (&self.reversed, self.before.is_none())
+    }
+}
+
+// Records the longest match of a reversed program.
+#[derive(Default)]
+pub struct Longest {
+    pub length: Option<usize>,
+}
+
+impl MatchHandler for Longest {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        ContinueMatching::Yes
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.length = self.length.max(info.captures[0].end);
+        ContinueMatching::Yes
+    }
+}
@@ -0,0 +1,393 @@
+// Expressions merged into a single automaton.
+//
+// Each expression in a database normally runs on its own, so the cost of a byte grows
+// with the number of expressions running over it. Expressions with position automata
+// (see glushkov.rs) can instead be merged: their positions are numbered together, and
+// the sets of positions that any of them could be at become the states of a DFA. The
+// DFA is built as the input reaches its states and cached in the scratch space, so a
+// step is a lookup in a table however many expressions there are, and each state
+// lists the expressions that end a match in it.
+//
+// Matches can start at any byte. The first positions of expressions with leading
+// assertions are grouped by those assertions, and which groups' assertions hold
+// before a byte is part of the symbol the DFA steps over. Trailing assertions are
+// checked by whoever reports the match.
+use crate::compiler::check_assertion;
+use crate::glushkov::Positions;
+use crate::{Assertion, ContinueMatching, LineTerminator};
+use std::collections::HashMap;
+use std::mem::size_of;
+
+// The most distinct sets of leading assertions the merged expressions can have.
+const MAX_GROUPS: usize = 4;
+
+// How many bytes a scratch space's cache of DFA states may use before it's emptied.
+const CACHE_BYTES: usize = 1 << 21;
+
+const UNKNOWN: u32 = u32::MAX;
+
+// The first positions of the expressions with the same leading assertions.
+struct Group {
+    leading: Vec<Assertion>,
+    line_terminator: LineTerminator,
+    first: Vec<u32>,
+}
+
+pub struct Merged {
+    // Whether each expression in the database is merged.
+    merged: Vec<bool>,
+
+    // The bytes each position matches and the positions that can follow it, numbered
+    // across all of the merged expressions.
+    bytes: Vec<[u64; 4]>,
+    follow: Vec<Vec<u32>>,
+
+    // The expression each position ends a match of, if any.
+    ends: Vec<Option<usize>>,
+
+    // Positions that can start a match anywhere, and those behind assertions.
+    first: Vec<u32>,
+    groups: Vec<Group>,
+
+    // Bytes that no position tells apart are in the same class.
+    classes: [u8; 256],
+    class_count: usize,
+
+    // The length of the longest match of an expression with fixed-length matches.
+    longest: Option<usize>,
+}
+
+impl Merged {
+    // Merges the expressions given by their index in the database, along with their
+    // positions and line terminators, leaving out those with leading assertions that
+    // would need too many groups. Returns None if none can be merged.
+    pub fn new(
+        expressions: usize,
+        candidates: Vec<(usize, &Positions, LineTerminator)>,
+    ) -> Option<Self> {
+        let mut merged = Self {
+            merged: vec![false; expressions],
+            bytes: vec![],
+            follow: vec![],
+            ends: vec![],
+            first: vec![],
+            groups: vec![],
+            classes: [0; 256],
+            class_count: 0,
+            longest: None,
+        };
+
+        for (index, positions, line_terminator) in candidates {
+            let group = if positions.leading().is_empty() {
+                None
+            } else if let Some(group) = merged.groups.iter().position(|x| {
+                x.leading == positions.leading() && x.line_terminator == line_terminator
+            }) {
+                Some(group)
+            } else if merged.groups.len() < MAX_GROUPS {
+                merged.groups.push(Group {
+                    leading: positions.leading().to_vec(),
+                    line_terminator,
+                    first: vec![],
+                });
+                Some(merged.groups.len() - 1)
+            } else {
+                continue;
+            };
+
+            let base = merged.follow.len() as u32;
+            for position in 0..positions.len() as u32 {
+                merged.bytes.push(*positions.bytes(position));
+                merged.follow.push(
+                    positions
+                        .follow(position)
+                        .iter()
+                        .map(|x| x + base)
+                        .collect(),
+                );
+                merged.ends.push(None);
+            }
+            for &position in positions.last() {
+                merged.ends[(base + position) as usize] = Some(index);
+            }
+            let first = positions.first().iter().map(|x| x + base);
+            match group {
+                Some(group) => merged.groups[group].first.extend(first),
+                None => merged.first.extend(first),
+            }
+            if let Some(length) = positions.length() {
+                merged.longest = merged.longest.max(Some(length));
+            }
+            merged.merged[index] = true;
+        }
+
+        if merged.follow.is_empty() {
+            return None;
+        }
+        merged.classify();
+        Some(merged)
+    }
+
+    // Splits the bytes into classes, so that every position matches either all of the
+    // bytes in a class or none of them.
+    fn classify(&mut self) {
+        let mut sets: Vec<[u64; 4]> = self.bytes.clone();
+        sets.sort_unstable();
+        sets.dedup();
+
+        let mut count = 1;
+        for set in sets {
+            let mut split: HashMap<(u8, bool), u8> = HashMap::new();
+            for byte in 0..=255u8 {
+                let key = (
+                    self.classes[byte as usize],
+                    set[byte as usize / 64] & 1 << (byte % 64) != 0,
+                );
+                let next = split.len() as u8;
+                self.classes[byte as usize] = *split.entry(key).or_insert(next);
+            }
+            count = split.len();
+            if count == 256 {
+                break;
+            }
+        }
+        self.class_count = count;
+    }
+
+    // Whether the expression at the given index in the database is merged.
+    pub fn contains(&self, index: usize) -> bool {
+        self.merged[index]
+    }
+
+    // The number of symbols a state can step over: a class, and which groups' assertions
+    // hold.
+    fn width(&self) -> usize {
+        self.class_count << self.groups.len()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.merged.capacity() * size_of::<bool>()
+            + self.bytes.capacity() * size_of::<[u64; 4]>()
+            + self.follow.capacity() * size_of::<Vec<u32>>()
+            + self
+                .follow
+                .iter()
+                .map(|x| x.capacity() * size_of::<u32>())
+                .sum::<usize>()
+            + self.ends.capacity() * size_of::<Option<usize>>()
+            + self.first.capacity() * size_of::<u32>()
+            + self.groups.capacity() * size_of::<Group>()
+            + self
+                .groups
+                .iter()
+                .map(|x| {
+                    x.leading.capacity() * size_of::<Assertion>()
+                        + x.first.capacity() * size_of::<u32>()
+                })
+                .sum::<usize>()
+    }
+
+    // As for Queue::heap_size: the cache is emptied before it grows past this.
+    pub fn scratch_size(&self) -> usize {
+        size_of::<MergedScratch>() + CACHE_BYTES + self.width() * size_of::<u32>()
+    }
+
+    pub fn make_scratch(&self) -> MergedScratch<'_> {
+        let mut scratch = MergedScratch {
+            merged: self,
+            states: vec![],
+            map: HashMap::new(),
+            table: vec![],
+            size: 0,
+            current: 0,
+            next: vec![],
+        };
+        scratch.flush(&[]);
+        scratch
+    }
+}
+
+// A state of the DFA: the positions that could be active, and the expressions they end
+// matches of.
+struct State {
+    positions: Vec<u32>,
+    matches: Vec<usize>,
+}
+
+pub struct MergedScratch<'a> {
+    merged: &'a Merged,
+    states: Vec<State>,
+    map: HashMap<Vec<u32>, u32>,
+    // The state each state steps to over each symbol, if it's known yet.
+    table: Vec<u32>,
+    // The number of bytes the cache holds.
+    size: usize,
+    current: u32,
+    // The positions of the state being built.
+    next: Vec<u32>,
+}
+
+impl MergedScratch<'_> {
+    pub fn reset(&mut self) {
+        self.current = 0;
+    }
+
+    // Whether no expression is partway through a match.
+    pub fn is_idle(&self) -> bool {
+        self.states[self.current as usize].positions.is_empty()
+    }
+
+    // The earliest offset at which a running match could have started, if that's known
+    // and matters.
+    pub fn earliest_start(&self, offset: usize) -> Option<usize> {
+        if self.is_idle() {
+            return None;
+        }
+        self.merged
+            .longest
+            .map(|longest| offset.saturating_sub(longest))
+    }
+
+    // Runs over a block of input starting at `offset`, calling `report` with the index
+    // in the database of each expression that ends a match before a byte, the offset,
+    // and the bytes on either side of it.
+    pub fn scan(
+        &mut self,
+        mut offset: usize,
+        mut last_byte: Option<u8>,
+        bytes: &[u8],
+        report: &mut dyn FnMut(usize, usize, Option<u8>, Option<u8>) -> ContinueMatching,
+    ) -> ContinueMatching {
+        let merged = self.merged;
+        let width = merged.width();
+        let shift = merged.groups.len();
+
+        for &byte in bytes {
+            if self.report(offset, last_byte, Some(byte), report) == ContinueMatching::No {
+                return ContinueMatching::No;
+            }
+
+            let mut mask = 0;
+            for (bit, group) in merged.groups.iter().enumerate() {
+                if group
+                    .leading
+                    .iter()
+                    .all(|&a| check_assertion(a, group.line_terminator, last_byte, Some(byte)))
+                {
+                    mask |= 1 << bit;
+                }
+            }
+            let symbol = (merged.classes[byte as usize] as usize) << shift | mask;
+            let mut next = self.table[self.current as usize * width + symbol];
+            if next == UNKNOWN {
+                next = self.step(byte, mask);
+                self.table[self.current as usize * width + symbol] = next;
+            }
+            self.current = next;
+
+            last_byte = Some(byte);
+            offset += 1;
+        }
+        ContinueMatching::Yes
+    }
+
+    // Reports the matches that end at the end of the input.
+    pub fn finish(
+        &mut self,
+        offset: usize,
+        last_byte: Option<u8>,
+        report: &mut dyn FnMut(usize, usize, Option<u8>, Option<u8>) -> ContinueMatching,
+    ) -> ContinueMatching {
+        self.report(offset, last_byte, None, report)
+    }
+
+    fn report(
+        &self,
+        offset: usize,
+        last_byte: Option<u8>,
+        byte: Option<u8>,
+        report: &mut dyn FnMut(usize, usize, Option<u8>, Option<u8>) -> ContinueMatching,
+    ) -> ContinueMatching {
+        for &index in self.states[self.current as usize].matches.iter() {
+            if report(index, offset, last_byte, byte) == ContinueMatching::No {
+                return ContinueMatching::No;
+            }
+        }
+        ContinueMatching::Yes
+    }
+
+    // Builds the state the current state steps to over a byte, given which groups'
+    // assertions hold before it.
+    fn step(&mut self, byte: u8, mask: usize) -> u32 {
+        let merged = self.merged;
+        let matches = |&position: &u32| {
+            merged.bytes[position as usize][byte as usize / 64] & 1 << (byte % 64) != 0
+        };
+
+        let mut next = std::mem::take(&mut self.next);
+        next.clear();
+        for &position in self.states[self.current as usize].positions.iter() {
+            next.extend(
+                merged.follow[position as usize]
+                    .iter()
+                    .filter(|x| matches(x)),
+            );
+        }
+        next.extend(merged.first.iter().filter(|x| matches(x)));
+        for (bit, group) in merged.groups.iter().enumerate() {
+            if mask & 1 << bit != 0 {
+                next.extend(group.first.iter().filter(|x| matches(x)));
+            }
+        }
+        next.sort_unstable();
+        next.dedup();
+
+        let state = match self.map.get(&next) {
+            Some(&state) => state,
+            None => {
+                if self.size > CACHE_BYTES {
+                    let current = self.states[self.current as usize].positions.clone();
+                    self.flush(&current);
+                }
+                self.add(next.clone())
+            }
+        };
+        self.next = next;
+        state
+    }
+
+    // Empties the cache, keeping only the empty state and the current one.
+    fn flush(&mut self, current: &[u32]) {
+        self.states.clear();
+        self.map.clear();
+        self.table.clear();
+        self.size = 0;
+        self.add(vec![]);
+        self.current = self.add(current.to_vec());
+    }
+
+    fn add(&mut self, positions: Vec<u32>) -> u32 {
+        if let Some(&state) = self.map.get(&positions) {
+            return state;
+        }
+
+        let merged = self.merged;
+        let mut matches: Vec<usize> = positions
+            .iter()
+            .filter_map(|&x| merged.ends[x as usize])
+            .collect();
+        matches.sort_unstable();
+        matches.dedup();
+
+        let state = self.states.len() as u32;
+        self.size += size_of::<State>()
+            + 2 * positions.len() * size_of::<u32>()
+            + matches.len() * size_of::<usize>()
+            + merged.width() * size_of::<u32>();
+        self.table
+            .resize(self.table.len() + merged.width(), UNKNOWN);
+        self.map.insert(positions.clone(), state);
+        self.states.push(State { positions, matches });
+        state
+    }
+}
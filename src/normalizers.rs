@@ -0,0 +1,139 @@
+// Normalizers, for matching text as it reads rather than as it's written.
+//
+// These are decoders like any other, so a scratch space maps the offsets of matches
+// in normalized text back to the original; see Scratch::with_decoder. Normalization
+// done elsewhere can be described with an OffsetMap and pushed with push_mapped.
+use crate::Decoder;
+
+/// Lowercases ASCII letters. Every other byte is left as it is.
+#[derive(Default)]
+pub struct LowercaseNormalizer {}
+
+impl LowercaseNormalizer {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for LowercaseNormalizer {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        output.push((byte.to_ascii_lowercase(), start));
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Collapses each run of ASCII whitespace into a single space. The space maps to the
+/// first byte of the run.
+#[derive(Default)]
+pub struct WhitespaceNormalizer {
+    in_whitespace: bool,
+}
+
+impl WhitespaceNormalizer {
+    pub fn new() -> Self {
+        Self {
+            in_whitespace: false,
+        }
+    }
+}
+
+impl Decoder for WhitespaceNormalizer {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        if !byte.is_ascii_whitespace() {
+            self.in_whitespace = false;
+            output.push((byte, start));
+        } else if !self.in_whitespace {
+            self.in_whitespace = true;
+            output.push((b' ', start));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.in_whitespace = false;
+    }
+}
+
+// The longest entity we recognize, from the ampersand to the semicolon.
+const MAX_ENTITY: usize = 10;
+
+/// Decodes HTML character references: decimal and hexadecimal references, and the
+/// named references for the characters HTML escapes most often. Decoded characters
+/// are encoded as UTF-8. Anything that doesn't decode is passed through as it is.
+#[derive(Default)]
+pub struct HtmlEntityDecoder {
+    // The reference seen so far and where each byte of it started.
+    pending: Vec<(u8, usize)>,
+}
+
+impl HtmlEntityDecoder {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::with_capacity(MAX_ENTITY),
+        }
+    }
+
+    // The character a reference stands for, given the text between the ampersand
+    // and the semicolon.
+    fn character(name: &[u8]) -> Option<char> {
+        let number = |digits: &[u8], radix| {
+            std::str::from_utf8(digits)
+                .ok()
+                .and_then(|x| u32::from_str_radix(x, radix).ok())
+                .and_then(char::from_u32)
+        };
+
+        match name {
+            b"amp" => Some('&'),
+            b"lt" => Some('<'),
+            b"gt" => Some('>'),
+            b"quot" => Some('"'),
+            b"apos" => Some('\''),
+            b"nbsp" => Some('\u{a0}'),
+            [b'#', b'x' | b'X', digits @ ..] => number(digits, 16),
+            [b'#', digits @ ..] => number(digits, 10),
+            _ => None,
+        }
+    }
+}
+
+impl Decoder for HtmlEntityDecoder {
+    fn decode(&mut self, byte: u8, start: usize, output: &mut Vec<(u8, usize)>) {
+        if self.pending.is_empty() {
+            if byte == b'&' {
+                self.pending.push((byte, start));
+            } else {
+                output.push((byte, start));
+            }
+            return;
+        }
+
+        if byte == b';' {
+            let name: Vec<u8> = self.pending[1..].iter().map(|x| x.0).collect();
+            if let Some(c) = Self::character(&name) {
+                let start = self.pending[0].1;
+                for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                    output.push((b, start));
+                }
+                self.pending.clear();
+                return;
+            }
+        } else if (byte.is_ascii_alphanumeric() || byte == b'#') && self.pending.len() < MAX_ENTITY
+        {
+            self.pending.push((byte, start));
+            return;
+        }
+
+        // Not a reference after all.
+        output.append(&mut self.pending);
+        self.decode(byte, start, output);
+    }
+
+    fn finish(&mut self, output: &mut Vec<(u8, usize)>) {
+        output.append(&mut self.pending);
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,194 @@
+// Mapping offsets in the stream the matcher sees back to the stream it came from.
+//
+// When input is decoded or normalized before it's matched, every byte the matcher
+// sees came from a span of the original input: the four characters of a base64
+// group, say, the five of an HTML entity, or a run of whitespace. The map records
+// that span for each byte, and capture offsets are translated through it before
+// they're reported. A scratch space only keeps spans for as long as a match could
+// still start at them.
+use crate::{Capture, ContinueMatching, MatchHandler, MatchInfo};
+use std::collections::VecDeque;
+
+/// Where each byte of some decoded or normalized input came from in the original
+/// stream. Build one alongside the normalized bytes and pass both to
+/// `Scratch::push_mapped`.
+pub struct OffsetMap {
+    // The offset of the first byte whose span is kept.
+    base: usize,
+
+    // The span of the original stream each byte came from, starting at base.
+    spans: VecDeque<(usize, usize)>,
+
+    // The end of the last span pushed.
+    end: usize,
+}
+
+impl Default for OffsetMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        Self {
+            base: 0,
+            spans: VecDeque::new(),
+            end: 0,
+        }
+    }
+
+    /// Forgets every byte mapped so far.
+    pub fn clear(&mut self) {
+        self.base = 0;
+        self.spans.clear();
+        self.end = 0;
+    }
+
+    /// Records that the next byte came from the bytes `start..end` of the original.
+    pub fn push(&mut self, start: usize, end: usize) {
+        self.spans.push_back((start, end));
+        self.end = end;
+    }
+
+    /// Records that the next `len` bytes are the original bytes starting at `start`,
+    /// unchanged.
+    pub fn push_unchanged(&mut self, start: usize, len: usize) {
+        for offset in start..start + len {
+            self.push(offset, offset + 1);
+        }
+    }
+
+    /// Returns the number of bytes mapped.
+    pub fn len(&self) -> usize {
+        self.base + self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the span of the original that the byte at `offset` came from.
+    pub fn span(&self, offset: usize) -> Option<(usize, usize)> {
+        offset
+            .checked_sub(self.base)
+            .and_then(|index| self.spans.get(index))
+            .copied()
+    }
+
+    // The span the byte after the last one mapped would have if it was unchanged.
+    pub(crate) fn next(&self) -> (usize, usize) {
+        (self.end, self.end + 1)
+    }
+
+    // Forgets the spans of the bytes before `offset`.
+    pub(crate) fn prune(&mut self, offset: usize) {
+        while self.base < offset && !self.spans.is_empty() {
+            self.spans.pop_front();
+            self.base += 1;
+        }
+    }
+
+    // Where the byte at `offset` starts in the original stream. The end of the
+    // stream maps to the end of the original.
+    pub(crate) fn start(&self, offset: usize) -> usize {
+        match offset.checked_sub(self.base) {
+            Some(index) if index < self.spans.len() => self.spans[index].0,
+            Some(_) => self.end,
+            None => self.spans.front().map_or(self.end, |x| x.0),
+        }
+    }
+
+    // Where the bytes ending at `offset` end in the original stream.
+    fn end(&self, offset: usize) -> usize {
+        match offset.checked_sub(self.base + 1) {
+            Some(index) if index < self.spans.len() => self.spans[index].1,
+            _ => self.start(offset),
+        }
+    }
+
+    /// Maps a capture over the mapped bytes to the span of the original it covers.
+    pub fn map(&self, capture: Capture) -> Capture {
+        // Empty captures stay empty.
+        if capture.start.is_some() && capture.start == capture.end {
+            let offset = capture.start.map(|x| self.start(x));
+            return Capture::new(offset, offset);
+        }
+        Capture::new(
+            capture.start.map(|x| self.start(x)),
+            capture.end.map(|x| self.end(x)),
+        )
+    }
+}
+
+// Passes matches on to a handler with their offsets mapped through an OffsetMap, if
+// there is one.
+pub struct MappedHandler<'a, 'b> {
+    handler: &'a mut (dyn MatchHandler + 'b),
+    offsets: Option<&'a OffsetMap>,
+    captures: Vec<Capture>,
+}
+
+impl<'a, 'b> MappedHandler<'a, 'b> {
+    pub fn new(handler: &'a mut (dyn MatchHandler + 'b), offsets: Option<&'a OffsetMap>) -> Self {
+        Self {
+            handler,
+            offsets,
+            captures: Vec::new(),
+        }
+    }
+
+    fn map(&mut self, captures: &[Capture]) {
+        let offsets = self.offsets.unwrap();
+        self.captures.clear();
+        self.captures
+            .extend(captures.iter().map(|&capture| offsets.map(capture)));
+    }
+}
+
+impl MatchHandler for MappedHandler<'_, '_> {
+    fn get_pulse_interval(&self) -> usize {
+        self.handler.get_pulse_interval()
+    }
+
+    fn on_pulse(&mut self) -> ContinueMatching {
+        self.handler.on_pulse()
+    }
+
+    fn on_reset(&mut self) {
+        self.handler.on_reset()
+    }
+
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        if self.offsets.is_none() {
+            return self.handler.on_match(id, captures);
+        }
+        self.map(captures);
+        self.handler.on_match(id, &self.captures)
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        if self.offsets.is_none() {
+            return self.handler.on_match_info(info);
+        }
+        self.map(info.captures);
+        self.handler.on_match_info(&MatchInfo {
+            captures: &self.captures,
+            ..*info
+        })
+    }
+
+    fn on_match_with_data(&mut self, info: &MatchInfo, data: &[Option<&[u8]>]) -> ContinueMatching {
+        if self.offsets.is_none() {
+            return self.handler.on_match_with_data(info, data);
+        }
+        self.map(info.captures);
+        self.handler.on_match_with_data(
+            &MatchInfo {
+                captures: &self.captures,
+                ..*info
+            },
+            data,
+        )
+    }
+}
@@ -0,0 +1,223 @@
+// A peephole optimizer for compiled programs.
+//
+// The compiler emits programs a piece at a time, which leaves behind jumps to
+// jumps and jumps to the next instruction. The optimizer threads jumps through to
+// their final destination and then removes the instructions that can no longer be
+// reached.
+//
+// The matcher keeps one thread per state at each offset, and a thread that reaches
+// a state another thread already holds is dropped. A state is usually a program
+// counter. So an optimized program has to queue threads at the same places as
+// before, or two threads that were kept apart would be merged and one of their
+// matches lost.
+// Threads are queued at the entry point, at the second branch of a split, after an
+// instruction that consumes a byte, and at the instructions of a counted loop and
+// its exit. Those places are left where they are: only the paths a thread follows
+// without being queued, through jumps and the first branches of splits, are
+// shortened, and a split is kept even when both of its branches lead to the same
+// place, since it queues a thread of its own.
+//
+// Runs of Byte or FoldedByte instructions that can only be entered at their first
+// byte are then merged into Literal instructions. Input is pushed to the matcher a
+// byte at a time, so a thread can stop partway through a run; each position in the
+// run keeps a state of its own, numbered after the program's instructions, so that
+// threads are queued just as they were at the Byte instructions.
+use crate::compiler::Instruction;
+
+// Optimizes a program in place, returning a map from each old program counter to
+// its new one. Program counter zero is always the entry point.
+pub fn optimize(program: &mut Vec<Instruction>) -> Vec<usize> {
+    let mut map: Vec<usize> = (0..program.len()).collect();
+
+    loop {
+        let size = program.len();
+        thread(program);
+        let queued = queued(program);
+        let pass = compact(program, &queued);
+        for pc in map.iter_mut() {
+            *pc = pass[*pc];
+        }
+        if program.len() == size {
+            return map;
+        }
+    }
+}
+
+// Follows NoOps and Jumps from a program counter to the first instruction that
+// does something.
+fn resolve(program: &[Instruction], mut pc: usize) -> usize {
+    // A chain of jumps can't be longer than the program without looping forever.
+    for _ in 0..program.len() {
+        match program[pc] {
+            Instruction::NoOp => pc += 1,
+            Instruction::Jump(target) if target == pc => break,
+            Instruction::Jump(target) => pc = target,
+            _ => break,
+        }
+    }
+    pc
+}
+
+// Points every jump and the first branch of every split directly at its
+// destination.
+fn thread(program: &mut [Instruction]) {
+    for pc in 0..program.len() {
+        program[pc] = match program[pc] {
+            Instruction::Jump(target) => match resolve(program, target) {
+                target if target == pc + 1 => Instruction::NoOp,
+                target if matches!(program[target], Instruction::Match) => Instruction::Match,
+                target => Instruction::Jump(target),
+            },
+            Instruction::Split(pc_a, pc_b) => Instruction::Split(resolve(program, pc_a), pc_b),
+            instruction => instruction,
+        }
+    }
+}
+
+// Returns the instructions that threads can be queued at.
+fn queued(program: &[Instruction]) -> Vec<bool> {
+    let mut queued = vec![false; program.len() + 1];
+    queued[0] = true;
+
+    for (pc, instruction) in program.iter().enumerate() {
+        match *instruction {
+            Instruction::Split(_, pc_b) => queued[pc_b] = true,
+            Instruction::Byte(_)
+            | Instruction::Literal(_)
+            | Instruction::FoldedByte(_)
+            | Instruction::CharClass(_) => queued[pc + 1] = true,
+            Instruction::RepeatEnter(_) => queued[pc..pc + 4].fill(true),
+            _ => {}
+        }
+    }
+    queued
+}
+
+// Returns the instructions that can be reached from the entry point.
+fn reachable(program: &[Instruction]) -> Vec<bool> {
+    let mut seen = vec![false; program.len()];
+    let mut stack = vec![0];
+
+    while let Some(pc) = stack.pop() {
+        if pc >= program.len() || seen[pc] {
+            continue;
+        }
+        seen[pc] = true;
+
+        match program[pc] {
+            Instruction::Match => {}
+            Instruction::Jump(target) => stack.push(target),
+            Instruction::Split(pc_a, pc_b) => {
+                stack.push(pc_a);
+                stack.push(pc_b);
+            }
+            // A counted loop's instructions are always reached together, and its exit
+            // is the instruction that follows them.
+            Instruction::RepeatEnter(_) => stack.extend([pc + 1, pc + 2, pc + 3]),
+            _ => stack.push(pc + 1),
+        }
+    }
+    seen
+}
+
+// Removes unreachable instructions, and NoOps that threads aren't queued at,
+// returning a map from each old program counter to its new one. Removed
+// instructions map to the instruction that follows them, which is where falling
+// through them would have led.
+fn compact(program: &mut Vec<Instruction>, queued: &[bool]) -> Vec<usize> {
+    let seen = reachable(program);
+    let keep: Vec<bool> = (0..program.len())
+        .map(|pc| seen[pc] && (queued[pc] || !matches!(program[pc], Instruction::NoOp)))
+        .collect();
+    let mut map = vec![0; program.len() + 1];
+    let mut kept = 0;
+
+    for pc in 0..program.len() {
+        map[pc] = kept;
+        if pc == 0 || keep[pc] {
+            kept += 1;
+        }
+    }
+    map[program.len()] = kept;
+
+    let mut pc = 0;
+    program.retain(|_| {
+        let kept = pc == 0 || keep[pc];
+        pc += 1;
+        kept
+    });
+
+    for instruction in program.iter_mut() {
+        *instruction = match *instruction {
+            Instruction::Jump(target) => Instruction::Jump(map[target]),
+            Instruction::Split(pc_a, pc_b) => Instruction::Split(map[pc_a], map[pc_b]),
+            instruction => instruction,
+        }
+    }
+
+    map.truncate(map.len() - 1);
+    map
+}
+
+// Merges runs of Byte or FoldedByte instructions into Literal instructions,
+// returning the bytes of each run, whether they're folded, and a map from each old
+// program counter to its new one. A run goes no further than the next instruction
+// that a jump, a split or a counted loop leads to, since a thread there would have
+// to start partway through the run.
+pub fn merge_literals(program: &mut Vec<Instruction>) -> (Vec<(Vec<u8>, bool)>, Vec<usize>) {
+    let mut targets = vec![false; program.len() + 1];
+    targets[0] = true;
+    for (pc, instruction) in program.iter().enumerate() {
+        match *instruction {
+            Instruction::Jump(target) => targets[target] = true,
+            Instruction::Split(pc_a, pc_b) => {
+                targets[pc_a] = true;
+                targets[pc_b] = true;
+            }
+            Instruction::RepeatEnter(_) => targets[pc + 3] = true,
+            _ => {}
+        }
+    }
+
+    let mut runs = vec![];
+    let mut merged = Vec::with_capacity(program.len());
+    let mut map = vec![0; program.len() + 1];
+    let mut pc = 0;
+    while pc < program.len() {
+        let folded = matches!(program[pc], Instruction::FoldedByte(_));
+        let mut run = vec![];
+        while let Some(&instruction) = program.get(pc + run.len()) {
+            match instruction {
+                _ if !run.is_empty() && targets[pc + run.len()] => break,
+                Instruction::Byte(b) if !folded => run.push(b),
+                Instruction::FoldedByte(b) if folded => run.push(b),
+                _ => break,
+            }
+        }
+
+        map[pc] = merged.len();
+        if run.len() > 1 {
+            // Nothing leads to the rest of the run, so they needn't map anywhere else.
+            map[pc..pc + run.len()].fill(merged.len());
+            merged.push(Instruction::Literal(runs.len()));
+            pc += run.len();
+            runs.push((run, folded));
+        } else {
+            merged.push(program[pc]);
+            pc += 1;
+        }
+    }
+    map[program.len()] = merged.len();
+
+    for instruction in merged.iter_mut() {
+        *instruction = match *instruction {
+            Instruction::Jump(target) => Instruction::Jump(map[target]),
+            Instruction::Split(pc_a, pc_b) => Instruction::Split(map[pc_a], map[pc_b]),
+            instruction => instruction,
+        }
+    }
+
+    *program = merged;
+    map.truncate(map.len() - 1);
+    (runs, map)
+}
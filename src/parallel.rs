@@ -0,0 +1,223 @@
+// Scanning input held in memory on several threads.
+//
+// The input is split into chunks, and each chunk is scanned by a scratch space of its
+// own, started at the beginning of the chunk as though nothing was running there. A
+// scratch space carries on past the end of its chunk until it's idle: until no
+// expression is partway through a match. A scratch space that started later has started
+// no match that one hasn't, so it's idle there too, and from there on the two find the
+// same matches. So the matches in the input are those the first chunk's scratch space
+// found before it was idle, then those found by the scratch space of the chunk it was
+// idle in from there until that one was idle, and so on.
+//
+// A match is found before a scratch space is idle if and only if it ends before then,
+// so the matches are stitched together by where they end. Expressions that are
+// reported once a stream might have been reported already by an earlier chunk, so
+// later reports of them are dropped. Combinations are worked out as the stitched
+// matches are reported.
+use crate::combinations::{CombinationState, CombiningHandler};
+use crate::{Capture, ContinueMatching, Database, MatchHandler, MatchInfo, Variant};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+// The most input a scratch space scans past the end of its chunk before it looks to
+// see if it's idle again.
+const MAX_STEP: usize = 4096;
+
+// A match, as it's kept until the chunks are stitched together.
+struct Found {
+    id: usize,
+    variant: Variant,
+    edits: usize,
+    captures: Vec<Capture>,
+}
+
+impl Found {
+    fn end(&self) -> usize {
+        self.captures[0].end.unwrap_or(0)
+    }
+}
+
+// Keeps the matches found in a chunk.
+#[derive(Default)]
+struct Collector {
+    found: Vec<Found>,
+}
+
+impl MatchHandler for Collector {
+    // Only combinations are reported this way, and they're worked out afterwards.
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        ContinueMatching::Yes
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.found.push(Found {
+            id: info.id,
+            variant: info.variant,
+            edits: info.edits,
+            captures: info.captures.to_vec(),
+        });
+        ContinueMatching::Yes
+    }
+}
+
+// The matches found from the start of a chunk up to the offset where the scratch space
+// scanning it was idle, or the end of the input.
+struct Part {
+    end: usize,
+    found: Vec<Found>,
+}
+
+/// Scans input that's all in memory on several threads, each scanning a chunk of it
+/// with a scratch space of its own. The matches are the same as those found by pushing
+/// all of the input to a single scratch space and finishing it, though they may be
+/// reported in a different order.
+///
+/// Matches are kept until every chunk has been scanned, and then reported from the
+/// calling thread. Scratch spaces are made for each chunk, so there are no decoders,
+/// capture buffers or statistics.
+pub struct ParallelScanner<'a> {
+    database: &'a Database,
+    threads: usize,
+    chunk_size: usize,
+}
+
+impl<'a> ParallelScanner<'a> {
+    pub fn new(database: &'a Database) -> Self {
+        Self {
+            database,
+            threads: thread::available_parallelism().map_or(1, |x| x.get()),
+            chunk_size: 1 << 20,
+        }
+    }
+
+    /// Sets the number of threads to scan on. By default, this is as many as can run
+    /// at once.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the number of bytes in each chunk, 1MiB by default. A thread scans past
+    /// the end of its chunk until no match is running, so chunks should be much larger
+    /// than the matches expected.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Scans `bytes` as a whole stream, reporting the matches to `handler`. Returns
+    /// `ContinueMatching::No` if the handler stopped the scan.
+    pub fn scan(&self, bytes: &[u8], handler: &mut dyn MatchHandler) -> ContinueMatching {
+        let chunks = bytes.len().div_ceil(self.chunk_size).max(1);
+        let next = AtomicUsize::new(0);
+        let mut parts: Vec<Option<Part>> = (0..chunks).map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(chunks))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut parts = vec![];
+                        loop {
+                            let chunk = next.fetch_add(1, Ordering::Relaxed);
+                            if chunk >= chunks {
+                                return parts;
+                            }
+                            parts.push((chunk, self.scan_chunk(bytes, chunk)));
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (chunk, part) in worker.join().unwrap() {
+                    parts[chunk] = Some(part);
+                }
+            }
+        });
+
+        let parts: Vec<Part> = parts.into_iter().map(Option::unwrap).collect();
+        let combinations = self.database.combinations();
+        let mut state = CombinationState::new(combinations);
+        let mut combining = CombiningHandler::new(handler, combinations, &mut state);
+        if self.stitch(&parts, bytes.len(), &mut combining) == ContinueMatching::No {
+            return ContinueMatching::No;
+        }
+        combinations.finish(&mut state, bytes.len(), handler)
+    }
+
+    // Scans a chunk, and on past its end until the scratch space is idle.
+    fn scan_chunk(&self, bytes: &[u8], chunk: usize) -> Part {
+        let start = chunk * self.chunk_size;
+        let mut end = (start + self.chunk_size).min(bytes.len());
+        let mut collector = Collector::default();
+        let mut scratch = self.database.make_scratch(&mut collector);
+        scratch.seek(start, &bytes[..start]);
+        scratch.push(&bytes[start..end]);
+
+        let mut step = 1;
+        while end < bytes.len() && !scratch.is_idle() {
+            let next = (end + step).min(bytes.len());
+            scratch.push(&bytes[end..next]);
+            end = next;
+            step = (2 * step).min(MAX_STEP);
+        }
+        if end == bytes.len() {
+            scratch.push_finish();
+        }
+        drop(scratch);
+        Part {
+            end,
+            found: collector.found,
+        }
+    }
+
+    // Reports the matches of each part from where the part before it was idle.
+    fn stitch(
+        &self,
+        parts: &[Part],
+        length: usize,
+        handler: &mut dyn MatchHandler,
+    ) -> ContinueMatching {
+        let mut once = self.once();
+        let mut start = 0;
+        loop {
+            let part = &parts[start / self.chunk_size];
+            for found in part.found.iter().filter(|x| x.end() >= start) {
+                if let Some(remaining) = once.get_mut(&(found.id, found.variant)) {
+                    if *remaining == 0 {
+                        continue;
+                    }
+                    *remaining -= 1;
+                }
+                let info = MatchInfo {
+                    id: found.id,
+                    variant: found.variant,
+                    edits: found.edits,
+                    captures: &found.captures,
+                };
+                if handler.on_match_info(&info) == ContinueMatching::No {
+                    return ContinueMatching::No;
+                }
+            }
+            if part.end == length {
+                return ContinueMatching::Yes;
+            }
+            start = part.end;
+        }
+    }
+
+    // The number of times matches with each id and variant can be reported, if they're
+    // all from expressions that are reported once. Matches don't say which expression
+    // they're from, so those with the same id and variant are counted together.
+    fn once(&self) -> HashMap<(usize, Variant), usize> {
+        let mut once: HashMap<(usize, Variant), Option<usize>> = HashMap::new();
+        for expression in self.database.expressions() {
+            let count = once
+                .entry((expression.get_id(), expression.variant()))
+                .or_insert(Some(0));
+            *count = count.filter(|_| expression.reports_once()).map(|x| x + 1);
+        }
+        once.into_iter()
+            .filter_map(|(key, count)| Some((key, count?)))
+            .collect()
+    }
+}
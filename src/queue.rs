@@ -1,12 +1,102 @@
 use crate::intset::GrowSet;
 use crate::Capture;
 use std::collections::VecDeque;
+use std::mem::size_of;
+
+// Where the thread with the given captures started. Threads in existence-only
+// expressions have no captures, and so no start: any of them will do.
+pub fn start(captures: &[Capture]) -> Option<usize> {
+    captures.first().and_then(|x| x.start)
+}
+
+// The captures of an expression's threads, kept in fixed-size slots that threads
+// share. A thread that splits, or moves on past a byte, takes another reference to
+// its slot rather than a copy of it, and only gets a slot of its own when it records
+// a capture while sharing one. The cost of a step then grows with the captures that
+// change, not with the number of threads times the number of captures.
+pub struct Slots {
+    captures: Vec<Capture>,
+    refs: Vec<usize>,
+    free: Vec<usize>,
+    width: usize,
+}
+
+impl Slots {
+    pub fn new(count: usize, width: usize) -> Self {
+        Self {
+            captures: vec![Capture::new(None, None); count * width],
+            refs: vec![0; count],
+            free: (0..count).rev().collect(),
+            width,
+        }
+    }
+
+    // As for Queue::heap_size.
+    pub fn heap_size(count: usize, width: usize) -> usize {
+        count * (width * size_of::<Capture>() + 2 * size_of::<usize>())
+    }
+
+    pub fn get(&self, slot: usize) -> &[Capture] {
+        &self.captures[slot * self.width..(slot + 1) * self.width]
+    }
+
+    pub fn start(&self, slot: usize) -> Option<usize> {
+        start(self.get(slot))
+    }
+
+    // Returns a new slot for a thread that starts at `offset`.
+    pub fn empty(&mut self, offset: usize) -> usize {
+        let slot = self.allocate();
+        let captures = &mut self.captures[slot * self.width..(slot + 1) * self.width];
+        captures.fill(Capture::new(None, None));
+        if let Some(capture) = captures.first_mut() {
+            capture.start = Some(offset);
+        }
+        slot
+    }
+
+    pub fn share(&mut self, slot: usize) -> usize {
+        self.refs[slot] += 1;
+        slot
+    }
+
+    pub fn release(&mut self, slot: usize) {
+        self.refs[slot] -= 1;
+        if self.refs[slot] == 0 {
+            self.free.push(slot);
+        }
+    }
+
+    // Returns a capture for the thread holding `slot` to change, first giving it a
+    // copy of the slot if it's shared.
+    pub fn capture_mut(&mut self, slot: &mut usize, index: usize) -> &mut Capture {
+        if self.refs[*slot] > 1 {
+            let copy = self.allocate();
+            self.captures.copy_within(
+                *slot * self.width..(*slot + 1) * self.width,
+                copy * self.width,
+            );
+            self.refs[*slot] -= 1;
+            *slot = copy;
+        }
+        &mut self.captures[*slot * self.width + index]
+    }
+
+    // Slots are allocated up front for as many threads as there can be: one for
+    // each state in the two queues, and one for each place in a counted loop. Every
+    // slot in use is held by at least one of those threads, so one is always free.
+    fn allocate(&mut self) -> usize {
+        let slot = self.free.pop().expect("ran out of capture slots");
+        self.refs[slot] = 1;
+        slot
+    }
+}
 
 pub struct Queue {
     seen: GrowSet,
     deque: VecDeque<usize>,
-    pub captures: Vec<Vec<Capture>>,
-    empty: Vec<Capture>,
+    // The capture slot held by the thread at each program counter.
+    slots: Vec<usize>,
 }
 
 pub enum End {
@@ -15,57 +105,97 @@ pub enum End {
 }
 
 impl Queue {
-    pub fn new(program_size: usize, capture_count: usize) -> Self {
+    pub fn new(program_size: usize) -> Self {
         Self {
             seen: GrowSet::with_capacity(program_size),
             deque: VecDeque::with_capacity(program_size),
-            captures: (0..program_size)
-                .map(|_x| {
-                    (0..capture_count)
-                        .map(|_y| Capture::new(None, None))
-                        .collect()
-                })
-                .collect(),
-            empty: (0..capture_count)
-                .map(|_x| Capture::new(None, None))
-                .collect(),
+            slots: vec![0; program_size],
         }
     }
 
+    // Returns the number of bytes a queue with the given dimensions allocates, without
+    // having to allocate it.
+    pub fn heap_size(program_size: usize) -> usize {
+        2 * program_size * size_of::<usize>()
+            + program_size * size_of::<usize>()
+            + program_size * size_of::<usize>()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.deque.is_empty()
     }
 
-    pub fn clear(&mut self) {
+    pub fn len(&self) -> usize {
+        self.deque.len()
+    }
+
+    // The program counters of the queued threads, in order.
+    #[cfg(feature = "trace")]
+    pub fn threads(&self) -> Vec<usize> {
+        self.deque.iter().copied().collect()
+    }
+
+    // Empties the queue, releasing the slots of every thread that was queued since it
+    // was last cleared.
+    pub fn clear(&mut self, slots: &mut Slots) {
+        for &pc in self.seen.iter() {
+            slots.release(self.slots[pc]);
+        }
         self.deque.clear();
         self.seen.clear();
     }
 
-    pub fn push_empty(&mut self, start: usize) {
+    // The slot held by the thread at `pc`.
+    pub fn slot(&self, pc: usize) -> usize {
+        self.slots[pc]
+    }
+
+    pub fn captures<'a>(&self, pc: usize, slots: &'a Slots) -> &'a [Capture] {
+        slots.get(self.slots[pc])
+    }
+
+    // Returns a capture for the thread at `pc` to change.
+    pub fn capture_mut<'a>(
+        &mut self,
+        pc: usize,
+        index: usize,
+        slots: &'a mut Slots,
+    ) -> &'a mut Capture {
+        slots.capture_mut(&mut self.slots[pc], index)
+    }
+
+    // The earliest offset at which a queued thread started.
+    pub fn earliest_start(&self, slots: &Slots) -> Option<usize> {
+        self.deque
+            .iter()
+            .filter_map(|&x| slots.start(self.slots[x]))
+            .min()
+    }
+
+    pub fn push_empty(&mut self, offset: usize, slots: &mut Slots) {
         if self.seen.contains(0) {
-            if start < self.captures[0][0].start.unwrap() {
-                self.captures[0].copy_from_slice(&self.empty);
-                self.captures[0][0].start = Some(start);
+            if Some(offset) < slots.start(self.slots[0]) {
+                slots.release(self.slots[0]);
+                self.slots[0] = slots.empty(offset);
             }
             return;
         }
 
         self.seen.add(0);
-        self.captures[0].clone_from(&self.empty);
-        self.captures[0][0].start = Some(start);
+        self.slots[0] = slots.empty(offset);
         self.deque.push_back(0);
     }
 
-    pub fn push(&mut self, end: End, pc: usize, captures: &[Capture]) {
+    // Queues a thread at `pc` sharing `slot`, or has the thread already there share it
+    // instead if it started earlier.
+    pub fn push(&mut self, end: End, pc: usize, slot: usize, slots: &mut Slots) {
         if self.seen.contains(pc) {
-            if captures[0].start.unwrap() < self.captures[pc][0].start.unwrap() {
-                self.captures[pc].copy_from_slice(captures);
-            }
+            self.replace(pc, slot, slots);
             return;
         }
 
         self.seen.add(pc);
-        self.captures[pc].copy_from_slice(captures);
+        self.slots[pc] = slots.share(slot);
 
         match end {
             End::Front => self.deque.push_front(pc),
@@ -73,24 +203,41 @@ impl Queue {
         }
     }
 
-    pub fn push_from_current(&mut self, end: End, pc: usize, capture_pc: usize) {
+    // Threads are queued in order of their starting offsets. This pushes a thread
+    // into its place in that order: ahead of any threads that started at the same
+    // offset for End::Front, and behind them for End::Back.
+    pub fn push_sorted(&mut self, end: End, pc: usize, slot: usize, slots: &mut Slots) {
         if self.seen.contains(pc) {
-            if self.captures[capture_pc][0].start.unwrap() < self.captures[pc][0].start.unwrap() {
-                for index in 0..self.captures[pc].len() {
-                    self.captures[pc][index] = self.captures[capture_pc][index];
-                    // FIXME
-                }
-            }
+            self.replace(pc, slot, slots);
             return;
         }
 
         self.seen.add(pc);
-        for index in 0..self.captures[pc].len() {
-            self.captures[pc][index] = self.captures[capture_pc][index]; // FIXME
-        }
-        match end {
-            End::Front => self.deque.push_front(pc),
-            End::Back => self.deque.push_back(pc),
+        self.slots[pc] = slots.share(slot);
+
+        let first = slots.start(slot);
+        let position = match end {
+            End::Front => self
+                .deque
+                .iter()
+                .position(|&x| slots.start(self.slots[x]) >= first),
+            End::Back => self
+                .deque
+                .iter()
+                .position(|&x| slots.start(self.slots[x]) > first),
+        };
+        self.deque.insert(position.unwrap_or(self.deque.len()), pc);
+    }
+
+    pub fn push_from_current(&mut self, end: End, pc: usize, capture_pc: usize, slots: &mut Slots) {
+        self.push(end, pc, self.slots[capture_pc], slots);
+    }
+
+    fn replace(&mut self, pc: usize, slot: usize, slots: &mut Slots) {
+        if slots.start(slot) < slots.start(self.slots[pc]) {
+            let shared = slots.share(slot);
+            slots.release(self.slots[pc]);
+            self.slots[pc] = shared;
         }
     }
 
@@ -98,3 +245,164 @@ impl Queue {
         self.deque.pop_front().unwrap()
     }
 }
+
+// The threads inside a counted loop, identified by the offset at which they
+// entered the loop. Entries are kept in priority order, as threads would be in
+// a queue, and at most one thread is kept per entry offset. Threads in unbounded
+// loops that have passed the minimum are merged and pinned at the minimum. Each
+// entry holds the capture slot of its thread.
+pub struct CountedLoop {
+    entries: VecDeque<(usize, usize, bool)>,
+    stepped: Option<usize>,
+}
+
+impl CountedLoop {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            stepped: None,
+        }
+    }
+
+    // As for Queue::heap_size.
+    pub fn heap_size(capacity: usize) -> usize {
+        capacity * size_of::<(usize, usize, bool)>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // As for Queue::earliest_start.
+    pub fn earliest_start(&self, slots: &Slots) -> Option<usize> {
+        self.entries.iter().filter_map(|x| slots.start(x.1)).min()
+    }
+
+    pub fn clear(&mut self, slots: &mut Slots) {
+        while let Some((_, slot, _)) = self.entries.pop_back() {
+            slots.release(slot);
+        }
+        self.stepped = None;
+    }
+
+    // Add a thread to the loop. Threads that arrive before the loop has been stepped
+    // at this offset take priority over the threads already inside it.
+    pub fn enter(&mut self, offset: usize, slot: usize, slots: &mut Slots) {
+        let before = self.stepped != Some(offset);
+
+        if let Some(index) = self.entries.iter().position(|x| x.0 == offset) {
+            let (_, existing, pinned) = self.entries[index];
+            let first = slots.start(slot);
+
+            // A thread pinned at this offset was already inside the loop, so a new thread
+            // reaching it first wins ties and takes its place in the priority order.
+            if pinned && before && first <= slots.start(existing) {
+                let shared = slots.share(slot);
+                slots.release(existing);
+                self.entries.remove(index);
+                self.entries.push_front((offset, shared, false));
+            } else if first < slots.start(existing) {
+                let shared = slots.share(slot);
+                slots.release(existing);
+                self.entries[index].1 = shared;
+            }
+            return;
+        }
+
+        let shared = slots.share(slot);
+        if before {
+            self.entries.push_front((offset, shared, false));
+        } else {
+            self.entries.push_back((offset, shared, false));
+        }
+    }
+
+    // Returns the slot of the thread that should leave the loop at this offset, if
+    // any. As with the queues, the thread that started earliest wins, with ties going
+    // to the thread with the highest priority.
+    pub fn exit(&mut self, offset: usize, minimum: usize, slots: &Slots) -> Option<usize> {
+        self.stepped = Some(offset);
+
+        let mut best: Option<usize> = None;
+        for &(entered, slot, _) in self.entries.iter() {
+            if offset - entered < minimum {
+                continue;
+            }
+            match best {
+                Some(b) if slots.start(b) <= slots.start(slot) => {}
+                _ => best = Some(slot),
+            }
+        }
+        best
+    }
+
+    // Returns the slot of the earliest-starting thread that can iterate past the
+    // byte at `offset`, if any.
+    pub fn survivor(&self, offset: usize, maximum: Option<usize>, slots: &Slots) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for &(entered, slot, _) in self.entries.iter() {
+            if maximum.is_some_and(|maximum| offset + 1 - entered > maximum) {
+                continue;
+            }
+            match best {
+                Some(b) if slots.start(b) <= slots.start(slot) => {}
+                _ => best = Some(slot),
+            }
+        }
+        best
+    }
+
+    // Advance every thread in the loop past the byte at `offset`.
+    pub fn advance(
+        &mut self,
+        offset: usize,
+        matched: bool,
+        minimum: usize,
+        maximum: Option<usize>,
+        slots: &mut Slots,
+    ) {
+        if !matched {
+            self.clear(slots);
+            return;
+        }
+
+        let next = offset + 1;
+        match maximum {
+            Some(maximum) => self.entries.retain(|&(entered, slot, _)| {
+                if next - entered > maximum {
+                    slots.release(slot);
+                    return false;
+                }
+                true
+            }),
+            None => {
+                // Once past the minimum, the iteration count no longer matters, so
+                // every such thread is merged into the best one, which is then
+                // pinned at the minimum.
+                let mut best: Option<usize> = None;
+                for (index, &(entered, slot, _)) in self.entries.iter().enumerate() {
+                    if next - entered < minimum {
+                        continue;
+                    }
+                    match best {
+                        Some(b) if slots.start(self.entries[b].1) <= slots.start(slot) => {}
+                        _ => best = Some(index),
+                    }
+                }
+
+                if let Some(best) = best {
+                    self.entries[best] = (next - minimum, self.entries[best].1, true);
+                    let mut index = 0;
+                    self.entries.retain(|&(entered, slot, _)| {
+                        let keep = index == best || next - entered < minimum;
+                        index += 1;
+                        if !keep {
+                            slots.release(slot);
+                        }
+                        keep
+                    });
+                }
+            }
+        }
+    }
+}
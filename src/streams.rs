@@ -0,0 +1,125 @@
+// Scanning input that arrives asynchronously: read from a tokio reader, or sent to a
+// sink as Bytes. Either way the input is pushed to a scratch space as it arrives, and
+// a handler that stops matching stops the stream.
+use crate::{ContinueMatching, Database, MatchHandler, Scratch};
+use bytes::Bytes;
+use futures_sink::Sink;
+use std::error;
+use std::fmt;
+use std::io;
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// How many bytes are read at a time.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Reads `reader` to its end as a single stream, reporting the matches of the
+/// expressions in `database` to `handler`. Returns `ContinueMatching::No` as soon as
+/// the handler stops matching, without reading the rest.
+///
+/// The scratch space the input is pushed to is kept across reads, so the future isn't
+/// `Send`: block on it, or spawn it on a `LocalSet`.
+pub async fn scan_async_read(
+    reader: impl AsyncRead,
+    database: &Database,
+    handler: &mut dyn MatchHandler,
+) -> io::Result<ContinueMatching> {
+    let mut reader = pin!(reader);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut scratch = database.make_scratch(handler);
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        if scratch.push(&buffer[..read]) == ContinueMatching::No {
+            return Ok(ContinueMatching::No);
+        }
+    }
+    scratch.push_finish();
+    Ok(ContinueMatching::Yes)
+}
+
+/// Why a `ScratchSink` takes no more input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SinkError {
+    Stopped, // The scratch space's handler stopped matching.
+    Closed,  // The sink was closed, which finished the stream.
+}
+
+impl error::Error for SinkError {}
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SinkError::Stopped => write!(f, "the match handler stopped matching"),
+            SinkError::Closed => write!(f, "the sink is closed"),
+        }
+    }
+}
+
+/// A `Sink` that pushes the `Bytes` sent to it to a scratch space, as one stream.
+/// Closing the sink finishes the stream. Once the scratch space's handler stops
+/// matching, the sink takes no more input, and fails with `SinkError::Stopped`; once
+/// it's closed, it fails with `SinkError::Closed`.
+pub struct ScratchSink<'a> {
+    // Only taken while the scratch space is finished.
+    scratch: Option<Scratch<'a>>,
+    stopped: bool,
+    closed: bool,
+}
+
+impl<'a> ScratchSink<'a> {
+    pub fn new(scratch: Scratch<'a>) -> Self {
+        Self {
+            scratch: Some(scratch),
+            stopped: false,
+            closed: false,
+        }
+    }
+
+    /// Returns the scratch space, which has been reset if the sink was closed.
+    pub fn into_inner(self) -> Scratch<'a> {
+        self.scratch.unwrap()
+    }
+
+    fn check(&self) -> Result<(), SinkError> {
+        if self.stopped {
+            return Err(SinkError::Stopped);
+        }
+        if self.closed {
+            return Err(SinkError::Closed);
+        }
+        Ok(())
+    }
+}
+
+impl Sink<Bytes> for ScratchSink<'_> {
+    type Error = SinkError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), SinkError>> {
+        Poll::Ready(self.check())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), SinkError> {
+        let sink = self.get_mut();
+        sink.check()?;
+        if sink.scratch.as_mut().unwrap().push(&item) == ContinueMatching::No {
+            sink.stopped = true;
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), SinkError>> {
+        Poll::Ready(self.check())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), SinkError>> {
+        let sink = self.get_mut();
+        if !sink.stopped && !sink.closed {
+            sink.scratch = sink.scratch.take().map(|x| x.finish());
+            sink.closed = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -10,8 +10,7 @@ impl ShouldNotMatchHandler {
 
 impl MatchHandler for ShouldNotMatchHandler {
     fn on_match(&mut self, _id: usize, _matches: &[Capture]) -> ContinueMatching {
-        assert!(false);
-        ContinueMatching::No
+        panic!("unexpected match");
     }
 }
 
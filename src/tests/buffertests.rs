@@ -0,0 +1,175 @@
+use crate::*;
+
+type Data = Vec<Option<Vec<u8>>>;
+
+struct DataHandler {
+    matches: Vec<(Capture, Data)>,
+}
+
+impl MatchHandler for DataHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        panic!("matches should be reported with their data");
+    }
+
+    fn on_match_with_data(&mut self, info: &MatchInfo, data: &[Option<&[u8]>]) -> ContinueMatching {
+        assert_eq!(info.captures.len(), data.len());
+        self.matches.push((
+            info.captures[0],
+            data.iter().map(|x| x.map(|x| x.to_vec())).collect(),
+        ));
+        ContinueMatching::Yes
+    }
+}
+
+fn database(expression: &str) -> Result<Database, Error> {
+    Ok(DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, expression)
+                .mode(MatchMode::All(Submatch::All))
+                .build()?,
+        )
+        .build())
+}
+
+fn run(
+    expression: &str,
+    text: &[u8],
+    limit: usize,
+    block: usize,
+) -> Result<Vec<(Capture, Data)>, Error> {
+    let database = database(expression)?;
+    let mut handler = DataHandler { matches: vec![] };
+    let mut scratch = database
+        .make_scratch(&mut handler)
+        .with_capture_buffer(limit);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+fn data(data: &[Option<&str>]) -> Data {
+    data.iter()
+        .map(|x| x.map(|x| x.as_bytes().to_vec()))
+        .collect()
+}
+
+#[test]
+fn test_captured_data() -> Result<(), Error> {
+    let text = b"xx name=alice; yy name=bob;";
+    for block in [1, 2, 3, 4, 5, 6, 7, text.len()] {
+        assert_eq!(
+            run(r"name=(\w+);", text, 100, block)?,
+            vec![
+                (
+                    Capture::new(Some(3), Some(14)),
+                    data(&[Some("name=alice;"), Some("alice")])
+                ),
+                (
+                    Capture::new(Some(18), Some(27)),
+                    data(&[Some("name=bob;"), Some("bob")])
+                ),
+            ]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unanchored_data() -> Result<(), Error> {
+    // Expressions without prefixes run from every offset.
+    let text = b"a1 b22 c333";
+    for block in [1, 2, 3, text.len()] {
+        let matches = run(r"(?-u)[a-z]([0-9]+)\b", text, 100, block)?;
+        let groups: Vec<_> = matches.into_iter().map(|x| x.1[1].clone()).collect();
+        assert_eq!(
+            groups,
+            vec![
+                Some(b"1".to_vec()),
+                Some(b"22".to_vec()),
+                Some(b"333".to_vec())
+            ]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_limit() -> Result<(), Error> {
+    // Captures longer than the limit are reported without their bytes, whether or
+    // not they were still buffered.
+    let text = format!("start{}id=42;", "x".repeat(100));
+    for block in [1, 7, text.len()] {
+        assert_eq!(
+            run(r"start\w*id=(\d+);", text.as_bytes(), 20, block)?,
+            vec![(Capture::new(Some(0), Some(111)), data(&[None, Some("42")]))]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_missing_group() -> Result<(), Error> {
+    assert_eq!(
+        run(r"a(b)?(c)", b"ac", 10, 1)?,
+        vec![(
+            Capture::new(Some(0), Some(2)),
+            data(&[Some("ac"), None, Some("c")])
+        )]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_decoded_data() -> Result<(), Error> {
+    // The bytes are those matched; the offsets are those pushed.
+    let database = database(r"id=(\d+)!")?;
+    let mut handler = DataHandler { matches: vec![] };
+    let mut scratch = database
+        .make_scratch(&mut handler)
+        .with_capture_buffer(100)
+        .with_decoder(Box::new(PercentDecoder::new()));
+    for chunk in b"%69%64%3D%34%32!".chunks(2) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    assert_eq!(
+        handler.matches,
+        vec![(
+            Capture::new(Some(0), Some(16)),
+            data(&[Some("id=42!"), Some("42")])
+        )]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_without_buffer() -> Result<(), Error> {
+    struct Handler {
+        matches: usize,
+    }
+
+    impl MatchHandler for Handler {
+        fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+            self.matches += 1;
+            ContinueMatching::Yes
+        }
+
+        fn on_match_with_data(
+            &mut self,
+            _info: &MatchInfo,
+            _data: &[Option<&[u8]>],
+        ) -> ContinueMatching {
+            panic!("no data without a buffer");
+        }
+    }
+
+    let database = database("foo")?;
+    let mut handler = Handler { matches: 0 };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"foo foo");
+    scratch.finish();
+    assert_eq!(handler.matches, 2);
+    Ok(())
+}
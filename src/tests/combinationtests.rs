@@ -0,0 +1,179 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches
+            .push((id, captures[0].start.unwrap(), captures[0].end.unwrap()));
+        ContinueMatching::Yes
+    }
+}
+
+fn database(rules: &[(usize, &str)]) -> Result<Database, Error> {
+    let mut builder = DatabaseBuilder::new()
+        .with_expression(Regex::new(1, "foo").build()?)
+        .with_expression(Regex::new(2, "bar").build()?)
+        .with_expression(Regex::new(3, "baz").build()?);
+    for &(id, expression) in rules {
+        builder = builder.with_combination(CombinationRule::new(id, expression));
+    }
+    builder.try_build()
+}
+
+fn run(rules: &[(usize, &str)], text: &str) -> Result<Vec<(usize, usize, usize)>, Error> {
+    let database = database(rules)?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.as_bytes().chunks(3) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+fn combinations(rules: &[(usize, &str)], text: &str) -> Result<Vec<(usize, usize)>, Error> {
+    Ok(run(rules, text)?
+        .into_iter()
+        .filter(|x| x.0 >= 10)
+        .map(|x| (x.0, x.2))
+        .collect())
+}
+
+#[test]
+fn test_and() -> Result<(), Error> {
+    assert_eq!(
+        run(&[(10, "1 & 2")], "foo and bar")?,
+        vec![(1, 0, 3), (2, 8, 11), (10, 11, 11)]
+    );
+    assert_eq!(
+        combinations(&[(10, "1 & 2")], "bar and foo")?,
+        vec![(10, 11)]
+    );
+    assert!(combinations(&[(10, "1 & 2")], "foo and foo")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_or() -> Result<(), Error> {
+    // Reported once, when it first becomes true.
+    assert_eq!(
+        combinations(&[(10, "1 | 2")], "bar, foo, bar")?,
+        vec![(10, 3)]
+    );
+    assert!(combinations(&[(10, "1 | 2")], "baz")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_not() -> Result<(), Error> {
+    // Rules that need an expression not to match are settled at the end.
+    assert_eq!(
+        combinations(&[(10, "1 & !3")], "foo and bar")?,
+        vec![(10, 11)]
+    );
+    assert!(combinations(&[(10, "1 & !3")], "foo and baz")?.is_empty());
+    assert!(combinations(&[(10, "1 & !3")], "baz and foo")?.is_empty());
+    assert_eq!(combinations(&[(10, "!3")], "")?, vec![(10, 0)]);
+
+    // Unless they're true either way.
+    assert_eq!(
+        combinations(&[(10, "1 | !3")], "foo and bar")?,
+        vec![(10, 3)]
+    );
+    assert_eq!(
+        combinations(&[(10, "!(!1 & !2)")], "foo and bar")?,
+        vec![(10, 3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_precedence() -> Result<(), Error> {
+    assert_eq!(combinations(&[(10, "1 | 2 & 3")], "foo")?, vec![(10, 3)]);
+    assert!(combinations(&[(10, "(1 | 2) & 3")], "foo")?.is_empty());
+    assert_eq!(
+        combinations(&[(10, "(1 | 2) & 3")], "bar baz")?,
+        vec![(10, 7)]
+    );
+    assert_eq!(combinations(&[(10, "!!1")], "foo")?, vec![(10, 3)]);
+    Ok(())
+}
+
+#[test]
+fn test_several_rules() -> Result<(), Error> {
+    assert_eq!(
+        combinations(
+            &[(10, "1 & 2"), (11, "2 & !3"), (12, "1 & 3"), (13, "!1")],
+            "bar foo"
+        )?,
+        vec![(10, 7), (11, 7)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_reset() -> Result<(), Error> {
+    let database = database(&[(10, "1 & 2"), (11, "!3")])?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"foo bar");
+    scratch = scratch.finish();
+    scratch.push(b"bar baz");
+    scratch = scratch.finish();
+    scratch.push(b"bar foo");
+    scratch.finish();
+
+    let combinations: Vec<_> = handler
+        .matches
+        .iter()
+        .filter(|x| x.0 >= 10)
+        .map(|x| (x.0, x.2))
+        .collect();
+    assert_eq!(combinations, vec![(10, 7), (11, 7), (10, 7), (11, 7)]);
+    Ok(())
+}
+
+#[test]
+fn test_decoded() -> Result<(), Error> {
+    let database = database(&[(10, "1 & !3")])?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database
+        .make_scratch(&mut handler)
+        .with_decoder(Box::new(HexDecoder::new()));
+    scratch.push(b"666f6f20626172");
+    scratch.finish();
+    assert_eq!(handler.matches, vec![(1, 0, 6), (2, 8, 14), (10, 14, 14)]);
+    Ok(())
+}
+
+#[test]
+fn test_errors() -> Result<(), Error> {
+    for expression in ["", "1 &", "1 2", "(1 | 2", "1 | 2)", "!", "a", "1 && 2"] {
+        assert!(
+            matches!(
+                database(&[(10, expression)]),
+                Err(Error::InvalidExpression(_))
+            ),
+            "{}",
+            expression
+        );
+    }
+
+    // Rules can only refer to expressions, and can't share their ids.
+    assert!(matches!(
+        database(&[(10, "1 & 4")]),
+        Err(Error::InvalidExpression(_))
+    ));
+    assert!(matches!(
+        database(&[(10, "1"), (11, "10")]),
+        Err(Error::InvalidExpression(_))
+    ));
+    assert!(matches!(
+        database(&[(3, "1 & 2")]),
+        Err(Error::InvalidExpression(_))
+    ));
+    Ok(())
+}
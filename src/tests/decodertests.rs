@@ -0,0 +1,132 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<Vec<(usize, usize)>>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push(
+            captures
+                .iter()
+                .map(|x| (x.start.unwrap(), x.end.unwrap()))
+                .collect(),
+        );
+        ContinueMatching::Yes
+    }
+}
+
+fn decoders(names: &[&str]) -> Vec<Box<dyn Decoder>> {
+    names
+        .iter()
+        .map(|name| -> Box<dyn Decoder> {
+            match *name {
+                "base64" => Box::new(Base64Decoder::new()),
+                "hex" => Box::new(HexDecoder::new()),
+                "percent" => Box::new(PercentDecoder::new()),
+                "chunked" => Box::new(ChunkedDecoder::new()),
+                _ => unreachable!(),
+            }
+        })
+        .collect()
+}
+
+// Runs an expression over some text through the named decoders, pushing the text in
+// blocks of various sizes to make sure the results don't depend on where blocks split.
+fn run(expression: &str, names: &[&str], text: &str) -> Result<Vec<Vec<(usize, usize)>>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(Regex::new(1, expression).build()?)
+        .build();
+    let mut results = vec![];
+
+    for size in [1, 2, 3, 5, 7, 11, 4096] {
+        let mut handler = CollectingHandler { matches: vec![] };
+        let mut scratch = database.make_scratch(&mut handler);
+        for decoder in decoders(names) {
+            scratch = scratch.with_decoder(decoder);
+        }
+        for block in text.as_bytes().chunks(size) {
+            scratch.push(block);
+        }
+        scratch.finish();
+        results.push(handler.matches);
+    }
+
+    for result in results.iter() {
+        assert_eq!(result, &results[0]);
+    }
+    Ok(results.remove(0))
+}
+
+#[test]
+fn test_base64_decoder() -> Result<(), Error> {
+    assert_eq!(
+        run("password", &["base64"], "cGFzc3dvcmQ=")?,
+        vec![vec![(0, 11)]]
+    );
+    assert_eq!(
+        run("password", &["base64"], "dXNlcj1w\r\nYXNzd29yZDs=")?,
+        vec![vec![(6, 20)]]
+    );
+    assert_eq!(
+        run("sword", &["base64"], "cGFzc3dvcmQ=")?,
+        vec![vec![(4, 11)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_hex_decoder() -> Result<(), Error> {
+    assert_eq!(
+        run("pass(wo)rd", &["hex"], "id=70617373 776f7264;")?,
+        vec![vec![(3, 20), (12, 16)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_percent_decoder() -> Result<(), Error> {
+    assert_eq!(
+        run("pass(wo)rd", &["percent"], "q=%70ass%77ord&x")?,
+        vec![vec![(2, 14), (8, 12)]]
+    );
+    assert_eq!(run("%zz", &["percent"], "100%zz")?, vec![vec![(3, 6)]]);
+    assert_eq!(run("a%", &["percent"], "a%")?, vec![vec![(0, 2)]]);
+    Ok(())
+}
+
+#[test]
+fn test_chunked_decoder() -> Result<(), Error> {
+    let text = "4\r\npass\r\n4;ext=1\r\nword\r\n0\r\nTrailer: password\r\n\r\npassword";
+    assert_eq!(run("password", &["chunked"], text)?, vec![vec![(3, 22)]]);
+    assert!(run("4", &["chunked"], text)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_chained_decoders() -> Result<(), Error> {
+    // Base64 sent in chunks, split in the middle of a group.
+    let text = "5\r\ncGFzc\r\n7\r\n3dvcmQ=\r\n0\r\n\r\n";
+    assert_eq!(
+        run("password", &["chunked", "base64"], text)?,
+        vec![vec![(3, 19)]]
+    );
+    // Percent-encoded hex.
+    assert_eq!(
+        run("password", &["percent", "hex"], "%37%30617373776f7264")?,
+        vec![vec![(0, 20)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_decoder_offsets_across_long_streams() -> Result<(), Error> {
+    let mut text = "AAAA".repeat(10000);
+    text.push_str("cGFzc3dvcmQ=");
+    text.push_str(&"AAAA".repeat(10000));
+    assert_eq!(
+        run("password", &["base64"], &text)?,
+        vec![vec![(40000, 40011)]]
+    );
+    Ok(())
+}
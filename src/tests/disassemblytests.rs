@@ -0,0 +1,70 @@
+use crate::*;
+
+#[test]
+fn test_disassemble() -> Result<(), Error> {
+    let regex = Regex::new(7, r##"(?P<word>[a-fx]+)\b\n"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    let listing = regex.disassemble();
+    println!("{}", listing);
+
+    assert!(listing.starts_with("expression 7\n"));
+    assert!(listing.contains("anchored: false\n"));
+    assert!(listing.contains("captures: 2 1=word\n"));
+    assert!(listing.contains("class [a-fx]"));
+    assert!(listing.contains("assert ByteWordBoundary"));
+    assert!(listing.contains("byte '\\x0a'"));
+    assert!(listing.contains("start 1"));
+    assert!(listing.contains("end 1"));
+    assert_eq!(
+        listing.lines().filter(|x| x.starts_with("00")).count(),
+        regex.program_size()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_disassemble_counted_repetition() -> Result<(), Error> {
+    let regex = Regex::new(1, r##"^[^\]\\-]{2,40}"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    let listing = regex.disassemble();
+    println!("{}", listing);
+
+    assert!(listing.contains("anchored: true\n"));
+    assert!(listing.contains(r"repeat.enter 0 [\x00-,.-\[\^-\xff]{2,40} exit 4"));
+    assert!(listing.contains("repeat.next 0"));
+    assert!(listing.contains("repeat.consume 0"));
+    Ok(())
+}
+
+#[test]
+fn test_disassemble_prefixes() -> Result<(), Error> {
+    let regex = Regex::new(1, r##"foo"bar|baz"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    let listing = regex.disassemble();
+    assert!(listing.contains(r#"prefixes: "foo\"bar" "baz""#));
+    Ok(())
+}
+
+#[test]
+fn test_to_dot() -> Result<(), Error> {
+    let regex = Regex::new(3, r##"a(b|")*"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    let dot = regex.to_dot();
+    println!("{}", dot);
+
+    assert!(dot.starts_with("digraph expression_3 {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains(r#"0 -> 1 [label="a"];"#));
+    assert!(dot.contains(r#"[label="1"];"#));
+    assert!(dot.contains(r#"[label="2"];"#));
+    assert!(dot.contains(r#"3 -> 4 [label="[\"b]"];"#));
+    assert_eq!(
+        dot.lines().filter(|x| x.contains(" [label=\"00")).count(),
+        regex.program_size()
+    );
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches
+            .push((id, captures[0].start.unwrap(), captures[0].end.unwrap()));
+        ContinueMatching::Yes
+    }
+}
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn utf16be(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars().map(|x| x as u32 as u8).collect()
+}
+
+fn run(expression: &str, encoding: Encoding, text: &[u8]) -> Result<Vec<(usize, usize)>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, expression)
+                .mode(MatchMode::First(Submatch::Expression))
+                .encoding(encoding)
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+    Ok(handler.matches.iter().map(|x| (x.1, x.2)).collect())
+}
+
+#[test]
+fn test_utf16_literals() -> Result<(), Error> {
+    let text = "say héllo";
+    assert_eq!(
+        run("héllo", Encoding::UTF16LE, &utf16le(text))?,
+        vec![(8, 18)]
+    );
+    assert_eq!(
+        run("héllo", Encoding::UTF16BE, &utf16be(text))?,
+        vec![(8, 18)]
+    );
+
+    // The wrong byte order doesn't match. Note that text misaligned by one byte can:
+    // in "say héllo", the zero byte of the space followed by "héllo" in little-endian
+    // is "héllo" in big-endian.
+    assert_eq!(run("héllo", Encoding::UTF16LE, &utf16be("héllo"))?, vec![]);
+    assert_eq!(run("héllo", Encoding::UTF16BE, &utf16le("héllo"))?, vec![]);
+    assert_eq!(
+        run("héllo", Encoding::UTF16BE, &utf16le(text))?,
+        vec![(7, 17)]
+    );
+
+    // Nor does the wrong encoding.
+    assert_eq!(run("héllo", Encoding::UTF16LE, text.as_bytes())?, vec![]);
+    Ok(())
+}
+
+#[test]
+fn test_utf16_case_insensitive() -> Result<(), Error> {
+    assert_eq!(
+        run("(?i)héllo", Encoding::UTF16LE, &utf16le("HÉLLO"))?,
+        vec![(0, 10)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_utf16_classes() -> Result<(), Error> {
+    let text = "hi привет!";
+    assert_eq!(
+        run("[а-я]+!", Encoding::UTF16LE, &utf16le(text))?,
+        vec![(6, 20)]
+    );
+    assert_eq!(
+        run("[а-я]+!", Encoding::UTF16BE, &utf16be(text))?,
+        vec![(6, 20)]
+    );
+
+    // A range crossing a high byte boundary.
+    let text = "\u{1ff}\u{200}\u{3ff}";
+    assert_eq!(
+        run("^[\u{1ff}-\u{3ff}]+$", Encoding::UTF16LE, &utf16le(text))?,
+        vec![(0, 6)]
+    );
+    assert_eq!(
+        run("[\u{1ff}-\u{3ff}]", Encoding::UTF16LE, &utf16le("\u{400}"))?,
+        vec![]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_utf16_surrogates() -> Result<(), Error> {
+    let text = "a😀b";
+    assert_eq!(
+        run("a😀b", Encoding::UTF16LE, &utf16le(text))?,
+        vec![(0, 8)]
+    );
+    assert_eq!(
+        run("^a.b$", Encoding::UTF16LE, &utf16le(text))?,
+        vec![(0, 8)]
+    );
+    assert_eq!(
+        run("^a.b$", Encoding::UTF16BE, &utf16be(text))?,
+        vec![(0, 8)]
+    );
+    assert_eq!(
+        run(
+            r"a[\x{1F600}-\x{1F64F}]b",
+            Encoding::UTF16BE,
+            &utf16be(text)
+        )?,
+        vec![(0, 8)]
+    );
+    assert_eq!(
+        run(
+            r"a[\x{1F601}-\x{1F64F}]b",
+            Encoding::UTF16BE,
+            &utf16be(text)
+        )?,
+        vec![]
+    );
+
+    // Ranges spanning many high surrogates.
+    let text = "\u{10000}\u{10400}\u{1f600}\u{10fffd}";
+    assert_eq!(
+        run(
+            r"^[\x{10000}-\x{10FFFF}]{4}$",
+            Encoding::UTF16LE,
+            &utf16le(text)
+        )?,
+        vec![(0, 16)]
+    );
+    assert_eq!(
+        run(
+            r"^[\x{10001}-\x{10FFFF}]",
+            Encoding::UTF16LE,
+            &utf16le(text)
+        )?,
+        vec![]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_latin1() -> Result<(), Error> {
+    let text = latin1("un café");
+    assert_eq!(run("café", Encoding::Latin1, &text)?, vec![(3, 7)]);
+    assert_eq!(run("caf[à-ÿ]", Encoding::Latin1, &text)?, vec![(3, 7)]);
+    assert_eq!(run(r"caf\w", Encoding::Latin1, &text)?, vec![(3, 7)]);
+    assert_eq!(run("(?i)CAFÉ", Encoding::Latin1, &text)?, vec![(3, 7)]);
+
+    // Characters outside of Latin-1 never match.
+    assert_eq!(run("caf€", Encoding::Latin1, &text)?, vec![]);
+    assert_eq!(run("caf(?:€|é)", Encoding::Latin1, &text)?, vec![(3, 7)]);
+    Ok(())
+}
+
+#[test]
+fn test_all_encodings_at_once() -> Result<(), Error> {
+    let mut builder = DatabaseBuilder::new();
+    for encoding in [
+        Encoding::UTF8,
+        Encoding::UTF16LE,
+        Encoding::UTF16BE,
+        Encoding::Latin1,
+    ] {
+        builder = builder.with_expression(Regex::new(1, "héllo").encoding(encoding).build()?);
+    }
+    let database = builder.build();
+
+    let mut text = vec![];
+    text.extend("héllo ".as_bytes());
+    text.extend(utf16le("héllo "));
+    text.extend(utf16be("héllo"));
+    text.extend(latin1(" héllo"));
+
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(&text);
+    scratch.finish();
+
+    handler.matches.sort();
+    assert_eq!(
+        handler.matches,
+        vec![(1, 0, 6), (1, 7, 17), (1, 19, 29), (1, 30, 35)]
+    );
+    Ok(())
+}
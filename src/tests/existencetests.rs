@@ -0,0 +1,166 @@
+use crate::*;
+
+// The id, start, end and edits of a match.
+type Match = (usize, Option<usize>, usize, usize);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        assert_eq!(info.captures.len(), 1);
+        self.matches.push((
+            info.id,
+            info.captures[0].start,
+            info.captures[0].end.unwrap(),
+            info.edits,
+        ));
+        ContinueMatching::Yes
+    }
+}
+
+fn run(regexes: Vec<Regex>, text: &[u8], block: usize) -> Result<Vec<Match>, Error> {
+    let mut builder = DatabaseBuilder::new();
+    for regex in regexes {
+        builder = builder.with_expression(regex.build()?);
+    }
+    let database = builder.build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+#[test]
+fn test_existence() -> Result<(), Error> {
+    let text = b"xx foooo yy foo";
+    for mode in [
+        MatchMode::First(Submatch::None),
+        MatchMode::All(Submatch::None),
+    ] {
+        for block in [1, 2, 3, 4, 5, 6, 7, text.len()] {
+            // Reported once, as soon as the first match is certain.
+            assert_eq!(
+                run(vec![Regex::new(1, "fo+").mode(mode)], text, block)?,
+                vec![(1, None, 5, 0)]
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_existence_several() -> Result<(), Error> {
+    let text = b"a1 b22 c333 a1";
+    for block in [1, 2, 3, text.len()] {
+        let mut matches = run(
+            vec![
+                Regex::new(1, "[a-c][0-9]{2}").mode(MatchMode::All(Submatch::None)),
+                Regex::new(2, "a1").mode(MatchMode::All(Submatch::None)),
+                Regex::new(3, "[0-9]{4}").mode(MatchMode::All(Submatch::None)),
+            ],
+            text,
+            block,
+        )?;
+        matches.sort();
+        assert_eq!(matches, vec![(1, None, 6, 0), (2, None, 2, 0)]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_existence_at_end() -> Result<(), Error> {
+    let text = b"foo foo";
+    for block in [1, 2, 3, text.len()] {
+        assert_eq!(
+            run(
+                vec![Regex::new(1, "foo$").mode(MatchMode::First(Submatch::None))],
+                text,
+                block
+            )?,
+            vec![(1, None, 7, 0)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_existence_counted() -> Result<(), Error> {
+    let text = "a".repeat(50);
+    for block in [1, 7, text.len()] {
+        assert_eq!(
+            run(
+                vec![Regex::new(1, "a{20,30}")
+                    .counted_repetition_threshold(2)
+                    .mode(MatchMode::All(Submatch::None))],
+                text.as_bytes(),
+                block
+            )?,
+            vec![(1, None, 20, 0)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_existence_approximate() -> Result<(), Error> {
+    assert_eq!(
+        run(
+            vec![Regex::new(1, "hello")
+                .max_edits(1)
+                .mode(MatchMode::All(Submatch::None))],
+            b"say helo, hello",
+            3
+        )?,
+        vec![(1, None, 8, 1)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_existence_groups() -> Result<(), Error> {
+    // Groups aren't tracked, even when asked for.
+    let regex = Regex::new(1, "(a)(b)?")
+        .mode(MatchMode::First(Submatch::None))
+        .build()?;
+    assert!(regex.disassemble().contains("captures: 0"));
+    assert!(!regex.disassemble().contains("start 1"));
+    Ok(())
+}
+
+#[test]
+fn test_existence_scratch_size() -> Result<(), Error> {
+    let size = |submatch| -> Result<usize, Error> {
+        Ok(Regex::new(1, "foo(bar)+")
+            .mode(MatchMode::First(submatch))
+            .build()?
+            .scratch_size())
+    };
+    assert!(size(Submatch::None)? < size(Submatch::Expression)?);
+    Ok(())
+}
+
+#[test]
+fn test_first_reported_once() -> Result<(), Error> {
+    // Expressions started by their prefixes are disabled after their first match,
+    // too.
+    for text in [&b"xfoofoo"[..], b"foo foo foo"] {
+        for block in [1, 2, 3, 4, text.len()] {
+            let matches = run(
+                vec![Regex::new(1, "fo+").mode(MatchMode::First(Submatch::Expression))],
+                text,
+                block,
+            )?;
+            assert_eq!(matches.len(), 1, "{:?} {}", text, block);
+        }
+    }
+    Ok(())
+}
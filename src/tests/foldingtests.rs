@@ -0,0 +1,116 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches
+            .push((id, captures[0].start.unwrap(), captures[0].end.unwrap()));
+        ContinueMatching::Yes
+    }
+}
+
+fn insensitive(id: usize, expression: &str) -> Result<CompiledRegex, Error> {
+    Regex::new(id, expression)
+        .encoding(Encoding::Byte)
+        .case_sensitive(false)
+        .mode(MatchMode::All(Submatch::Expression))
+        .build()
+}
+
+fn run(database: &Database, blocks: &[&str]) -> Vec<(usize, usize, usize)> {
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for block in blocks {
+        scratch.push(block.as_bytes());
+    }
+    scratch.finish();
+    handler.matches.sort();
+    handler.matches
+}
+
+#[test]
+fn test_folded_program() -> Result<(), Error> {
+    let compiled = Regex::new(1, "hello world")
+        .encoding(Encoding::Byte)
+        .case_sensitive(false)
+        .optimize(false)
+        .build()?;
+    assert!(compiled
+        .program()
+        .iter()
+        .all(|x| !matches!(x, Instruction::CharClass(_))));
+    assert!(compiled
+        .program()
+        .iter()
+        .any(|x| matches!(x, Instruction::FoldedByte(b'h'))));
+    assert!(compiled
+        .disassemble()
+        .contains("prefixes: (case-insensitive) \"hello world\"\n"));
+    assert!(compiled.disassemble().contains("folded byte 'Hh'"));
+
+    // The optimizer merges the folded bytes into runs.
+    let listing = insensitive(1, "hello world")?.disassemble();
+    assert!(listing.contains("literal \"(?i)hello\""));
+    assert!(listing.contains("byte ' '"));
+    assert!(listing.contains("literal \"(?i)world\""));
+    Ok(())
+}
+
+#[test]
+fn test_folded_matching() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(insensitive(1, "hello world")?)
+        .build();
+    let text = "xx HeLLo WoRLD yy hello world";
+    assert_eq!(run(&database, &[text]), vec![(1, 3, 14), (1, 18, 29)]);
+
+    // Prefixes that start in one block and end in another.
+    let blocks: Vec<String> = text.chars().map(|x| x.to_string()).collect();
+    let blocks: Vec<&str> = blocks.iter().map(|x| x.as_str()).collect();
+    assert_eq!(run(&database, &blocks), vec![(1, 3, 14), (1, 18, 29)]);
+    assert_eq!(run(&database, &["xx HeL", "Lo WoRLD"]), vec![(1, 3, 14)]);
+    Ok(())
+}
+
+#[test]
+fn test_folded_and_exact_prefixes() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, "Hello")
+                .encoding(Encoding::Byte)
+                .mode(MatchMode::All(Submatch::Expression))
+                .build()?,
+        )
+        .with_expression(insensitive(2, "hello")?)
+        .with_expression(insensitive(3, "h[e3]llo")?)
+        .build();
+    assert_eq!(
+        run(&database, &["hello HEL", "LO Hello h3LLo"]),
+        vec![
+            (1, 12, 17),
+            (2, 0, 5),
+            (2, 6, 11),
+            (2, 12, 17),
+            (3, 0, 5),
+            (3, 6, 11),
+            (3, 12, 17),
+            (3, 18, 23)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_folded_prefixes_stay_small() -> Result<(), Error> {
+    let sensitive = DatabaseBuilder::new()
+        .with_expression(Regex::new(1, "abcdefgh").encoding(Encoding::Byte).build()?)
+        .build();
+    let insensitive = DatabaseBuilder::new()
+        .with_expression(insensitive(1, "abcdefgh")?)
+        .build();
+    assert_eq!(insensitive.heap_size(), sensitive.heap_size());
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.matches.push((
+            info.captures[0].start.unwrap(),
+            info.captures[0].end.unwrap(),
+            info.edits,
+        ));
+        ContinueMatching::Yes
+    }
+}
+
+struct CaptureHandler {
+    captures: Vec<Vec<Capture>>,
+}
+
+impl MatchHandler for CaptureHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.captures.push(captures.to_vec());
+        ContinueMatching::Yes
+    }
+}
+
+fn run_with(regex: Regex, text: &[u8], block: usize) -> Result<Vec<(usize, usize, usize)>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(regex.build()?)
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+fn run(expression: &str, edits: usize, text: &str) -> Result<Vec<(usize, usize, usize)>, Error> {
+    run_with(
+        Regex::new(1, expression).max_edits(edits),
+        text.as_bytes(),
+        usize::MAX,
+    )
+}
+
+#[test]
+fn test_exact() -> Result<(), Error> {
+    assert_eq!(run("hello", 0, "say hello")?, vec![(4, 9, 0)]);
+
+    // Shorter matches with more edits overlap the exact one, and aren't reported.
+    assert_eq!(run("hello", 1, "say hello")?, vec![(4, 9, 0)]);
+    Ok(())
+}
+
+#[test]
+fn test_ties() -> Result<(), Error> {
+    // Of the matches that overlap, the one with the fewest edits is reported, even
+    // when a longer one needs more.
+    assert_eq!(run("hello", 1, "hhello")?, vec![(1, 6, 0)]);
+
+    // Then the longest.
+    assert_eq!(run("hello|llo", 1, "hello")?, vec![(0, 5, 0)]);
+    assert_eq!(run("llo|hello", 1, "hello")?, vec![(0, 5, 0)]);
+    assert_eq!(run("abc", 1, "abxc")?, vec![(0, 4, 1)]);
+    Ok(())
+}
+
+#[test]
+fn test_separate_occurrences() -> Result<(), Error> {
+    // Matches that don't overlap are reported separately, however many edits they need.
+    assert_eq!(
+        run("hello", 1, "helo hello hallo")?,
+        vec![(0, 4, 1), (5, 10, 0), (11, 16, 1)]
+    );
+    assert_eq!(run("abc", 1, "abcabc")?, vec![(0, 3, 0), (3, 6, 0)]);
+    Ok(())
+}
+
+#[test]
+fn test_substitution() -> Result<(), Error> {
+    assert_eq!(run("hello", 1, "say hallo")?, vec![(4, 9, 1)]);
+    assert!(run("hello", 1, "say hallu")?.is_empty());
+    assert_eq!(run("hello", 2, "say hallu")?, vec![(4, 9, 2)]);
+    Ok(())
+}
+
+#[test]
+fn test_insertion() -> Result<(), Error> {
+    assert_eq!(run("hello", 1, "say helllo")?, vec![(4, 10, 1)]);
+    assert_eq!(run("hello", 1, "say helxlo!")?, vec![(4, 10, 1)]);
+    Ok(())
+}
+
+#[test]
+fn test_deletion() -> Result<(), Error> {
+    assert_eq!(run("hello", 1, "say helo!")?, vec![(4, 8, 1)]);
+
+    // Substituting the space costs the same as deleting the "h", and starts earlier.
+    assert_eq!(run("hello", 1, "say ello!")?, vec![(3, 8, 1)]);
+    Ok(())
+}
+
+#[test]
+fn test_classes_and_alternation() -> Result<(), Error> {
+    assert_eq!(
+        run("[0-9]{3}-[0-9]{4}", 1, "call 555 1234")?,
+        vec![(5, 13, 1)]
+    );
+    assert_eq!(run("^(?:cat|dog)$", 1, "cot")?, vec![(0, 3, 1)]);
+    assert!(run("^(?:cat|dog)$", 1, "cow")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_counted_repetition() -> Result<(), Error> {
+    // Repetitions that would otherwise become counted loops are unrolled.
+    let text = format!("{}b{}", "a".repeat(20), "a".repeat(20));
+    let regex = Regex::new(1, "^a{41}$")
+        .counted_repetition_threshold(2)
+        .max_edits(1);
+    assert_eq!(
+        run_with(regex, text.as_bytes(), usize::MAX)?,
+        vec![(0, 41, 1)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_blocks() -> Result<(), Error> {
+    let text = b"the quick brwn fox jumsp over the lazy dog";
+    let regex = || Regex::new(1, "brown fox jumps").max_edits(2);
+    let expected = run_with(regex(), text, usize::MAX)?;
+    assert_eq!(expected.last(), Some(&(10, 23, 2)));
+    for block in 1..8 {
+        assert_eq!(run_with(regex(), text, block)?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_first() -> Result<(), Error> {
+    let regex = || {
+        Regex::new(1, "hello")
+            .mode(MatchMode::First(Submatch::Expression))
+            .max_edits(1)
+    };
+    assert_eq!(
+        run_with(regex(), b"say helo, hello", usize::MAX)?,
+        vec![(4, 8, 1)]
+    );
+
+    // The exact match is reported rather than the shorter one inside it that's found
+    // first.
+    for block in 1..4 {
+        assert_eq!(
+            run_with(regex(), b"say hello, helo", block)?,
+            vec![(4, 9, 0)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_captures() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, "^(hel)(lo)$")
+                .mode(MatchMode::All(Submatch::All))
+                .max_edits(1)
+                .build()?,
+        )
+        .build();
+    let mut handler = CaptureHandler { captures: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"helxlo");
+    scratch.finish();
+
+    // The inserted byte is counted as part of the second group.
+    assert_eq!(
+        handler.captures,
+        vec![vec![
+            Capture::new(Some(0), Some(6)),
+            Capture::new(Some(0), Some(3)),
+            Capture::new(Some(3), Some(6)),
+        ]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_disassembly() -> Result<(), Error> {
+    let regex = Regex::new(1, "hello").max_edits(2).build()?;
+    assert!(regex.disassemble().contains("max edits: 2"));
+    assert!(!Regex::new(1, "hello")
+        .build()?
+        .disassemble()
+        .contains("max edits"));
+    Ok(())
+}
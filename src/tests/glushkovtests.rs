@@ -0,0 +1,223 @@
+use crate::*;
+
+// The id, start and end of a match.
+type Match = (usize, Option<usize>, usize);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.matches.push((
+            info.id,
+            info.captures[0].start,
+            info.captures[0].end.unwrap(),
+        ));
+        ContinueMatching::Yes
+    }
+}
+
+fn run(regexes: Vec<Regex>, text: &[u8], block: usize) -> Result<Vec<Match>, Error> {
+    let mut builder = DatabaseBuilder::new();
+    for regex in regexes {
+        builder = builder.with_expression(regex.build()?);
+    }
+    let database = builder.build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+fn is_bit_parallel(regex: Regex) -> Result<bool, Error> {
+    Ok(regex
+        .build()?
+        .disassemble()
+        .contains("engine: bit-parallel"))
+}
+
+// Runs an expression on both engines, which must agree.
+fn compare(regex: impl Fn() -> Regex, text: &str) -> Result<Vec<Match>, Error> {
+    assert!(is_bit_parallel(regex())?);
+    let expected = run(
+        vec![regex().bit_parallel(false)],
+        text.as_bytes(),
+        text.len(),
+    )?;
+    for block in [1, 2, 3, 4, 5, 7, text.len()] {
+        assert_eq!(
+            run(vec![regex()], text.as_bytes(), block)?,
+            expected,
+            "{}",
+            block
+        );
+    }
+    Ok(expected)
+}
+
+#[test]
+fn test_bit_parallel_fixed_length() -> Result<(), Error> {
+    let cases: &[(&str, &str)] = &[
+        ("foo", "foo xfoo foofoo"),
+        ("a[bc]d", "abd acd aad abcd"),
+        ("(?i)hello", "Hello HELLO hellO"),
+        ("[0-9]{3}-[0-9]{4}", "555-1234 55-12345 5551-2345"),
+        ("(?:ab|cd)e", "abe cde ace"),
+        ("héé", "hé héé hééé"),
+        ("(?-u)\\bcat\\b", "cat concat cats cat"),
+        ("^abc", "abcabc"),
+        ("abc$", "abcabc"),
+        ("(?m)^x[a-z]y$", "xay\nxby\r\nzxcy\nxdy"),
+        ("aa", "aaaaa"),
+    ];
+
+    for &(expression, text) in cases {
+        for mode in [
+            MatchMode::First(Submatch::Expression),
+            MatchMode::All(Submatch::Expression),
+            MatchMode::All(Submatch::All),
+        ] {
+            let matches = compare(|| Regex::new(1, expression).mode(mode), text)?;
+            assert!(!matches.is_empty(), "{}", expression);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_existence() -> Result<(), Error> {
+    let cases: &[(&str, &str)] = &[
+        ("fo+", "xx foooo yy foo"),
+        ("a.*b", "xxaxxxxbxxb"),
+        ("[a-z]+[0-9]", "abc1 de2"),
+        ("(?-u)\\bx+\\b", "axxx xx"),
+        ("(ab)*c", "ababc"),
+    ];
+
+    for &(expression, text) in cases {
+        for mode in [
+            MatchMode::First(Submatch::None),
+            MatchMode::All(Submatch::None),
+        ] {
+            let matches = compare(|| Regex::new(1, expression).mode(mode), text)?;
+            assert_eq!(matches.len(), 1, "{}", expression);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_lookback() -> Result<(), Error> {
+    let cases: &[(&str, &str)] = &[
+        ("fo+", "xx foooo yy foo"),
+        ("a|ab|abc", "abcab abc"),
+        ("[a-z]+[0-9]", "abc1 de2 f3g4"),
+        ("x*y", "xxy y xxxy"),
+        ("(foo|bar)+baz", "foobarbaz barbaz bazbaz"),
+    ];
+
+    for &(expression, text) in cases {
+        for mode in [
+            MatchMode::First(Submatch::Expression),
+            MatchMode::All(Submatch::Expression),
+        ] {
+            let matches = compare(|| Regex::new(1, expression).mode(mode).lookback(64), text)?;
+            assert!(!matches.is_empty(), "{}", expression);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_wide() -> Result<(), Error> {
+    // More than 64 positions, but no more than 128.
+    let expression = "[a-c]{90}d";
+    let text = format!("{}d {}d", "abc".repeat(40), "c".repeat(89));
+    let matches = compare(
+        || {
+            Regex::new(1, expression)
+                .counted_repetition_threshold(usize::MAX)
+                .max_instructions(usize::MAX)
+        },
+        &text,
+    )?;
+    assert_eq!(matches, vec![(1, Some(30), 121)]);
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_many_expressions() -> Result<(), Error> {
+    let text = b"the cat sat on the mat with the hat";
+    let regexes = || {
+        vec![
+            Regex::new(1, "[chm]at"),
+            Regex::new(2, "the").mode(MatchMode::First(Submatch::Expression)),
+            Regex::new(3, "s.t").mode(MatchMode::All(Submatch::None)),
+        ]
+    };
+    let expected = run(
+        regexes()
+            .into_iter()
+            .map(|x| x.bit_parallel(false))
+            .collect(),
+        text,
+        text.len(),
+    )?;
+    for block in [1, 3, 8] {
+        let mut matches = run(regexes(), text, block)?;
+        let mut sorted = expected.clone();
+        matches.sort();
+        sorted.sort();
+        assert_eq!(matches, sorted);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_fallback() -> Result<(), Error> {
+    // Captures.
+    assert!(!is_bit_parallel(Regex::new(1, "(a)b"))?);
+    // Assertions inside the expression.
+    assert!(!is_bit_parallel(
+        Regex::new(1, "(?-u)a\\bb").mode(MatchMode::All(Submatch::None))
+    )?);
+    // Matches of different lengths, with no way to find where they start.
+    assert!(!is_bit_parallel(
+        Regex::new(1, "ab+").mode(MatchMode::All(Submatch::Expression))
+    )?);
+    // Too many positions.
+    assert!(!is_bit_parallel(
+        Regex::new(1, "[a-c]{200}")
+            .counted_repetition_threshold(usize::MAX)
+            .mode(MatchMode::All(Submatch::None))
+    )?);
+    // Matches of the empty string.
+    assert!(!is_bit_parallel(
+        Regex::new(1, "a*").mode(MatchMode::All(Submatch::None))
+    )?);
+    // Approximate matches.
+    assert!(!is_bit_parallel(
+        Regex::new(1, "abc")
+            .mode(MatchMode::All(Submatch::None))
+            .max_edits(1)
+    )?);
+    // Asked not to.
+    assert!(!is_bit_parallel(Regex::new(1, "abc").bit_parallel(false))?);
+    assert!(is_bit_parallel(Regex::new(1, "abc"))?);
+    Ok(())
+}
+
+#[test]
+fn test_bit_parallel_disassembly() -> Result<(), Error> {
+    let listing = Regex::new(1, "a[bc]d").build()?.disassemble();
+    assert!(listing.contains("engine: bit-parallel (3 positions)"));
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::intset::{GrowSet, ShrinkSet};
+use std::collections::HashSet;
+
+// A small xorshift generator, so that the property tests are reproducible
+// without pulling in another dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.next() % n
+    }
+}
+
+const CAPACITY: usize = 64;
+const OPERATIONS: usize = 10_000;
+
+#[test]
+fn test_growset_matches_hashset() {
+    for seed in 1..=16 {
+        let mut rng = Rng(seed);
+        let mut set = GrowSet::with_capacity(CAPACITY);
+        let mut model: HashSet<usize> = HashSet::new();
+        let mut order: Vec<usize> = Vec::new();
+
+        for _ in 0..OPERATIONS {
+            let value = rng.below(CAPACITY + 8);
+            match rng.below(6) {
+                0 | 1 => match set.try_add(value) {
+                    Ok(added) => {
+                        assert!(value < CAPACITY);
+                        assert_eq!(added, model.insert(value));
+                        if added {
+                            order.push(value);
+                        }
+                    }
+                    Err(e) => {
+                        assert!(value >= CAPACITY);
+                        assert_eq!(e.value(), value);
+                        assert_eq!(e.capacity(), CAPACITY);
+                    }
+                },
+                2 => {
+                    assert_eq!(set.remove(value), model.remove(&value));
+                    order.retain(|&x| x != value);
+                }
+                3 => {
+                    let popped = set.pop();
+                    assert_eq!(popped, order.pop());
+                    if let Some(popped) = popped {
+                        assert!(model.remove(&popped));
+                    }
+                }
+                4 => {
+                    if rng.below(20) == 0 {
+                        set.clear();
+                        model.clear();
+                        order.clear();
+                    }
+                }
+                _ => assert_eq!(set.contains(value), model.contains(&value)),
+            }
+
+            assert_eq!(set.len(), model.len());
+            assert_eq!(set.is_empty(), model.is_empty());
+            assert_eq!(set.iter().copied().collect::<Vec<usize>>(), order);
+        }
+    }
+}
+
+#[test]
+fn test_shrinkset_matches_hashset() {
+    for seed in 1..=16 {
+        let mut rng = Rng(seed);
+        let mut set = ShrinkSet::new(CAPACITY);
+        let mut model: HashSet<usize> = (0..CAPACITY).collect();
+
+        for _ in 0..OPERATIONS {
+            let value = rng.below(CAPACITY + 8);
+            match rng.below(6) {
+                0 | 1 => {
+                    set.remove(value);
+                    model.remove(&value);
+                }
+                2 => assert_eq!(set.insert(value), value < CAPACITY && model.insert(value)),
+                3 => {
+                    if let Some(popped) = set.pop() {
+                        assert!(model.remove(&popped));
+                    } else {
+                        assert!(model.is_empty());
+                    }
+                }
+                4 => {
+                    if rng.below(20) == 0 {
+                        set.refill();
+                        model = (0..CAPACITY).collect();
+                    }
+                }
+                _ => assert_eq!(set.contains(value), model.contains(&value)),
+            }
+
+            assert_eq!(set.len(), model.len());
+            assert_eq!(set.is_empty(), model.is_empty());
+            assert_eq!(set.iter().copied().collect::<HashSet<usize>>(), model);
+        }
+    }
+}
+
+#[test]
+fn test_growset_iterates_in_insertion_order() {
+    let mut set = GrowSet::with_capacity(10);
+    for value in [7, 3, 9, 0, 3, 5] {
+        set.add(value);
+    }
+    assert_eq!(
+        set.iter().copied().collect::<Vec<usize>>(),
+        vec![7, 3, 9, 0, 5]
+    );
+
+    assert!(set.remove(9));
+    assert!(!set.remove(9));
+    assert_eq!(
+        set.iter().copied().collect::<Vec<usize>>(),
+        vec![7, 3, 0, 5]
+    );
+
+    assert_eq!(set.pop(), Some(5));
+    assert_eq!(set.iter().copied().collect::<Vec<usize>>(), vec![7, 3, 0]);
+}
+
+#[test]
+fn test_out_of_range() {
+    let mut grow = GrowSet::with_capacity(4);
+    assert!(!grow.contains(100));
+    assert!(grow.try_add(4).is_err());
+    assert_eq!(grow.try_add(3), Ok(true));
+    assert_eq!(grow.try_add(3), Ok(false));
+
+    let mut shrink = ShrinkSet::new(4);
+    assert!(!shrink.contains(100));
+    assert!(!shrink.insert(100));
+    assert_eq!(shrink.remove(100), 100);
+    assert_eq!(shrink.len(), 4);
+}
@@ -0,0 +1,124 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches
+            .push((captures[0].start.unwrap(), captures[0].end.unwrap()));
+        ContinueMatching::Yes
+    }
+}
+
+fn run(
+    expression: &str,
+    terminator: LineTerminator,
+    text: &[u8],
+) -> Result<Vec<(usize, usize)>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, expression)
+                .encoding(Encoding::Byte)
+                .line_terminator(terminator)
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+#[test]
+fn test_cr_or_lf() -> Result<(), Error> {
+    // Both CR and LF end lines on their own, even inside CRLF.
+    assert_eq!(
+        run(r"(?m)\r$", LineTerminator::CROrLF, b"a\r\nb")?,
+        vec![(1, 2)]
+    );
+    assert_eq!(
+        run(r"(?m)^\n", LineTerminator::CROrLF, b"a\r\nb")?,
+        vec![(2, 3)]
+    );
+    assert_eq!(
+        run(r"(?m)^b", LineTerminator::CROrLF, b"a\rb")?,
+        vec![(2, 3)]
+    );
+    assert_eq!(run(r"a.b", LineTerminator::CROrLF, b"a\rb")?, vec![(0, 3)]);
+    assert_eq!(run(r"a.b", LineTerminator::CROrLF, b"a\nb")?, vec![]);
+    Ok(())
+}
+
+#[test]
+fn test_crlf() -> Result<(), Error> {
+    // CRLF is never split.
+    assert_eq!(run(r"(?m)\r$", LineTerminator::CRLF, b"a\r\nb")?, vec![]);
+    assert_eq!(run(r"(?m)^\n", LineTerminator::CRLF, b"a\r\nb")?, vec![]);
+    assert_eq!(
+        run(r"(?m)^b$", LineTerminator::CRLF, b"a\r\nb\r\n")?,
+        vec![(3, 4)]
+    );
+
+    // But CR and LF still end lines on their own.
+    assert_eq!(
+        run(r"(?m)^b$", LineTerminator::CRLF, b"a\rb\r")?,
+        vec![(2, 3)]
+    );
+    assert_eq!(
+        run(r"(?m)^b$", LineTerminator::CRLF, b"a\nb\n")?,
+        vec![(2, 3)]
+    );
+
+    // And . excludes both.
+    assert_eq!(run(r"a.b", LineTerminator::CRLF, b"a\rb")?, vec![]);
+    assert_eq!(run(r"a.b", LineTerminator::CRLF, b"a\nb")?, vec![]);
+    assert_eq!(
+        run(r"(?s)a.b", LineTerminator::CRLF, b"a\rb")?,
+        vec![(0, 3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lf() -> Result<(), Error> {
+    assert_eq!(run(r"(?m)^b", LineTerminator::LF, b"a\rb")?, vec![]);
+    assert_eq!(run(r"(?m)^b", LineTerminator::LF, b"a\nb")?, vec![(2, 3)]);
+    assert_eq!(run(r"(?m)a$", LineTerminator::LF, b"a\r")?, vec![]);
+    assert_eq!(
+        run(r"(?m)a\r$", LineTerminator::LF, b"a\r\n")?,
+        vec![(0, 2)]
+    );
+    assert_eq!(run(r"a.b", LineTerminator::LF, b"a\rb")?, vec![(0, 3)]);
+    Ok(())
+}
+
+#[test]
+fn test_nul() -> Result<(), Error> {
+    assert_eq!(
+        run(r"(?m)^.+$", LineTerminator::NUL, b"foo\0ba\nr\0")?,
+        vec![(0, 3), (4, 8)]
+    );
+    assert_eq!(run(r"(?m)^b", LineTerminator::NUL, b"a\nb")?, vec![]);
+    assert_eq!(run(r"(?s)a.b", LineTerminator::NUL, b"a\0b")?, vec![(0, 3)]);
+    Ok(())
+}
+
+#[test]
+fn test_unicode_dot() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, r"(?m)^.$")
+                .line_terminator(LineTerminator::NUL)
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push("é\0\n\0é".as_bytes());
+    scratch.finish();
+    assert_eq!(handler.matches, vec![(0, 2), (3, 4), (5, 7)]);
+    Ok(())
+}
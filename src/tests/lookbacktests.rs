@@ -0,0 +1,246 @@
+use crate::*;
+
+// The id, start and end of a match.
+type Match = (usize, Option<usize>, usize);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+    data: Vec<Option<Vec<u8>>>,
+}
+
+impl CollectingHandler {
+    fn new() -> Self {
+        Self {
+            matches: vec![],
+            data: vec![],
+        }
+    }
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        assert_eq!(info.captures.len(), 1);
+        self.matches.push((
+            info.id,
+            info.captures[0].start,
+            info.captures[0].end.unwrap(),
+        ));
+        ContinueMatching::Yes
+    }
+
+    fn on_match_with_data(&mut self, info: &MatchInfo, data: &[Option<&[u8]>]) -> ContinueMatching {
+        self.data.push(data[0].map(|x| x.to_vec()));
+        self.on_match_info(info)
+    }
+}
+
+fn run(regex: Regex, text: &[u8], block: usize) -> Result<Vec<Match>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(regex.build()?)
+        .build();
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+#[test]
+fn test_lookback_same_as_tracking() -> Result<(), Error> {
+    let cases: &[(&str, &str)] = &[
+        ("fo+", "xx foooo yy foo"),
+        ("a|ab|abc", "abcab abc"),
+        ("[a-z]+[0-9]", "abc1 de2 f3g4"),
+        ("(?-u)\\bfoo\\b", "foo food foo_ foo"),
+        ("^foo", "foofoo"),
+        ("bar$", "bar barbar"),
+        ("(?m)^x+$", "xx\nx\r\nyx\nxxx"),
+        ("héé?", "hé, hée, hééé"),
+        ("x*y", "xxy y xxxy"),
+        ("(foo|bar)+baz", "foobarbaz barbaz bazbaz"),
+    ];
+
+    for &(expression, text) in cases {
+        for mode in [
+            MatchMode::First(Submatch::Expression),
+            MatchMode::All(Submatch::Expression),
+        ] {
+            for block in [1, 2, 3, 4, 5, 6, 7, text.len()] {
+                let tracked = run(Regex::new(1, expression).mode(mode), text.as_bytes(), block)?;
+                assert!(!tracked.is_empty(), "{}", expression);
+                assert_eq!(
+                    run(
+                        Regex::new(1, expression).mode(mode).lookback(64),
+                        text.as_bytes(),
+                        block
+                    )?,
+                    tracked,
+                    "{} {:?} {}",
+                    expression,
+                    mode,
+                    block
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lookback_counted() -> Result<(), Error> {
+    let text = "baaaaaaaaaab aaab";
+    for block in [1, 3, text.len()] {
+        let regex = || {
+            Regex::new(1, "a{3,10}b")
+                .counted_repetition_threshold(2)
+                .mode(MatchMode::All(Submatch::Expression))
+        };
+        assert_eq!(
+            run(regex().lookback(32), text.as_bytes(), block)?,
+            run(regex(), text.as_bytes(), block)?
+        );
+        assert_eq!(
+            run(regex().lookback(32), text.as_bytes(), block)?,
+            vec![(1, Some(1), 12), (1, Some(13), 17)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lookback_window() -> Result<(), Error> {
+    for block in [1, 2, 4] {
+        // The start is as early as the window allows.
+        assert_eq!(
+            run(
+                Regex::new(1, "a+b")
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .lookback(3),
+                b"aaaaab",
+                block
+            )?,
+            vec![(1, Some(3), 6)]
+        );
+
+        // Or there isn't one at all.
+        assert_eq!(
+            run(
+                Regex::new(1, "ab+")
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .lookback(2),
+                b"abbbb",
+                block
+            )?,
+            vec![(1, Some(0), 2), (1, None, 3), (1, None, 4), (1, None, 5)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lookback_capture_buffer() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, "<[a-z]+>")
+                .mode(MatchMode::All(Submatch::Expression))
+                .lookback(16)
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler).with_capture_buffer(64);
+    for chunk in b"xx <foo> yy <barbaz>".chunks(3) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+
+    assert_eq!(handler.matches, vec![(1, Some(3), 8), (1, Some(12), 20)]);
+    assert_eq!(
+        handler.data,
+        vec![Some(b"<foo>".to_vec()), Some(b"<barbaz>".to_vec())]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lookback_disassembly() -> Result<(), Error> {
+    let regex = Regex::new(1, "foo")
+        .mode(MatchMode::First(Submatch::Expression))
+        .lookback(8)
+        .build()?;
+    let listing = regex.disassemble();
+    assert!(listing.contains("lookback: 8"));
+    assert!(listing.contains("captures: 0"));
+    Ok(())
+}
+
+#[test]
+fn test_lookback_needs_expression() {
+    for mode in [
+        MatchMode::All(Submatch::All),
+        MatchMode::First(Submatch::AtMost(2)),
+        MatchMode::All(Submatch::None),
+    ] {
+        assert!(Regex::new(1, "foo").mode(mode).lookback(8).build().is_err());
+    }
+    assert!(Regex::new(1, "foo")
+        .mode(MatchMode::All(Submatch::Expression))
+        .max_edits(1)
+        .lookback(8)
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_lookback_empty_matches() -> Result<(), Error> {
+    // Empty matches at the start of the input start there too.
+    let cases: &[(&str, &str, &[Match])] = &[
+        (
+            "x*",
+            "ab",
+            &[(1, Some(0), 0), (1, Some(1), 1), (1, Some(2), 2)],
+        ),
+        ("a?", "b", &[(1, Some(0), 0), (1, Some(1), 1)]),
+        ("b*", "", &[(1, Some(0), 0)]),
+    ];
+    for &(expression, text, expected) in cases {
+        for block in [1, 2, 3] {
+            let regex = || Regex::new(1, expression).mode(MatchMode::All(Submatch::Expression));
+            assert_eq!(run(regex(), text.as_bytes(), block)?, expected);
+            assert_eq!(
+                run(regex().lookback(8), text.as_bytes(), block)?,
+                expected,
+                "{} {}",
+                expression,
+                block
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lookback_one_match_per_end() -> Result<(), Error> {
+    // Both alternatives match at the same offset, and find the same start within the
+    // lookback, so the match is only reported once.
+    for block in [1, 2, 3] {
+        assert_eq!(
+            run(
+                Regex::new(1, "x{1,}?a|a")
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .bit_parallel(false)
+                    .lookback(1),
+                b"xax",
+                block
+            )?,
+            vec![(1, Some(1), 2)]
+        );
+    }
+    Ok(())
+}
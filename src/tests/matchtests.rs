@@ -17,11 +17,7 @@ impl TestHandler {
 impl MatchHandler for TestHandler {
     fn on_match(&mut self, _id: usize, matches: &[Capture]) -> ContinueMatching {
         assert!(self.expected.len() <= matches.len());
-        let matched: Vec<Capture> = matches
-            .iter()
-            .map(|x| *x)
-            .take(self.expected.len())
-            .collect();
+        let matched: Vec<Capture> = matches.iter().copied().take(self.expected.len()).collect();
         // because ergex is designed to do streaming and multi-matching, it can report
         // multiple matches; we just need to make sure the right one is in there.
         if self.expected == matched {
@@ -45,8 +41,7 @@ impl ShouldNotMatchHandler {
 
 impl MatchHandler for ShouldNotMatchHandler {
     fn on_match(&mut self, _id: usize, _matches: &[Capture]) -> ContinueMatching {
-        assert!(false);
-        ContinueMatching::No
+        panic!("unexpected match");
     }
 }
 
@@ -0,0 +1,57 @@
+use crate::*;
+
+fn make_builder() -> Result<DatabaseBuilder, Error> {
+    Ok(DatabaseBuilder::new()
+        .with_expression(Regex::new(1, r##"abc"##).build()?)
+        .with_expression(Regex::new(2, r##"(a)(b)(c)(d)[a-z]{1,5}"##).build()?)
+        .with_expression(Regex::new(3, r##"x{2,200}y"##).build()?))
+}
+
+#[test]
+fn test_memory_usage_breakdown() -> Result<(), Error> {
+    let database = make_builder()?.build();
+    let usage = database.memory_usage();
+
+    assert_eq!(
+        usage.iter().map(|x| x.id).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    assert!(usage.iter().all(|x| x.heap_size > 0 && x.scratch_size > 0));
+    assert!(usage[1].scratch_size > usage[0].scratch_size);
+    assert!(database.heap_size() >= usage.iter().map(|x| x.heap_size).sum::<usize>());
+    assert!(database.scratch_size() >= usage.iter().map(|x| x.scratch_size).sum::<usize>());
+    Ok(())
+}
+
+#[test]
+fn test_scratch_size_grows_with_captures() -> Result<(), Error> {
+    let plain = Regex::new(1, r##"abcdefgh"##).build()?;
+    let captures = Regex::new(1, r##"(a)(b)(c)(d)(e)(f)(g)(h)"##).build()?;
+    assert!(captures.scratch_size() > plain.scratch_size());
+    Ok(())
+}
+
+#[test]
+fn test_max_scratch_bytes() -> Result<(), Error> {
+    let size = make_builder()?.build().scratch_size();
+
+    let database = make_builder()?.max_scratch_bytes(size).try_build()?;
+    assert_eq!(database.scratch_size(), size);
+
+    match make_builder()?.max_scratch_bytes(size - 1).try_build() {
+        Err(Error::ScratchTooLarge(id)) => assert_eq!(id, 3),
+        _ => panic!("expected scratch limit to be exceeded"),
+    }
+
+    match make_builder()?.max_scratch_bytes(0).try_build() {
+        Err(Error::ScratchTooLarge(id)) => assert_eq!(id, 1),
+        _ => panic!("expected scratch limit to be exceeded"),
+    }
+    Ok(())
+}
+
+#[test]
+#[should_panic]
+fn test_max_scratch_bytes_build() {
+    make_builder().unwrap().max_scratch_bytes(0).build();
+}
@@ -0,0 +1,240 @@
+use crate::*;
+
+// The id, start and end of a match.
+type Match = (usize, Option<usize>, usize);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+    data: Vec<Option<Vec<u8>>>,
+}
+
+impl CollectingHandler {
+    fn new() -> Self {
+        Self {
+            matches: vec![],
+            data: vec![],
+        }
+    }
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.matches.push((
+            info.id,
+            info.captures[0].start,
+            info.captures[0].end.unwrap(),
+        ));
+        ContinueMatching::Yes
+    }
+
+    fn on_match_with_data(&mut self, info: &MatchInfo, data: &[Option<&[u8]>]) -> ContinueMatching {
+        self.data.push(data[0].map(|x| x.to_vec()));
+        self.on_match_info(info)
+    }
+}
+
+fn build(regexes: Vec<Regex>, merge: bool) -> Result<Database, Error> {
+    let mut builder = DatabaseBuilder::new().merge_expressions(merge);
+    for regex in regexes {
+        builder = builder.with_expression(regex.build()?);
+    }
+    Ok(builder.build())
+}
+
+fn run(database: &Database, text: &[u8], block: usize) -> Vec<Match> {
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.chunks(block) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+
+    let mut matches = handler.matches;
+    matches.sort();
+    matches
+}
+
+// Runs expressions with and without merging them, which must agree.
+fn compare(regexes: impl Fn() -> Vec<Regex>, text: &str) -> Result<Vec<Match>, Error> {
+    let expected = run(&build(regexes(), false)?, text.as_bytes(), text.len());
+    let merged = build(regexes(), true)?;
+    for block in [1, 2, 3, 5, 8, text.len()] {
+        assert_eq!(run(&merged, text.as_bytes(), block), expected, "{}", block);
+    }
+    Ok(expected)
+}
+
+#[test]
+fn test_merged_same_as_separate() -> Result<(), Error> {
+    let text = "the cat sat on the mat; the hat, the bat and the rat sat flat";
+    let matches = compare(
+        || {
+            vec![
+                Regex::new(1, "[chm]at"),
+                Regex::new(2, "the"),
+                Regex::new(3, "s.t").mode(MatchMode::All(Submatch::Expression)),
+                Regex::new(4, "a[a-z]+").mode(MatchMode::All(Submatch::None)),
+                Regex::new(5, "(?i)THE").mode(MatchMode::First(Submatch::Expression)),
+                Regex::new(6, "at").mode(MatchMode::First(Submatch::None)),
+                Regex::new(7, "xyz"),
+            ]
+        },
+        text,
+    )?;
+    assert!(matches.iter().any(|x| x.0 == 1));
+    assert_eq!(matches.iter().filter(|x| x.0 == 4).count(), 1);
+    assert_eq!(matches.iter().filter(|x| x.0 == 5).count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_merged_assertions() -> Result<(), Error> {
+    let text = "cat concat cats cat\nbat\r\nbats bat";
+    compare(
+        || {
+            vec![
+                Regex::new(1, "(?-u)\\bcat\\b"),
+                Regex::new(2, "(?-u)\\bbat"),
+                Regex::new(3, "^cat"),
+                Regex::new(4, "bat$"),
+                Regex::new(5, "(?m)^bat$"),
+                Regex::new(6, "(?m)^[bc]at"),
+                Regex::new(7, "(?-u)at\\b"),
+            ]
+        },
+        text,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_merged_with_others() -> Result<(), Error> {
+    // Expressions that can't be merged run as they would otherwise.
+    let text = "foo=1 bar=22 foo=333";
+    let matches = compare(
+        || {
+            vec![
+                Regex::new(1, "foo"),
+                Regex::new(2, "([a-z]+)=([0-9]+)"),
+                Regex::new(3, "[0-9]+").mode(MatchMode::All(Submatch::Expression)),
+                Regex::new(4, "=[0-9]").max_edits(1),
+                Regex::new(5, "o+")
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .lookback(8),
+                Regex::new(6, "bar"),
+            ]
+        },
+        text,
+    )?;
+    for id in 1..=6 {
+        assert!(matches.iter().any(|x| x.0 == id), "{}", id);
+    }
+
+    let database = build(
+        vec![Regex::new(1, "foo"), Regex::new(2, "([a-z]+)=([0-9]+)")],
+        true,
+    )?;
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler).with_stats();
+    scratch.push(text.as_bytes());
+    let stats = scratch.finish().stats();
+
+    // The merged expression isn't run on its own.
+    let merged = stats.get(1).next().unwrap();
+    assert_eq!(merged.matches, 2);
+    assert_eq!(merged.bytes_executed, 0);
+    assert!(stats.get(2).next().unwrap().bytes_executed > 0);
+    Ok(())
+}
+
+#[test]
+fn test_merged_many() -> Result<(), Error> {
+    let words: Vec<String> = (0..2000)
+        .map(|x| format!("w{}x{}", x * 7919 % 10007, x % 13))
+        .collect();
+    let text: String = (0..500)
+        .map(|x| words[x * 37 % words.len()].clone())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let matches = compare(
+        || {
+            words
+                .iter()
+                .enumerate()
+                .map(|(id, word)| Regex::new(id, format!("(?-u)\\b{}\\b", word)))
+                .collect()
+        },
+        &text,
+    )?;
+    assert_eq!(matches.len(), 500);
+    Ok(())
+}
+
+#[test]
+fn test_merged_disable() -> Result<(), Error> {
+    let database = build(vec![Regex::new(1, "ab"), Regex::new(2, "cd")], true)?;
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"ab cd ");
+    scratch.disable(1);
+    scratch.push(b"ab cd");
+    scratch.finish();
+    assert_eq!(
+        handler.matches,
+        vec![(1, Some(0), 2), (2, Some(3), 5), (2, Some(9), 11)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_merged_capture_buffer() -> Result<(), Error> {
+    let database = build(
+        vec![
+            Regex::new(1, "<[a-z]{3}>"),
+            Regex::new(2, "[0-9]{2}").mode(MatchMode::All(Submatch::None)),
+        ],
+        true,
+    )?;
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler).with_capture_buffer(16);
+    for chunk in b"xx <foo> 12 <bar>".chunks(3) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+
+    assert_eq!(
+        handler.matches,
+        vec![(1, Some(3), 8), (2, None, 11), (1, Some(12), 17)]
+    );
+    assert_eq!(
+        handler.data,
+        vec![Some(b"<foo>".to_vec()), None, Some(b"<bar>".to_vec())]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_merged_across_streams() -> Result<(), Error> {
+    let database = build(vec![Regex::new(1, "^ab"), Regex::new(2, "b$")], true)?;
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"abab");
+    scratch = scratch.finish();
+    scratch.push(b"a");
+    scratch.push(b"b");
+    scratch.finish();
+    assert_eq!(
+        handler.matches,
+        vec![
+            (1, Some(0), 2),
+            (2, Some(3), 4),
+            (1, Some(0), 2),
+            (2, Some(1), 2)
+        ]
+    );
+    Ok(())
+}
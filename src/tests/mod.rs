@@ -1,8 +1,34 @@
 mod boundarytests;
 mod boundarytests_baat;
+mod buffertests;
+mod combinationtests;
+mod decodertests;
+mod disassemblytests;
+mod encodingtests;
+mod existencetests;
+mod foldingtests;
+mod fuzzytests;
+mod glushkovtests;
+mod intsettests;
+mod lineterminatortests;
+mod lookbacktests;
 mod matchtests;
+mod memorytests;
+mod mergedtests;
+mod normalizertests;
 mod oneoff;
+mod optimizertests;
+mod optiontests;
+mod paralleltests;
+mod repetitiontests;
+mod sequencetests;
 mod shakespeare;
 mod shakespeare_noalloc;
 mod smoketests;
+mod statstests;
+#[cfg(feature = "async")]
+mod streamtests;
 mod teakettle;
+#[cfg(feature = "trace")]
+mod tracetests;
+mod varianttests;
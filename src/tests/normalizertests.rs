@@ -0,0 +1,145 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<Vec<Capture>>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push(captures.to_vec());
+        ContinueMatching::Yes
+    }
+}
+
+fn database(expression: &str) -> Result<Database, Error> {
+    Ok(DatabaseBuilder::new()
+        .with_expression(Regex::new(1, expression).build()?)
+        .build())
+}
+
+fn span(start: usize, end: usize) -> Capture {
+    Capture {
+        start: Some(start),
+        end: Some(end),
+    }
+}
+
+// Lowercases text and collapses runs of spaces, the way a caller might before
+// pushing it. The text starts at `offset` in the original stream.
+fn normalize(text: &str, offset: usize) -> (Vec<u8>, OffsetMap) {
+    let mut normalized = vec![];
+    let mut spans: Vec<(usize, usize)> = vec![];
+    for (index, byte) in text.bytes().enumerate() {
+        let start = offset + index;
+        if byte == b' ' && normalized.last() == Some(&b' ') {
+            spans.last_mut().unwrap().1 = start + 1;
+        } else {
+            normalized.push(byte.to_ascii_lowercase());
+            spans.push((start, start + 1));
+        }
+    }
+
+    let mut offsets = OffsetMap::new();
+    for (start, end) in spans {
+        offsets.push(start, end);
+    }
+    (normalized, offsets)
+}
+
+#[test]
+fn test_push_mapped() -> Result<(), Error> {
+    let database = database("hello (w)orld")?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    let (first, first_offsets) = normalize("Say HELLO   ", 0);
+    let (second, second_offsets) = normalize("World!", 12);
+    scratch.push_mapped(&first, &first_offsets);
+    scratch.push_mapped(&second, &second_offsets);
+    scratch.finish();
+
+    assert_eq!(handler.matches, vec![vec![span(4, 17), span(12, 13)]]);
+    Ok(())
+}
+
+#[test]
+fn test_push_after_push_mapped() -> Result<(), Error> {
+    let database = database("a bcd")?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    let (normalized, offsets) = normalize("A   B", 0);
+    scratch.push_mapped(&normalized, &offsets);
+    scratch.push(b"cd");
+    scratch.finish();
+
+    assert_eq!(handler.matches, vec![vec![span(0, 7)]]);
+    Ok(())
+}
+
+#[test]
+#[should_panic]
+fn test_push_mapped_needs_every_byte() {
+    let database = database("a").unwrap();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    let mut offsets = OffsetMap::new();
+    offsets.push_unchanged(0, 1);
+    scratch.push_mapped(b"ab", &offsets);
+}
+
+#[test]
+fn test_offset_map() {
+    let mut offsets = OffsetMap::new();
+    offsets.push_unchanged(10, 2);
+    offsets.push(12, 16);
+    assert_eq!(offsets.len(), 3);
+    assert_eq!(offsets.span(2), Some((12, 16)));
+    assert_eq!(offsets.span(3), None);
+    assert_eq!(offsets.map(span(1, 3)), span(11, 16));
+    assert_eq!(offsets.map(span(2, 2)), span(12, 12));
+}
+
+fn run(
+    expression: &str,
+    decoders: Vec<Box<dyn Decoder>>,
+    text: &str,
+) -> Result<Vec<Capture>, Error> {
+    let database = database(expression)?;
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for decoder in decoders {
+        scratch = scratch.with_decoder(decoder);
+    }
+    for byte in text.as_bytes().chunks(1) {
+        scratch.push(byte);
+    }
+    scratch.finish();
+    Ok(handler.matches.iter().map(|x| x[0]).collect())
+}
+
+#[test]
+fn test_lowercase_and_whitespace_normalizers() -> Result<(), Error> {
+    assert_eq!(
+        run(
+            "hello world",
+            vec![
+                Box::new(LowercaseNormalizer::new()),
+                Box::new(WhitespaceNormalizer::new())
+            ],
+            "Hello \t\n  World!"
+        )?,
+        vec![span(0, 15)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_html_entity_decoder() -> Result<(), Error> {
+    let text = "a &lt;b&gt; &#x41;&#66;&bogus; x&amp";
+    let decoders = || -> Vec<Box<dyn Decoder>> { vec![Box::new(HtmlEntityDecoder::new())] };
+    assert_eq!(run("<b>", decoders(), text)?, vec![span(2, 11)]);
+    assert_eq!(run("AB", decoders(), text)?, vec![span(12, 23)]);
+    assert_eq!(run("&bogus;", decoders(), text)?, vec![span(23, 30)]);
+    assert_eq!(run("x&amp$", decoders(), text)?, vec![span(31, 36)]);
+    assert_eq!(run("\u{a0}", decoders(), "&nbsp;")?, vec![span(0, 6)]);
+    Ok(())
+}
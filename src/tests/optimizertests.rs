@@ -0,0 +1,222 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<Vec<Capture>>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push(captures.to_vec());
+        ContinueMatching::Yes
+    }
+}
+
+fn run(
+    regex: &str,
+    text: &str,
+    case_sensitive: bool,
+    optimize: bool,
+) -> Result<Vec<Vec<Capture>>, Error> {
+    scan(
+        Regex::new(1, regex)
+            .case_sensitive(case_sensitive)
+            .optimize(optimize),
+        text,
+    )
+}
+
+fn scan(regex: Regex, text: &str) -> Result<Vec<Vec<Capture>>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            regex
+                .encoding(Encoding::Byte)
+                .mode(MatchMode::All(Submatch::All))
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    for byte in text.as_bytes().chunks(1) {
+        scratch.push(byte);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+// A small xorshift generator, so that the random expressions are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.next() % n
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len())]
+    }
+}
+
+// Makes a random expression out of alternations, which is where threads that
+// reach the same instruction by different paths are most common.
+fn expression(rng: &mut Rng, depth: usize) -> String {
+    let mut alternatives = vec![];
+    for _ in 0..1 + rng.below(3) {
+        let mut sequence = String::new();
+        for _ in 0..1 + rng.below(3) {
+            if depth < 2 && rng.below(4) == 0 {
+                sequence += &format!("(?:{})", expression(rng, depth + 1));
+                sequence += rng.pick(&["", "", "?", "??"]);
+            } else {
+                sequence += rng.pick(&["a", "b", "c", " ", ".", "[ab]"]);
+                sequence += rng.pick(&[
+                    "", "", "", "?", "??", "*", "*?", "+", "+?", "{0,19}", "{2,}", "{1,3}?",
+                ]);
+            }
+        }
+        alternatives.push(sequence);
+    }
+    alternatives.join("|")
+}
+
+// Pulls the value out of a line like `let regex: &'static str = r##"..."##;`.
+fn raw_string(line: &str) -> &str {
+    let start = line.find("r##\"").unwrap() + 4;
+    let end = line.rfind("\"##").unwrap();
+    &line[start..end]
+}
+
+#[test]
+fn test_optimizer_matches_unoptimized() -> Result<(), Error> {
+    // rustfmt moves long strings onto the line after their declaration, so join
+    // those lines back up before looking at them.
+    let source = include_str!("matchtests.rs").replace("str =\n", "str = ");
+    let mut regex = None;
+    let mut text = None;
+    let mut count = 0;
+
+    for line in source.lines().map(|x| x.trim()) {
+        if line.starts_with("let regex: &'static str") {
+            regex = Some(raw_string(line));
+        } else if line.starts_with("let text: &'static str") {
+            text = Some(raw_string(line));
+        } else if line.starts_with(".case_sensitive(") {
+            let case_sensitive = line == ".case_sensitive(true)";
+            let (regex, text) = (regex.take().unwrap(), text.take().unwrap());
+            assert_eq!(
+                run(regex, text, case_sensitive, true)?,
+                run(regex, text, case_sensitive, false)?,
+                "{:?} on {:?}",
+                regex,
+                text
+            );
+            count += 1;
+        }
+    }
+
+    assert!(count > 1000);
+    Ok(())
+}
+
+#[test]
+fn test_optimizer_keeps_overlapping_matches() -> Result<(), Error> {
+    for (regex, text) in [
+        (r##" +|ba?? ?"##, "b  b "),
+        (r##".c|a?cb{0,19}|bc"##, "acbcbc"),
+        (r##"(a|ab)(c|bcd)(d*)"##, "abcd abcdd"),
+    ] {
+        for counted in [true, false] {
+            let configure = |optimize| {
+                let regex = Regex::new(1, regex).optimize(optimize);
+                if counted {
+                    regex
+                } else {
+                    regex
+                        .counted_repetition_threshold(usize::MAX)
+                        .bit_parallel(false)
+                }
+            };
+            assert_eq!(
+                scan(configure(true), text)?,
+                scan(configure(false), text)?,
+                "{:?} on {:?}",
+                regex,
+                text
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_optimizer_matches_unoptimized_random() -> Result<(), Error> {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    for _ in 0..2000 {
+        let regex = expression(&mut rng, 0);
+        let text: String = (0..rng.below(12))
+            .map(|_| rng.pick(&["a", "b", "c", " "]))
+            .collect();
+        assert_eq!(
+            scan(Regex::new(1, &regex), &text)?,
+            scan(Regex::new(1, &regex).optimize(false), &text)?,
+            "{:?} on {:?}",
+            regex,
+            text
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_optimizer_merges_literal_runs() -> Result<(), Error> {
+    for (expression, before, after) in [
+        (r##"hello world|help"##, 18, 5),
+        (r##"(foo)bar(?:baz)+"##, 17, 8),
+    ] {
+        let optimized = Regex::new(1, expression).encoding(Encoding::Byte).build()?;
+        assert_eq!(optimized.unoptimized_program_size(), before);
+        assert_eq!(optimized.program_size(), after);
+    }
+
+    let listing = Regex::new(1, r##"(foo)bar(?:baz)+"##)
+        .encoding(Encoding::Byte)
+        .build()?
+        .disassemble();
+    assert!(listing.contains("literal \"foo\""));
+    assert!(listing.contains("literal \"barbaz\""));
+    assert!(listing.contains("literal \"baz\""));
+
+    // Threads partway through a run carry on from where they stopped.
+    for text in ["xhello worldx help", "barbaz foobarbazbaz"] {
+        for regex in [r##"hello world|help"##, r##"(foo)bar(?:baz)+"##] {
+            assert_eq!(
+                scan(Regex::new(1, regex), text)?,
+                scan(Regex::new(1, regex).optimize(false), text)?
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_optimizer_shrinks_programs() -> Result<(), Error> {
+    for expression in [r##"(?:|a)+"##, r##"(a|)+b"##, r##"(|a|bc)*d"##] {
+        let optimized = Regex::new(1, expression).encoding(Encoding::Byte).build()?;
+        let unoptimized = Regex::new(1, expression)
+            .encoding(Encoding::Byte)
+            .optimize(false)
+            .build()?;
+        assert_eq!(
+            optimized.unoptimized_program_size(),
+            unoptimized.program_size()
+        );
+        assert!(optimized.program_size() < unoptimized.program_size());
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<Vec<Capture>>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push(captures.to_vec());
+        ContinueMatching::Yes
+    }
+}
+
+fn run(regex: Regex, text: &[u8]) -> Result<Vec<Vec<Capture>>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(regex.build()?)
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+fn spans(matches: &[Vec<Capture>]) -> Vec<(usize, usize)> {
+    matches
+        .iter()
+        .map(|x| (x[0].start.unwrap(), x[0].end.unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_swap_greed() -> Result<(), Error> {
+    let greedy = run(Regex::new(1, r##"^(a+)(a*)$"##), b"aaa")?;
+    assert_eq!(greedy[0][1], Capture::new(Some(0), Some(3)));
+
+    let lazy = run(Regex::new(1, r##"^(a+)(a*)$"##).swap_greed(true), b"aaa")?;
+    assert_eq!(lazy[0][1], Capture::new(Some(0), Some(1)));
+
+    // The U flag does the same thing inline, and ? swaps it back.
+    let lazy = run(Regex::new(1, r##"(?U)^(a+)(a*)$"##), b"aaa")?;
+    assert_eq!(lazy[0][1], Capture::new(Some(0), Some(1)));
+    let greedy = run(Regex::new(1, r##"^(a+?)(a*)$"##).swap_greed(true), b"aaa")?;
+    assert_eq!(greedy[0][1], Capture::new(Some(0), Some(3)));
+    Ok(())
+}
+
+#[test]
+fn test_octal() -> Result<(), Error> {
+    assert!(Regex::new(1, r##"\141"##).build().is_err());
+    assert_eq!(
+        spans(&run(Regex::new(1, r##"\141"##).octal(true), b"xa")?),
+        vec![(1, 2)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unicode() -> Result<(), Error> {
+    let text = "é".as_bytes();
+
+    // Unicode follows the encoding by default.
+    assert_eq!(spans(&run(Regex::new(1, r##"^\w$"##), text)?), vec![(0, 2)]);
+    assert!(run(Regex::new(1, r##"^\w$"##).encoding(Encoding::Byte), text)?.is_empty());
+
+    // And can be turned on or off regardless.
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"^\w$"##)
+                .encoding(Encoding::Byte)
+                .unicode(true),
+            text
+        )?),
+        vec![(0, 2)]
+    );
+    assert!(run(Regex::new(1, r##"^\w$"##).unicode(false), text)?.is_empty());
+
+    // Without Unicode, . matches any single byte but a newline, even under UTF-8.
+    assert_eq!(
+        spans(&run(Regex::new(1, r##"^..$"##).unicode(false), text)?),
+        vec![(0, 2)]
+    );
+    assert!(run(Regex::new(1, r##"^.$"##).unicode(false), text)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_allow_invalid_utf8() -> Result<(), Error> {
+    assert!(Regex::new(1, r##"(?-u:\xff)"##).build().is_err());
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"(?-u:\xff)"##).allow_invalid_utf8(true),
+            b"a\xff"
+        )?),
+        vec![(1, 2)]
+    );
+    // Byte encoding always allows invalid UTF-8.
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"\xff"##).encoding(Encoding::Byte),
+            b"a\xff"
+        )?),
+        vec![(1, 2)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_case_sensitive() -> Result<(), Error> {
+    assert!(run(Regex::new(1, r##"abc"##), b"ABC")?.is_empty());
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"abc"##).case_sensitive(false),
+            b"ABC"
+        )?),
+        vec![(0, 3)]
+    );
+    assert_eq!(
+        spans(&run(Regex::new(1, r##"(?i)abc"##), b"ABC")?),
+        vec![(0, 3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_allow_whitespace() -> Result<(), Error> {
+    assert!(run(Regex::new(1, r##"a b # comment"##), b"ab")?.is_empty());
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"a b # comment"##).allow_whitespace(true),
+            b"ab"
+        )?),
+        vec![(0, 2)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_dot_matches_new_line() -> Result<(), Error> {
+    assert!(run(Regex::new(1, r##"a.b"##), b"a\nb")?.is_empty());
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"a.b"##).dot_matches_new_line(true),
+            b"a\nb"
+        )?),
+        vec![(0, 3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_multi_line() -> Result<(), Error> {
+    assert_eq!(
+        spans(&run(Regex::new(1, r##"^b$"##), b"a\r\nb\r\nc")?),
+        vec![]
+    );
+    assert_eq!(
+        spans(&run(
+            Regex::new(1, r##"^b$"##).multi_line(true),
+            b"a\r\nb\r\nc"
+        )?),
+        vec![(3, 4)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_max_depth() -> Result<(), Error> {
+    assert!(Regex::new(1, r##"((((a))))"##).build().is_ok());
+    assert!(Regex::new(1, r##"((((a))))"##)
+        .max_depth(2)
+        .build()
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use crate::*;
+
+// The id, start and end of a match.
+type Match = (usize, Option<usize>, Option<usize>);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+    limit: usize,
+}
+
+impl CollectingHandler {
+    fn new() -> Self {
+        Self {
+            matches: vec![],
+            limit: usize::MAX,
+        }
+    }
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push((id, captures[0].start, captures[0].end));
+        if self.matches.len() >= self.limit {
+            return ContinueMatching::No;
+        }
+        ContinueMatching::Yes
+    }
+}
+
+fn sequential(database: &Database, text: &[u8]) -> Vec<Match> {
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+
+    let mut matches = handler.matches;
+    matches.sort();
+    matches
+}
+
+fn parallel(database: &Database, text: &[u8], chunk_size: usize, threads: usize) -> Vec<Match> {
+    let mut handler = CollectingHandler::new();
+    ParallelScanner::new(database)
+        .chunk_size(chunk_size)
+        .threads(threads)
+        .scan(text, &mut handler);
+
+    let mut matches = handler.matches;
+    matches.sort();
+    matches
+}
+
+// Scans the text in parallel in chunks of various sizes, which must find the same
+// matches as scanning it all at once.
+fn compare(database: &Database, text: &str) -> Vec<Match> {
+    let expected = sequential(database, text.as_bytes());
+    for chunk_size in [1, 2, 3, 5, 8, 13, 64, text.len()] {
+        for threads in [1, 4] {
+            assert_eq!(
+                parallel(database, text.as_bytes(), chunk_size, threads),
+                expected,
+                "{} {}",
+                chunk_size,
+                threads
+            );
+        }
+    }
+    expected
+}
+
+fn build(regexes: Vec<Regex>, merge: bool) -> Result<Database, Error> {
+    let mut builder = DatabaseBuilder::new().merge_expressions(merge);
+    for regex in regexes {
+        builder = builder.with_expressions(regex.build_all()?);
+    }
+    Ok(builder.build())
+}
+
+fn regexes() -> Vec<Regex> {
+    vec![
+        Regex::new(1, "[chm]at"),
+        Regex::new(2, "the").mode(MatchMode::First(Submatch::Expression)),
+        Regex::new(3, "s.t").mode(MatchMode::All(Submatch::None)),
+        Regex::new(4, "c[a-z ]*t").mode(MatchMode::All(Submatch::Expression)),
+        Regex::new(5, "([a-z]+)@([a-z]+)").mode(MatchMode::All(Submatch::All)),
+        Regex::new(6, "[0-9]+"),
+        Regex::new(7, "a[a-z]+")
+            .mode(MatchMode::All(Submatch::Expression))
+            .lookback(16),
+        Regex::new(8, "^the"),
+        Regex::new(9, "flat$"),
+        Regex::new(10, "(?-u)\\bsat\\b"),
+        Regex::new(11, "(?m)^[a-z]+"),
+        Regex::new(12, "mouse").max_edits(1),
+        Regex::new(13, "x*").mode(MatchMode::All(Submatch::Expression)),
+        Regex::new(14, "bat")
+            .mode(MatchMode::First(Submatch::Expression))
+            .variants(&[Variant::Encoded(Encoding::UTF8), Variant::Hex]),
+        Regex::new(15, "on").mode(MatchMode::First(Submatch::None)),
+    ]
+}
+
+const TEXT: &str = "the cat sat on the mat; a@b the hat 123, the bat\n\
+                    and the rat sat 45 flat\nhouse cat c@d 626174 mouse flat";
+
+#[test]
+fn test_parallel_same_as_sequential() -> Result<(), Error> {
+    let matches = compare(&build(regexes(), false)?, TEXT);
+    for id in 1..=15 {
+        assert!(matches.iter().any(|x| x.0 == id), "{}", id);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_merged() -> Result<(), Error> {
+    compare(&build(regexes(), true)?, TEXT);
+    Ok(())
+}
+
+#[test]
+fn test_parallel_assertions() -> Result<(), Error> {
+    // Expressions that start with an assertion see the byte before each chunk, even
+    // without prefixes to look back for.
+    for expression in ["(?-u)\\bab", "(?m)^ab", "(?-u)\\B", "(?-u)\\b"] {
+        let database = build(
+            vec![Regex::new(1, expression)
+                .encoding(Encoding::Byte)
+                .mode(MatchMode::All(Submatch::Expression))],
+            false,
+        )?;
+        compare(&database, "xxxxxxxxab");
+        compare(&database, "xxxxxxxxab ab\nab xab");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_long_matches() -> Result<(), Error> {
+    // Matches that span many chunks, and threads that never finish.
+    let database = build(
+        vec![
+            Regex::new(1, "a[^z]*b"),
+            Regex::new(2, "(?s)q.*").mode(MatchMode::All(Submatch::None)),
+            Regex::new(3, "[a-y]{20}"),
+        ],
+        false,
+    )?;
+    let text = format!(
+        "{} b {}q {}",
+        "a".repeat(50),
+        "xy".repeat(40),
+        "cd".repeat(30)
+    );
+    assert!(!compare(&database, &text).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_parallel_combinations() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(Regex::new(1, "foo").build()?)
+        .with_expression(Regex::new(2, "bar").build()?)
+        .with_expression(Regex::new(3, "baz").build()?)
+        .with_combination(CombinationRule::new(10, "1 & 2"))
+        .with_combination(CombinationRule::new(11, "!2 | 3"))
+        .with_sequence(SequenceRule::new(12, &[1, 2, 1]).within(8))
+        .build();
+    let matches = compare(&database, "foo xx bar foo baz foo bar yy foo");
+    for id in [10, 11, 12] {
+        assert!(matches.iter().any(|x| x.0 == id), "{}", id);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_many_chunks() -> Result<(), Error> {
+    let words = ["alpha", "beta", "gamma", "delta", "the", "cat", "a@b", "42"];
+    let text: String = (0..5000)
+        .map(|x| words[x * 7 % 11 % words.len()])
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let database = build(regexes(), false)?;
+    let expected = sequential(&database, text.as_bytes());
+    for chunk_size in [100, 1000, 7777] {
+        assert_eq!(
+            parallel(&database, text.as_bytes(), chunk_size, 8),
+            expected
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_empty() -> Result<(), Error> {
+    let database = build(vec![Regex::new(1, "a*"), Regex::new(2, "b")], false)?;
+    assert_eq!(compare(&database, ""), vec![(1, Some(0), Some(0))]);
+    Ok(())
+}
+
+#[test]
+fn test_parallel_stop() -> Result<(), Error> {
+    let database = build(vec![Regex::new(1, "a")], false)?;
+    let mut handler = CollectingHandler::new();
+    handler.limit = 3;
+    let result = ParallelScanner::new(&database)
+        .chunk_size(4)
+        .scan(b"a a a a a a a a", &mut handler);
+    assert_eq!(result, ContinueMatching::No);
+    assert_eq!(handler.matches.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<Vec<Capture>>,
+}
+
+impl CollectingHandler {
+    fn new() -> Self {
+        Self {
+            matches: Vec::new(),
+        }
+    }
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, matches: &[Capture]) -> ContinueMatching {
+        self.matches.push(matches.to_vec());
+        ContinueMatching::Yes
+    }
+}
+
+fn run(
+    regex: &str,
+    threshold: usize,
+    text: &str,
+    block_size: usize,
+) -> Result<Vec<Vec<Capture>>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, regex)
+                .encoding(Encoding::Byte)
+                .mode(MatchMode::All(Submatch::All))
+                .counted_repetition_threshold(threshold)
+                .build()?,
+        )
+        .build();
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    for chunk in text.as_bytes().chunks(block_size) {
+        scratch.push(chunk);
+    }
+    scratch.finish();
+    Ok(handler.matches)
+}
+
+#[test]
+fn test_counted_repetition_is_small() -> Result<(), Error> {
+    let counted = Regex::new(1, r##"[a-z]{1,5000}"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    let unrolled = Regex::new(1, r##"[a-z]{1,5000}"##)
+        .encoding(Encoding::Byte)
+        .counted_repetition_threshold(usize::MAX)
+        .build()?;
+    assert!(counted.program_size() < 10);
+    assert!(unrolled.program_size() > 5000);
+
+    // Lazy repetitions are always unrolled.
+    let lazy = Regex::new(1, r##"[a-z]{1,5000}?"##)
+        .encoding(Encoding::Byte)
+        .build()?;
+    assert!(lazy.program_size() > 5000);
+    Ok(())
+}
+
+#[test]
+fn test_counted_repetition_matches_unrolled() -> Result<(), Error> {
+    let cases = [
+        (
+            r##"x{20,30}y"##,
+            "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxy xxxxxxxxxxxxxxxxxxxxxxy",
+        ),
+        (
+            r##"[a-c]{17,}d"##,
+            "abcabcabcabcabcabcabcd abcabcd cccccccccccccccccccccccd",
+        ),
+        (
+            r##"(a{0,40}?)(a*)b"##,
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab",
+        ),
+        (
+            r##"(a{0,40})(a*)b"##,
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab",
+        ),
+        (r##"(b{18})(b+)"##, "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"),
+        (
+            r##"(?:ab{0,20}c)+"##,
+            "abbbbbbbcacabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbcabc",
+        ),
+        (r##"^.{16,20}$"##, "0123456789abcdefghi"),
+        (
+            r##"\b[0-9]{3,17}\b"##,
+            "12 345 67890123456789012 1234567890",
+        ),
+        // Overlapping matches that start inside the loop.
+        (r##"x{1,20}?"##, "axxa"),
+        (r##"x{1,20}"##, "axxa xxxxxxxxxxxxxxxxxxxxxxxxx"),
+        (r##"c{16,}?"##, "cccccccccccccccccc"),
+        (r##"c{16,}"##, "cccccccccccccccccc"),
+        (r##"c{2,}?"##, "cccc"),
+        (r##"c{2,3}"##, "cccc"),
+        (r##"a[ab]{0,3}"##, "bcaababbabc"),
+        (r##"[ab]{0,3}|a+b*"##, "bcaababbabc"),
+    ];
+
+    for (regex, text) in cases {
+        for block_size in [1, 2, 3, 5, 7, 64] {
+            let expected = run(regex, usize::MAX, text, block_size)?;
+            assert!(!expected.is_empty());
+            assert_eq!(expected, run(regex, 0, text, block_size)?);
+        }
+    }
+    Ok(())
+}
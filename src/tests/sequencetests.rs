@@ -0,0 +1,192 @@
+use crate::*;
+
+// Checks the sequences reported against those expected, ignoring the expressions
+// they're made of.
+struct TestHandler {
+    expected: Vec<(usize, Capture)>,
+}
+
+impl TestHandler {
+    fn new(expected: Vec<(usize, Capture)>) -> Self {
+        Self { expected }
+    }
+}
+
+impl MatchHandler for TestHandler {
+    fn on_match(&mut self, id: usize, matches: &[Capture]) -> ContinueMatching {
+        if id >= 10 {
+            assert_ne!(self.expected.len(), 0, "unexpected {} {:?}", id, matches[0]);
+            assert_eq!(self.expected[0], (id, matches[0]));
+            self.expected.remove(0);
+        }
+        ContinueMatching::Yes
+    }
+
+    fn on_reset(&mut self) {
+        assert_eq!(self.expected, vec![]);
+    }
+}
+
+fn database(rules: Vec<SequenceRule>) -> Result<Database, Error> {
+    let mut builder = DatabaseBuilder::new()
+        .with_expression(Regex::new(1, "foo").build()?)
+        .with_expression(Regex::new(2, "bar").build()?)
+        .with_expression(Regex::new(3, "baz").build()?);
+    for rule in rules {
+        builder = builder.with_sequence(rule);
+    }
+    builder.try_build()
+}
+
+fn check(
+    rules: Vec<SequenceRule>,
+    text: &str,
+    expected: Vec<(usize, usize, usize)>,
+) -> Result<(), Error> {
+    let database = database(rules)?;
+    for block_size in [1, 2, 3, 4, 5, 6, 7, text.len().max(1)] {
+        let matches = expected
+            .iter()
+            .map(|&(id, start, end)| (id, Capture::new(Some(start), Some(end))))
+            .collect();
+        let mut handler = TestHandler::new(matches);
+        let mut scratch = database.make_scratch(&mut handler);
+        for chunk in text.as_bytes().chunks(block_size) {
+            scratch.push(chunk);
+        }
+        scratch.finish();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sequence_within() -> Result<(), Error> {
+    let rule = || SequenceRule::new(10, &[1, 2]).within(10);
+    check(vec![rule()], "xxfooxxxxbarxx", vec![(10, 2, 12)])?;
+    check(vec![rule()], "xxfooxxxxxxxxxxbarxx", vec![(10, 2, 18)])?;
+    check(vec![rule()], "xxfooxxxxxxxxxxxbarxx", vec![])?;
+    check(vec![rule()], "foobar", vec![(10, 0, 6)])?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_order() -> Result<(), Error> {
+    check(vec![SequenceRule::new(10, &[1, 2])], "bar foo", vec![])?;
+
+    // Matches are found out of order within a block: "foo" before "bar".
+    check(
+        vec![SequenceRule::new(10, &[2, 1]).within(1)],
+        "bar foo",
+        vec![(10, 0, 7)],
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_steps() -> Result<(), Error> {
+    let rule = || SequenceRule::new(10, &[1, 2, 3]).within(5);
+    check(vec![rule()], "foo bar baz", vec![(10, 0, 11)])?;
+    check(vec![rule()], "foo bar      baz", vec![])?;
+    check(vec![rule()], "foo baz bar", vec![])?;
+    check(vec![rule()], "foo baz bar baz", vec![(10, 0, 15)])?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_latest() -> Result<(), Error> {
+    // The latest match of each step is the one carried forward.
+    check(
+        vec![SequenceRule::new(10, &[1, 2])],
+        "foo foo bar",
+        vec![(10, 4, 11)],
+    )?;
+    check(
+        vec![SequenceRule::new(10, &[1, 2]).within(1)],
+        "foo foo bar",
+        vec![(10, 4, 11)],
+    )?;
+
+    // Every completion is reported.
+    check(
+        vec![SequenceRule::new(10, &[1, 2])],
+        "foo bar bar",
+        vec![(10, 0, 7), (10, 0, 11)],
+    )?;
+    check(
+        vec![SequenceRule::new(10, &[1, 2]).within(1)],
+        "foo bar bar",
+        vec![(10, 0, 7)],
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_repeated() -> Result<(), Error> {
+    let rule = || SequenceRule::new(10, &[1, 1]).within(2);
+    check(vec![rule()], "foo", vec![])?;
+    check(vec![rule()], "foofoo", vec![(10, 0, 6)])?;
+    check(vec![rule()], "foo  foo foo", vec![(10, 0, 8), (10, 5, 12)])?;
+    check(vec![rule()], "foo   foo", vec![])?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_several() -> Result<(), Error> {
+    check(
+        vec![
+            SequenceRule::new(10, &[1, 2]).within(3),
+            SequenceRule::new(11, &[2, 3]),
+            SequenceRule::new(12, &[3]),
+        ],
+        "foo bar    baz",
+        vec![(10, 0, 7), (11, 4, 14), (12, 11, 14)],
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_sequence_reset() -> Result<(), Error> {
+    let database = database(vec![SequenceRule::new(10, &[1, 2])])?;
+    let mut handler = TestHandler::new(vec![(10, Capture::new(Some(0), Some(7)))]);
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"foo bar");
+    scratch = scratch.finish();
+    scratch.push(b"bar");
+    scratch.finish();
+    Ok(())
+}
+
+#[test]
+fn test_sequence_decoded() -> Result<(), Error> {
+    // Distances are measured in the input as it was pushed.
+    for (within, expected) in [
+        (1, vec![]),
+        (2, vec![(10, Capture::new(Some(0), Some(14)))]),
+    ] {
+        let database = database(vec![SequenceRule::new(10, &[1, 2]).within(within)])?;
+        let mut handler = TestHandler::new(expected);
+        let mut scratch = database
+            .make_scratch(&mut handler)
+            .with_decoder(Box::new(HexDecoder::new()));
+        for chunk in b"666f6f20626172".chunks(3) {
+            scratch.push(chunk);
+        }
+        scratch.finish();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sequence_errors() -> Result<(), Error> {
+    for rule in [
+        SequenceRule::new(10, &[]),
+        SequenceRule::new(10, &[1, 4]),
+        SequenceRule::new(2, &[1, 3]),
+    ] {
+        assert!(matches!(
+            database(vec![rule]),
+            Err(Error::InvalidExpression(_))
+        ));
+    }
+    Ok(())
+}
@@ -16,20 +16,16 @@ impl TestHandler {
         }
     }
 
-    fn to_results(self) -> HashMap<usize, Vec<Vec<Capture>>> {
+    fn into_results(self) -> HashMap<usize, Vec<Vec<Capture>>> {
         self.matches
     }
 }
 
 impl MatchHandler for TestHandler {
     fn on_match(&mut self, id: usize, matches: &[Capture]) -> ContinueMatching {
-        if !self.matches.contains_key(&id) {
-            self.matches.insert(id, Vec::new());
-        }
-
         self.matches
-            .get_mut(&id)
-            .unwrap()
+            .entry(id)
+            .or_default()
             .push(matches.to_vec().clone());
         ContinueMatching::Yes
     }
@@ -527,7 +523,7 @@ fn test_shakespeare() -> Result<(), Error> {
         }
         scratch.finish();
 
-        let results = handler.to_results();
+        let results = handler.into_results();
         for key in expected.keys() {
             assert!(results.contains_key(key));
             assert_eq!(expected[key], results[key]);
@@ -11,7 +11,7 @@ impl CollectingHandler {
         }
     }
 
-    fn to_results(self) -> Vec<Vec<Capture>> {
+    fn into_results(self) -> Vec<Vec<Capture>> {
         self.matches
     }
 }
@@ -43,7 +43,7 @@ fn test_should_be_fast() -> Result<(), Error> {
     scratch.push(text.as_bytes());
     scratch.finish();
 
-    let results = handler.to_results();
+    let results = handler.into_results();
     assert_eq!(expected, results);
     Ok(())
 }
@@ -80,7 +80,46 @@ fn test_repeated_matches() -> Result<(), Error> {
     scratch.push(text.as_bytes());
     scratch.finish();
 
-    let results = handler.to_results();
+    let results = handler.into_results();
     assert_eq!(expected, results);
     Ok(())
 }
+
+#[test]
+fn test_overlapping_prefixes() -> Result<(), Error> {
+    // "imes" ends wherever "times" does, so the prefilter finds both at once.
+    let text: &'static str = r##"xx times yy times"##;
+    for block in [1, 2, 3, 5, text.len()] {
+        let database = DatabaseBuilder::new()
+            .with_expression(
+                Regex::new(1, r##"times"##)
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .build()?,
+            )
+            .with_expression(
+                Regex::new(2, r##"imes"##)
+                    .mode(MatchMode::All(Submatch::Expression))
+                    .build()?,
+            )
+            .build();
+        let mut handler = CollectingHandler::new();
+        let mut scratch = database.make_scratch(&mut handler);
+        for chunk in text.as_bytes().chunks(block) {
+            scratch.push(chunk);
+        }
+        scratch.finish();
+
+        let mut results = handler.into_results();
+        results.sort_by_key(|x| (x[0].start, x[0].end));
+        assert_eq!(
+            results,
+            vec![
+                vec![Capture::new(Some(3), Some(8))],
+                vec![Capture::new(Some(4), Some(8))],
+                vec![Capture::new(Some(12), Some(17))],
+                vec![Capture::new(Some(13), Some(17))],
+            ]
+        );
+    }
+    Ok(())
+}
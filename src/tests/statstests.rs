@@ -0,0 +1,96 @@
+use crate::*;
+
+struct CountingHandler {
+    matches: usize,
+}
+
+impl MatchHandler for CountingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        self.matches += 1;
+        ContinueMatching::Yes
+    }
+}
+
+fn make_database() -> Result<Database, Error> {
+    Ok(DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(1, r##"needle"##)
+                .encoding(Encoding::Byte)
+                .build()?,
+        )
+        .with_expression(
+            Regex::new(2, r##"[^,]*q"##)
+                .encoding(Encoding::Byte)
+                .build()?,
+        )
+        .build())
+}
+
+#[test]
+fn test_stats() -> Result<(), Error> {
+    let database = make_database()?;
+    let mut handler = CountingHandler { matches: 0 };
+    let mut scratch = database.make_scratch(&mut handler).with_stats();
+    scratch.push(b"a needle, 12 nee");
+    scratch.push(b"dles");
+    scratch = scratch.finish();
+
+    let stats = scratch.stats();
+    assert_eq!(stats.expressions.len(), 2);
+
+    let needle = stats.get(1).next().unwrap();
+    assert_eq!(needle.prefix_hits, 2);
+    assert_eq!(needle.matches, 2);
+    // Only the input around the prefix hits is run.
+    assert!(needle.bytes_executed >= 12 && needle.bytes_executed < 20);
+    assert_eq!(needle.peak_threads, 1);
+
+    // An expression without prefixes runs over every byte.
+    let anywhere = stats.get(2).next().unwrap();
+    assert_eq!(anywhere.prefix_hits, 0);
+    assert_eq!(anywhere.matches, 0);
+    assert_eq!(anywhere.bytes_executed, 20);
+    assert!(anywhere.peak_threads > 0);
+
+    assert_eq!(stats.time(), needle.time + anywhere.time);
+
+    // Statistics are kept across streams.
+    scratch.push(b"needle");
+    scratch = scratch.finish();
+    assert_eq!(scratch.stats().get(1).next().unwrap().matches, 3);
+
+    scratch.reset_stats();
+    let stats = scratch.stats();
+    assert_eq!(
+        stats.get(1).next().unwrap(),
+        &ExpressionStats {
+            id: 1,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        stats.get(2).next().unwrap(),
+        &ExpressionStats {
+            id: 2,
+            ..Default::default()
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_stats_are_opt_in() -> Result<(), Error> {
+    let database = make_database()?;
+    let mut handler = CountingHandler { matches: 0 };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(b"a needle, 12 needles");
+    scratch = scratch.finish();
+
+    let stats = scratch.stats();
+    assert!(stats.expressions.iter().all(|x| *x
+        == ExpressionStats {
+            id: x.id,
+            ..Default::default()
+        }));
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::*;
+use bytes::Bytes;
+use futures_util::SinkExt;
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+// The id, start and end of a match.
+type Match = (usize, Option<usize>, Option<usize>);
+
+struct CollectingHandler {
+    matches: Vec<Match>,
+    limit: usize,
+}
+
+impl CollectingHandler {
+    fn new() -> Self {
+        Self {
+            matches: vec![],
+            limit: usize::MAX,
+        }
+    }
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, id: usize, captures: &[Capture]) -> ContinueMatching {
+        self.matches.push((id, captures[0].start, captures[0].end));
+        if self.matches.len() >= self.limit {
+            return ContinueMatching::No;
+        }
+        ContinueMatching::Yes
+    }
+}
+
+fn database() -> Result<Database, Error> {
+    Ok(DatabaseBuilder::new()
+        .with_expression(Regex::new(1, "foo[0-9]+").build()?)
+        .with_expression(Regex::new(2, "bar$").build()?)
+        .with_combination(CombinationRule::new(3, "1 & !2"))
+        .build())
+}
+
+const TEXT: &[u8] = b"foo1 xx foo22 yy foo333 bar";
+
+fn sequential(database: &Database, text: &[u8]) -> Vec<Match> {
+    let mut handler = CollectingHandler::new();
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+    handler.matches
+}
+
+// Writes the text in small pieces, as a reader at the other end reads it.
+async fn write(mut writer: DuplexStream, text: &[u8]) {
+    for chunk in text.chunks(3) {
+        if writer.write_all(chunk).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_scan_async_read() -> Result<(), Error> {
+    let database = database()?;
+    let (writer, reader) = duplex(4);
+    let mut handler = CollectingHandler::new();
+    let (_, result) = tokio::join!(
+        write(writer, TEXT),
+        scan_async_read(reader, &database, &mut handler)
+    );
+    assert_eq!(result.unwrap(), ContinueMatching::Yes);
+    assert_eq!(handler.matches, sequential(&database, TEXT));
+    assert_eq!(handler.matches.last(), Some(&(2, Some(24), Some(27))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scan_async_read_stop() -> Result<(), Error> {
+    let database = database()?;
+    let text = TEXT.repeat(1000);
+    let (writer, reader) = duplex(4);
+    let mut handler = CollectingHandler::new();
+    handler.limit = 2;
+    let (_, result) = tokio::join!(
+        write(writer, &text),
+        scan_async_read(reader, &database, &mut handler)
+    );
+    assert_eq!(result.unwrap(), ContinueMatching::No);
+    assert_eq!(handler.matches, sequential(&database, &text)[..2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scratch_sink() -> Result<(), SinkError> {
+    let database = database().unwrap();
+    let mut handler = CollectingHandler::new();
+    let mut sink = ScratchSink::new(database.make_scratch(&mut handler));
+    for chunk in TEXT.chunks(5) {
+        sink.send(Bytes::copy_from_slice(chunk)).await?;
+    }
+    sink.close().await?;
+    sink.close().await?;
+    drop(sink);
+    assert_eq!(handler.matches, sequential(&database, TEXT));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scratch_sink_stop() -> Result<(), SinkError> {
+    let database = database().unwrap();
+    let mut handler = CollectingHandler::new();
+    handler.limit = 1;
+    let mut sink = ScratchSink::new(database.make_scratch(&mut handler));
+    sink.send(Bytes::from_static(b"xx foo")).await?;
+    assert_eq!(
+        sink.send(Bytes::from_static(b"1 foo2")).await,
+        Err(SinkError::Stopped)
+    );
+    assert_eq!(
+        sink.send(Bytes::from_static(b"foo3 ")).await,
+        Err(SinkError::Stopped)
+    );
+    sink.close().await?;
+    drop(sink);
+    assert_eq!(handler.matches, vec![(1, Some(3), Some(7))]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scratch_sink_closed() -> Result<(), SinkError> {
+    let database = database().unwrap();
+    let mut handler = CollectingHandler::new();
+    let mut sink = ScratchSink::new(database.make_scratch(&mut handler));
+    sink.send(Bytes::from_static(b"foo1 ")).await?;
+    sink.close().await?;
+
+    // Input sent after the stream is finished doesn't start another.
+    assert_eq!(
+        sink.send(Bytes::from_static(b"foo2 ")).await,
+        Err(SinkError::Closed)
+    );
+    drop(sink);
+    assert_eq!(handler.matches, sequential(&database, b"foo1 "));
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use crate::*;
+
+struct NullHandler {
+    matches: usize,
+}
+
+impl MatchHandler for NullHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        self.matches += 1;
+        ContinueMatching::Yes
+    }
+}
+
+#[derive(Default)]
+struct RecordingTracer {
+    steps: Vec<(usize, Option<u8>, Vec<usize>)>,
+    executed: usize,
+    assertions: Vec<(usize, Assertion, bool)>,
+    captures: Vec<(usize, usize, Capture)>,
+    deaths: Vec<(usize, usize)>,
+}
+
+impl Tracer for RecordingTracer {
+    fn on_step(&mut self, id: usize, offset: usize, byte: Option<u8>, threads: &[usize]) {
+        assert_eq!(id, 5);
+        self.steps.push((offset, byte, threads.to_vec()));
+    }
+
+    fn on_execute(
+        &mut self,
+        _id: usize,
+        _offset: usize,
+        _thread: usize,
+        _pc: usize,
+        _instruction: Instruction,
+    ) {
+        self.executed += 1;
+    }
+
+    fn on_assertion(
+        &mut self,
+        _id: usize,
+        offset: usize,
+        _thread: usize,
+        assertion: Assertion,
+        passed: bool,
+    ) {
+        self.assertions.push((offset, assertion, passed));
+    }
+
+    fn on_capture(
+        &mut self,
+        _id: usize,
+        offset: usize,
+        _thread: usize,
+        slot: usize,
+        capture: Capture,
+    ) {
+        self.captures.push((offset, slot, capture));
+    }
+
+    fn on_thread_death(&mut self, _id: usize, offset: usize, _thread: usize, pc: usize) {
+        self.deaths.push((offset, pc));
+    }
+}
+
+#[test]
+fn test_trace_across_blocks() -> Result<(), Error> {
+    let database = DatabaseBuilder::new()
+        .with_expression(
+            Regex::new(5, r##"\b(o+)$"##)
+                .encoding(Encoding::Byte)
+                .build()?,
+        )
+        .build();
+    let mut handler = NullHandler { matches: 0 };
+    let mut tracer = RecordingTracer::default();
+    let mut scratch = database.make_scratch(&mut handler).with_tracer(&mut tracer);
+    scratch.push(b"f o");
+    scratch.push(b"o");
+    scratch.finish();
+
+    // One step for each byte, spread over both blocks, and one for the end of input.
+    let offsets: Vec<(usize, Option<u8>)> = tracer.steps.iter().map(|x| (x.0, x.1)).collect();
+    assert_eq!(
+        offsets,
+        vec![
+            (0, Some(b'f')),
+            (1, Some(b' ')),
+            (2, Some(b'o')),
+            (3, Some(b'o')),
+            (4, None)
+        ]
+    );
+    assert!(tracer.steps.iter().all(|x| !x.2.is_empty()));
+    assert!(tracer.executed > 0);
+
+    // The word boundary passes before the first "o" and fails between the two.
+    assert!(tracer
+        .assertions
+        .contains(&(0, Assertion::ByteWordBoundary, true)));
+    assert!(tracer
+        .assertions
+        .contains(&(3, Assertion::ByteWordBoundary, false)));
+    assert!(tracer
+        .assertions
+        .contains(&(2, Assertion::ByteWordBoundary, true)));
+    assert!(tracer.assertions.contains(&(4, Assertion::EndOfText, true)));
+
+    // The group opens at 2 and is closed at the end of input, followed by the
+    // match as a whole.
+    assert!(tracer
+        .captures
+        .contains(&(2, 1, Capture::new(Some(2), None))));
+    assert!(tracer
+        .captures
+        .contains(&(4, 1, Capture::new(Some(2), Some(4)))));
+    assert!(tracer
+        .captures
+        .contains(&(4, 0, Capture::new(Some(2), Some(4)))));
+
+    // The thread starting at "f" dies when " " isn't an "o".
+    assert!(tracer.deaths.iter().any(|x| x.0 == 0));
+    assert_eq!(handler.matches, 1);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::*;
+
+struct CollectingHandler {
+    matches: Vec<(usize, Variant, usize, usize)>,
+}
+
+impl MatchHandler for CollectingHandler {
+    fn on_match(&mut self, _id: usize, _captures: &[Capture]) -> ContinueMatching {
+        unreachable!()
+    }
+
+    fn on_match_info(&mut self, info: &MatchInfo) -> ContinueMatching {
+        self.matches.push((
+            info.id,
+            info.variant,
+            info.captures[0].start.unwrap(),
+            info.captures[0].end.unwrap(),
+        ));
+        ContinueMatching::Yes
+    }
+}
+
+fn run(regex: Regex, text: &[u8]) -> Result<Vec<(usize, Variant, usize, usize)>, Error> {
+    let database = DatabaseBuilder::new()
+        .with_expressions(regex.build_all()?)
+        .build();
+    let mut handler = CollectingHandler { matches: vec![] };
+    let mut scratch = database.make_scratch(&mut handler);
+    scratch.push(text);
+    scratch.finish();
+    handler.matches.sort_by_key(|x| (x.2, x.3));
+    Ok(handler.matches)
+}
+
+#[test]
+fn test_encoded_variants() -> Result<(), Error> {
+    let regex = Regex::new(7, "secret")
+        .mode(MatchMode::All(Submatch::Expression))
+        .variants(&[
+            Variant::Encoded(Encoding::UTF8),
+            Variant::Encoded(Encoding::UTF16LE),
+        ]);
+    let mut text = b"a secret ".to_vec();
+    text.extend("secret".encode_utf16().flat_map(|x| x.to_le_bytes()));
+
+    assert_eq!(
+        run(regex, &text)?,
+        vec![
+            (7, Variant::Encoded(Encoding::UTF8), 2, 8),
+            (7, Variant::Encoded(Encoding::UTF16LE), 9, 21),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_hex_variant() -> Result<(), Error> {
+    let regex = Regex::new(1, "x:pas+word")
+        .mode(MatchMode::All(Submatch::Expression))
+        .variants(&[Variant::Hex]);
+    assert_eq!(
+        run(regex, b"dump 783A70617373776f7264 end")?,
+        vec![(1, Variant::Hex, 5, 25)]
+    );
+
+    let regex = Regex::new(1, "[a-c]")
+        .mode(MatchMode::All(Submatch::Expression))
+        .variants(&[Variant::Hex]);
+    assert_eq!(
+        run(regex, b"606162636465")?,
+        vec![
+            (1, Variant::Hex, 2, 4),
+            (1, Variant::Hex, 4, 6),
+            (1, Variant::Hex, 6, 8)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_base64_variant() -> Result<(), Error> {
+    // "password" at each of the three alignments.
+    for (text, start, end) in [
+        ("cGFzc3dvcmQ=", 0, 10),
+        ("dXNlcj1wYXNzd29yZDs=", 7, 17),
+        ("eHhwYXNzd29yZCEh", 3, 13),
+    ] {
+        let regex = Regex::new(1, "password")
+            .mode(MatchMode::First(Submatch::Expression))
+            .variants(&[Variant::Base64]);
+        assert_eq!(
+            run(regex, text.as_bytes())?,
+            vec![(1, Variant::Base64, start, end)],
+            "{}",
+            text
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_base64_variant_needs_literal() {
+    for expression in ["pass(word)?", "a", "(?i)password"] {
+        assert!(Regex::new(1, expression)
+            .variants(&[Variant::Base64])
+            .build_all()
+            .is_err());
+    }
+}
+
+#[test]
+fn test_build_ignores_variants() -> Result<(), Error> {
+    let compiled = Regex::new(1, "secret")
+        .variants(&[Variant::Hex, Variant::Base64])
+        .build()?;
+    assert_eq!(compiled.variant(), Variant::Encoded(Encoding::UTF8));
+    assert_eq!(Regex::new(1, "secret").build_all()?.len(), 1);
+    Ok(())
+}
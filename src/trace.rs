@@ -0,0 +1,88 @@
+// Execution tracing for the matching VM.
+//
+// Tracing is only compiled in with the "trace" feature. Without it, the tracer
+// slot carried around by a scratch space is zero-sized and the trace! macro
+// expands to nothing, so the matching loop is exactly as it would be otherwise.
+#[cfg(feature = "trace")]
+use crate::{Assertion, Capture, Instruction};
+#[cfg(not(feature = "trace"))]
+use std::marker::PhantomData;
+
+/// Receives a step-by-step account of the matching VM. All methods do nothing by
+/// default. `id` is always the id of the expression being run, and `offset` is the
+/// offset of the byte being examined in the stream as a whole.
+#[cfg(feature = "trace")]
+pub trait Tracer {
+    /// Called before an expression's threads are run over a byte, or over the end
+    /// of input when `byte` is None. `threads` are the states of the threads waiting
+    /// to run, in priority order. A state is the thread's program counter, unless it's
+    /// partway through a literal run or the expression is approximate.
+    fn on_step(&mut self, _id: usize, _offset: usize, _byte: Option<u8>, _threads: &[usize]) {
+        /* do nothing by default */
+    }
+
+    /// Called for every instruction executed by the thread identified by `thread`.
+    fn on_execute(
+        &mut self,
+        _id: usize,
+        _offset: usize,
+        _thread: usize,
+        _pc: usize,
+        _instruction: Instruction,
+    ) {
+        /* do nothing by default */
+    }
+
+    /// Called with the result of every assertion checked.
+    fn on_assertion(
+        &mut self,
+        _id: usize,
+        _offset: usize,
+        _thread: usize,
+        _assertion: Assertion,
+        _passed: bool,
+    ) {
+        /* do nothing by default */
+    }
+
+    /// Called whenever a thread's capture slot is updated, with its new value.
+    fn on_capture(
+        &mut self,
+        _id: usize,
+        _offset: usize,
+        _thread: usize,
+        _slot: usize,
+        _capture: Capture,
+    ) {
+        /* do nothing by default */
+    }
+
+    /// Called when a thread dies without matching or moving on to the next byte.
+    fn on_thread_death(&mut self, _id: usize, _offset: usize, _thread: usize, _pc: usize) {
+        /* do nothing by default */
+    }
+}
+
+#[cfg(feature = "trace")]
+pub type TracerSlot<'a> = Option<&'a mut dyn Tracer>;
+
+#[cfg(not(feature = "trace"))]
+pub type TracerSlot<'a> = PhantomData<&'a ()>;
+
+#[cfg(feature = "trace")]
+macro_rules! trace {
+    ($tracer:expr, $method:ident($($arg:expr),* $(,)?)) => {
+        if let Some(tracer) = $tracer.as_mut() {
+            tracer.$method($($arg),*);
+        }
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($tracer:expr, $($rest:tt)*) => {
+        let _ = &$tracer;
+    };
+}
+
+pub(crate) use trace;
@@ -4,6 +4,8 @@ use regex_syntax::hir::{
 };
 use regex_syntax::utf8::{Utf8Sequence, Utf8Sequences};
 
+use crate::LineTerminator;
+
 pub trait Transformer {
     fn empty(&self) -> Hir {
         Hir::empty()
@@ -301,3 +303,436 @@ impl Transformer for ByteTransformer {
         }
     }
 }
+
+// Rewrites the class produced by `.` so that it excludes the bytes that end a line,
+// rather than just \n. Any class matching everything but \n is rewritten, so this
+// also applies to classes written out as `[^\n]`. In Unicode mode, a terminator
+// byte outside ASCII is never a character on its own, and so isn't excluded.
+pub struct LineTerminatorTransformer {
+    terminators: Vec<u8>,
+}
+
+impl LineTerminatorTransformer {
+    pub fn new(line_terminator: LineTerminator) -> Self {
+        Self {
+            terminators: match line_terminator {
+                LineTerminator::CROrLF | LineTerminator::LF => vec![b'\n'],
+                LineTerminator::CRLF => vec![b'\r', b'\n'],
+                LineTerminator::Byte(b) => vec![b],
+            },
+        }
+    }
+}
+
+impl Transformer for LineTerminatorTransformer {
+    fn class(&self, node: Class) -> Hir {
+        match node {
+            Class::Bytes(class) => {
+                let mut dot = ClassBytes::new(vec![ClassBytesRange::new(b'\n', b'\n')]);
+                dot.negate();
+                if class != dot {
+                    return Hir::class(Class::Bytes(class));
+                }
+
+                let mut class = ClassBytes::new(
+                    self.terminators
+                        .iter()
+                        .map(|&b| ClassBytesRange::new(b, b))
+                        .collect::<Vec<_>>(),
+                );
+                class.negate();
+                Hir::class(Class::Bytes(class))
+            }
+            Class::Unicode(class) => {
+                let mut dot = ClassUnicode::new(vec![ClassUnicodeRange::new('\n', '\n')]);
+                dot.negate();
+                if class != dot {
+                    return Hir::class(Class::Unicode(class));
+                }
+
+                let mut class = ClassUnicode::new(
+                    self.terminators
+                        .iter()
+                        .filter(|b| b.is_ascii())
+                        .map(|&b| ClassUnicodeRange::new(b as char, b as char))
+                        .collect::<Vec<_>>(),
+                );
+                class.negate();
+                Hir::class(Class::Unicode(class))
+            }
+        }
+    }
+}
+
+// Returns a byte literal in the form the other transformers expect: Literal::Byte is
+// only used for non-ASCII bytes.
+fn byte_literal(b: u8) -> Hir {
+    if b > 0x7f {
+        Hir::literal(Literal::Byte(b))
+    } else {
+        Hir::literal(Literal::Unicode(b as char))
+    }
+}
+
+fn byte_class(start: u8, end: u8) -> Hir {
+    Hir::class(Class::Bytes(ClassBytes::new(vec![ClassBytesRange::new(
+        start, end,
+    )])))
+}
+
+// A class that matches nothing, for characters that can't be encoded.
+fn never() -> Hir {
+    Hir::class(Class::Bytes(ClassBytes::empty()))
+}
+
+fn alternation(mut alternations: Vec<Hir>) -> Hir {
+    match alternations.len() {
+        0 => never(),
+        1 => alternations.remove(0),
+        _ => Hir::alternation(alternations),
+    }
+}
+
+// Splits the range [start, end] into runs whose first parts are whole ranges of
+// `unit` values, e.g. 0x1234-0x5678 with a unit of 0x100 becomes 0x1234-0x12ff,
+// 0x1300-0x55ff and 0x5600-0x5678. Each run is returned as the range of first
+// parts and the range of second parts.
+fn split_range(start: u32, end: u32, unit: u32) -> Vec<((u32, u32), (u32, u32))> {
+    let (first_start, second_start) = (start / unit, start % unit);
+    let (first_end, second_end) = (end / unit, end % unit);
+    if first_start == first_end {
+        return vec![((first_start, first_start), (second_start, second_end))];
+    }
+
+    let mut runs = Vec::with_capacity(3);
+    let mut middle = (first_start, first_end);
+    if second_start != 0 {
+        runs.push(((first_start, first_start), (second_start, unit - 1)));
+        middle.0 += 1;
+    }
+    if second_end != unit - 1 {
+        middle.1 -= 1;
+    }
+    if middle.0 <= middle.1 {
+        runs.push((middle, (0, unit - 1)));
+    }
+    if second_end != unit - 1 {
+        runs.push(((first_end, first_end), (0, second_end)));
+    }
+    runs
+}
+
+// Transforms literals and classes into the byte sequences of their UTF-16 encoding.
+// Literal bytes and byte classes, which appear when Unicode mode is off, are
+// treated as the Latin-1 characters with the same values.
+//
+// Line anchors in multi-line mode and ASCII word boundaries look at single bytes,
+// and so aren't reliable in UTF-16 text.
+pub struct UTF16Transformer {
+    big_endian: bool,
+}
+
+impl UTF16Transformer {
+    pub fn new(big_endian: bool) -> Self {
+        Self { big_endian }
+    }
+
+    fn unit(&self, high: (u32, u32), low: (u32, u32)) -> Hir {
+        let high = byte_class(high.0 as u8, high.1 as u8);
+        let low = byte_class(low.0 as u8, low.1 as u8);
+        if self.big_endian {
+            Hir::concat(vec![high, low])
+        } else {
+            Hir::concat(vec![low, high])
+        }
+    }
+
+    // Returns the alternatives matching the code units in [start, end].
+    fn code_units(&self, start: u32, end: u32) -> Vec<Hir> {
+        split_range(start, end, 0x100)
+            .into_iter()
+            .map(|(high, low)| self.unit(high, low))
+            .collect()
+    }
+
+    // Returns the alternatives matching the characters in [start, end].
+    fn characters(&self, start: u32, end: u32) -> Vec<Hir> {
+        let mut alternations = Vec::new();
+
+        // Characters below the surrogates...
+        if start < 0xd800 {
+            alternations.extend(self.code_units(start, end.min(0xd7ff)));
+        }
+
+        // ...and above them, in the Basic Multilingual Plane, are a single code unit.
+        if start <= 0xffff && end >= 0xe000 {
+            alternations.extend(self.code_units(start.max(0xe000), end.min(0xffff)));
+        }
+
+        // Everything else is a surrogate pair.
+        if end >= 0x10000 {
+            let start = start.max(0x10000) - 0x10000;
+            let end = end - 0x10000;
+            for (high, low) in split_range(start, end, 0x400) {
+                let high = self.code_units(0xd800 + high.0, 0xd800 + high.1);
+                let low = self.code_units(0xdc00 + low.0, 0xdc00 + low.1);
+                alternations.push(Hir::concat(vec![alternation(high), alternation(low)]));
+            }
+        }
+
+        alternations
+    }
+
+    fn bytes(&self, unit: u16) -> [u8; 2] {
+        if self.big_endian {
+            unit.to_be_bytes()
+        } else {
+            unit.to_le_bytes()
+        }
+    }
+}
+
+impl Transformer for UTF16Transformer {
+    fn literal(&self, node: Literal) -> Hir {
+        let c = match node {
+            Literal::Unicode(c) => c,
+            Literal::Byte(b) => b as char,
+        };
+        Hir::concat(
+            c.encode_utf16(&mut [0; 2])
+                .iter()
+                .flat_map(|&unit| self.bytes(unit))
+                .map(byte_literal)
+                .collect(),
+        )
+    }
+
+    fn class(&self, node: Class) -> Hir {
+        let alternations = match node {
+            Class::Unicode(class) => class
+                .iter()
+                .flat_map(|x| self.characters(x.start() as u32, x.end() as u32))
+                .collect(),
+            Class::Bytes(class) => class
+                .iter()
+                .flat_map(|x| self.code_units(x.start() as u32, x.end() as u32))
+                .collect(),
+        };
+        alternation(alternations)
+    }
+}
+
+// Transforms literals and classes into Latin-1 bytes. Characters that can't be
+// represented in Latin-1 never match.
+pub struct Latin1Transformer {}
+
+impl Latin1Transformer {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Transformer for Latin1Transformer {
+    fn literal(&self, node: Literal) -> Hir {
+        match node {
+            Literal::Unicode(c) if (c as u32) <= 0xff => byte_literal(c as u8),
+            Literal::Unicode(_) => never(),
+            Literal::Byte(b) => Hir::literal(Literal::Byte(b)),
+        }
+    }
+
+    fn class(&self, node: Class) -> Hir {
+        match node {
+            Class::Unicode(class) => Hir::class(Class::Bytes(ClassBytes::new(
+                class
+                    .iter()
+                    .filter(|x| (x.start() as u32) <= 0xff)
+                    .map(|x| {
+                        ClassBytesRange::new(x.start() as u8, (x.end() as u32).min(0xff) as u8)
+                    })
+                    .collect::<Vec<_>>(),
+            ))),
+            Class::Bytes(class) => Hir::class(Class::Bytes(class)),
+        }
+    }
+}
+
+// Rewrites an expression over bytes into one over the hexadecimal text of those
+// bytes, two digits per byte, in either case. This runs after the encoding
+// transformers, so every literal and class it sees is already a byte.
+pub struct HexTransformer {}
+
+impl HexTransformer {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // The digits for a set of nibbles, given as a bitmask.
+    fn digits(&self, nibbles: u16) -> Hir {
+        let mut ranges = Vec::new();
+        for nibble in (0..16u8).filter(|x| nibbles & (1 << x) != 0) {
+            let digit = b"0123456789abcdef"[nibble as usize];
+            ranges.push(ClassBytesRange::new(digit, digit));
+            ranges.push(ClassBytesRange::new(
+                digit.to_ascii_uppercase(),
+                digit.to_ascii_uppercase(),
+            ));
+        }
+        Hir::class(Class::Bytes(ClassBytes::new(ranges)))
+    }
+
+    fn bytes(&self, ranges: impl Iterator<Item = (u8, u8)>) -> Hir {
+        // Group the bytes by their high nibble.
+        let mut low = [0u16; 16];
+        for (start, end) in ranges {
+            for b in start..=end {
+                low[(b >> 4) as usize] |= 1 << (b & 0xf);
+            }
+        }
+
+        alternation(
+            (0..16)
+                .filter(|&high| low[high] != 0)
+                .map(|high| Hir::concat(vec![self.digits(1 << high), self.digits(low[high])]))
+                .collect(),
+        )
+    }
+}
+
+impl Transformer for HexTransformer {
+    fn literal(&self, node: Literal) -> Hir {
+        let b = match node {
+            Literal::Unicode(c) => c as u32 as u8,
+            Literal::Byte(b) => b,
+        };
+        self.bytes(std::iter::once((b, b)))
+    }
+
+    fn class(&self, node: Class) -> Hir {
+        match node {
+            Class::Unicode(class) => self.bytes(
+                class
+                    .iter()
+                    .map(|x| (x.start() as u32 as u8, (x.end() as u32).min(0xff) as u8)),
+            ),
+            Class::Bytes(class) => self.bytes(class.iter().map(|x| (x.start(), x.end()))),
+        }
+    }
+}
+
+// Returns the bytes of an expression that is nothing but a literal, once encoded.
+pub fn literal_bytes(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(Literal::Unicode(c)) if c.is_ascii() => Some(vec![*c as u8]),
+        HirKind::Literal(Literal::Byte(b)) => Some(vec![*b]),
+        HirKind::Group(group) => literal_bytes(&group.hir),
+        HirKind::Concat(nodes) => {
+            let mut bytes = Vec::new();
+            for node in nodes {
+                bytes.extend(literal_bytes(node)?);
+            }
+            Some(bytes)
+        }
+        _ => None,
+    }
+}
+
+// Returns an expression matching the base64 encoding of `bytes` wherever they fall
+// in the encoded stream. Each of the three alignments of the bytes against the
+// three-byte groups of base64 gives a different encoding, and the characters at
+// either end that also depend on the neighbouring bytes are left out. Returns None
+// if some alignment leaves nothing to match.
+pub fn base64_literal(bytes: &[u8]) -> Option<Hir> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut alternations = Vec::new();
+
+    for (alignment, skip) in [(0, 0), (1, 2), (2, 3)] {
+        let mut data = vec![0u8; alignment];
+        data.extend_from_slice(bytes);
+        let determined = data.len() * 8 / 6;
+        if determined <= skip {
+            return None;
+        }
+
+        let characters = (skip..determined).map(|i| {
+            let (byte, shift) = (i * 6 / 8, i * 6 % 8);
+            let mut bits = (data[byte] as u16) << 8;
+            if byte + 1 < data.len() {
+                bits |= data[byte + 1] as u16;
+            }
+            let digit = (bits >> (10 - shift)) & 0x3f;
+            Hir::literal(Literal::Unicode(ALPHABET[digit as usize] as char))
+        });
+        alternations.push(Hir::concat(characters.collect()));
+    }
+    Some(alternation(alternations))
+}
+
+// Returns the lowercase letter if a class matches just the two cases of one ASCII
+// letter, as case-insensitive literals become.
+pub fn folded_letter(class: &ClassBytes) -> Option<u8> {
+    match class.ranges() {
+        [upper, lower]
+            if upper.start() == upper.end()
+                && lower.start() == lower.end()
+                && upper.start().is_ascii_uppercase()
+                && upper.start().to_ascii_lowercase() == lower.start() =>
+        {
+            Some(lower.start())
+        }
+        _ => None,
+    }
+}
+
+// Replaces every class that matches the two cases of an ASCII letter with the
+// lowercase letter, giving an expression whose literals can be looked for without
+// regard to case. This is only used to find prefixes, never to compile.
+pub struct CaseFolder {}
+
+impl CaseFolder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Transformer for CaseFolder {
+    fn class(&self, node: Class) -> Hir {
+        match node {
+            Class::Bytes(class) => match folded_letter(&class) {
+                Some(letter) => byte_literal(letter),
+                None => Hir::class(Class::Bytes(class)),
+            },
+            node => Hir::class(node),
+        }
+    }
+}
+
+// Reverses an expression over bytes, so that running it backwards from the end of a
+// match finds where the match could have started. Assertions are left as they are:
+// reversed programs check them with the bytes on either side swapped instead.
+pub struct Reverser {}
+
+impl Reverser {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Transformer for Reverser {
+    fn literal(&self, node: Literal) -> Hir {
+        match node {
+            Literal::Unicode(c) if !c.is_ascii() => Hir::concat(
+                c.encode_utf8(&mut [0; 4])
+                    .bytes()
+                    .rev()
+                    .map(byte_literal)
+                    .collect(),
+            ),
+            node => Hir::literal(node),
+        }
+    }
+
+    fn concat(&self, node: Vec<Hir>) -> Hir {
+        Hir::concat(node.into_iter().rev().map(|x| self.transform(x)).collect())
+    }
+}